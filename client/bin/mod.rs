@@ -15,18 +15,36 @@ extern crate client_lib;
 
 use std::borrow::Borrow;
 use std::env;
+use std::iter::Peekable;
+
+/// The next argument if it's a URL rather than a flag, or else `default`.
+fn url<Args: Iterator<Item=String>>(args: &mut Peekable<Args>, default: &str) -> String {
+  let is_url =
+    match args.peek() {
+      Some(arg) => !arg.starts_with("--"),
+      None => false,
+    };
+  if is_url {
+    args.next().unwrap()
+  } else {
+    String::from(default)
+  }
+}
 
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
-  args.next().unwrap();
-  let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/client.ipc"));
-  let server_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
-  assert!(args.next().is_none());
+  let mut args = env::args().skip(1).peekable();
+  let listen_url = url(&mut args, "ipc:///tmp/client.ipc");
+  let server_url = url(&mut args, "ipc:///tmp/server.ipc");
+  let settings =
+    match client_lib::settings::parse(args) {
+      Ok(settings) => settings,
+      Err(err) => panic!("{}", err),
+    };
 
   info!("Sending to {}.", server_url);
   info!("Listening on {}.", listen_url);
 
-  client_lib::run(listen_url.borrow(), server_url.borrow());
+  client_lib::run(listen_url.borrow(), server_url.borrow(), &settings);
 }
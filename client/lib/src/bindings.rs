@@ -0,0 +1,399 @@
+//! Map keys, mouse buttons and gamepad buttons to the actions they perform, so controls can be
+//! remapped.
+//!
+//! A bindings file has one `action = input, input, ...` per line, e.g. `jump = space, mouse-x1`,
+//! replacing that action's default inputs. `mouse-sensitivity`, `invert-mouse`, `stick-dead-zone`,
+//! `stick-curve` and `stick-look-speed` can also be set there. `#` starts a comment.
+
+use cgmath::{EuclideanVector, Vector, Vector2};
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::Mouse;
+use std::ascii::AsciiExt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Bindings are read from here, if it exists.
+pub const DEFAULT_PATH: &'static str = "bindings.txt";
+
+/// Something the player can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Action {
+  MoveForward,
+  MoveBack,
+  MoveLeft,
+  MoveRight,
+  Jump,
+  LookLeft,
+  LookRight,
+  LookUp,
+  LookDown,
+  /// Place a tree.
+  Build,
+  Dig,
+  ToggleHud,
+  ToggleDebugHud,
+  /// Switch between moving the camera and the sun.
+  ToggleInputMode,
+  NextTreeSpecies,
+  SavePrefab,
+  PastePrefab,
+  RotatePrefab,
+  DecreaseLoadDistance,
+  IncreaseLoadDistance,
+  /// Freeze (or unfreeze) where terrain is loaded around.
+  ToggleLoadPosition,
+}
+
+const ACTIONS: [(&'static str, Action); 21] = [
+  ("move-forward", Action::MoveForward),
+  ("move-back", Action::MoveBack),
+  ("move-left", Action::MoveLeft),
+  ("move-right", Action::MoveRight),
+  ("jump", Action::Jump),
+  ("look-left", Action::LookLeft),
+  ("look-right", Action::LookRight),
+  ("look-up", Action::LookUp),
+  ("look-down", Action::LookDown),
+  ("build", Action::Build),
+  ("dig", Action::Dig),
+  ("toggle-hud", Action::ToggleHud),
+  ("toggle-debug-hud", Action::ToggleDebugHud),
+  ("toggle-input-mode", Action::ToggleInputMode),
+  ("next-tree-species", Action::NextTreeSpecies),
+  ("save-prefab", Action::SavePrefab),
+  ("paste-prefab", Action::PastePrefab),
+  ("rotate-prefab", Action::RotatePrefab),
+  ("decrease-load-distance", Action::DecreaseLoadDistance),
+  ("increase-load-distance", Action::IncreaseLoadDistance),
+  ("toggle-load-position", Action::ToggleLoadPosition),
+];
+
+/// A key or button that can be bound to an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Input {
+  Key(Keycode),
+  Mouse(Mouse),
+  /// A button on any gamepad.
+  Button(Button),
+}
+
+const KEYS: [(&'static str, Keycode); 67] = [
+  ("a", Keycode::A), ("b", Keycode::B), ("c", Keycode::C), ("d", Keycode::D), ("e", Keycode::E),
+  ("f", Keycode::F), ("g", Keycode::G), ("h", Keycode::H), ("i", Keycode::I), ("j", Keycode::J),
+  ("k", Keycode::K), ("l", Keycode::L), ("m", Keycode::M), ("n", Keycode::N), ("o", Keycode::O),
+  ("p", Keycode::P), ("q", Keycode::Q), ("r", Keycode::R), ("s", Keycode::S), ("t", Keycode::T),
+  ("u", Keycode::U), ("v", Keycode::V), ("w", Keycode::W), ("x", Keycode::X), ("y", Keycode::Y),
+  ("z", Keycode::Z),
+  ("0", Keycode::Num0), ("1", Keycode::Num1), ("2", Keycode::Num2), ("3", Keycode::Num3), ("4", Keycode::Num4),
+  ("5", Keycode::Num5), ("6", Keycode::Num6), ("7", Keycode::Num7), ("8", Keycode::Num8), ("9", Keycode::Num9),
+  ("f1", Keycode::F1), ("f2", Keycode::F2), ("f3", Keycode::F3), ("f4", Keycode::F4), ("f5", Keycode::F5),
+  ("f6", Keycode::F6), ("f7", Keycode::F7), ("f8", Keycode::F8), ("f9", Keycode::F9), ("f10", Keycode::F10),
+  ("f11", Keycode::F11), ("f12", Keycode::F12),
+  ("space", Keycode::Space), ("return", Keycode::Return), ("tab", Keycode::Tab), ("backspace", Keycode::Backspace),
+  ("left", Keycode::Left), ("right", Keycode::Right), ("up", Keycode::Up), ("down", Keycode::Down),
+  ("minus", Keycode::Minus), ("equals", Keycode::Equals), ("comma", Keycode::Comma), ("period", Keycode::Period),
+  ("slash", Keycode::Slash),
+  ("left-shift", Keycode::LShift), ("right-shift", Keycode::RShift),
+  ("left-ctrl", Keycode::LCtrl), ("right-ctrl", Keycode::RCtrl),
+  ("left-alt", Keycode::LAlt), ("right-alt", Keycode::RAlt),
+];
+
+const MOUSE_BUTTONS: [(&'static str, Mouse); 5] = [
+  ("mouse-left", Mouse::Left),
+  ("mouse-middle", Mouse::Middle),
+  ("mouse-right", Mouse::Right),
+  ("mouse-x1", Mouse::X1),
+  ("mouse-x2", Mouse::X2),
+];
+
+const GAMEPAD_BUTTONS: [(&'static str, Button); 15] = [
+  ("pad-a", Button::A), ("pad-b", Button::B), ("pad-x", Button::X), ("pad-y", Button::Y),
+  ("pad-back", Button::Back), ("pad-guide", Button::Guide), ("pad-start", Button::Start),
+  ("pad-left-stick", Button::LeftStick), ("pad-right-stick", Button::RightStick),
+  ("pad-left-shoulder", Button::LeftShoulder), ("pad-right-shoulder", Button::RightShoulder),
+  ("pad-up", Button::DPadUp), ("pad-down", Button::DPadDown),
+  ("pad-left", Button::DPadLeft), ("pad-right", Button::DPadRight),
+];
+
+/// Parse an input's name, e.g. `w`, `space`, `f3`, `mouse-left` or `pad-a`, ignoring case.
+pub fn parse_input(name: &str) -> Result<Input, String> {
+  let name = name.trim().to_ascii_lowercase();
+  if let Some(&(_, key)) = KEYS.iter().find(|&&(n, _)| n == name) {
+    return Ok(Input::Key(key))
+  }
+  if let Some(&(_, button)) = MOUSE_BUTTONS.iter().find(|&&(n, _)| n == name) {
+    return Ok(Input::Mouse(button))
+  }
+  if let Some(&(_, button)) = GAMEPAD_BUTTONS.iter().find(|&&(n, _)| n == name) {
+    return Ok(Input::Button(button))
+  }
+  Err(format!("Unknown key or button {:?}", name))
+}
+
+/// Mouse motion is multiplied by this to get radians, before the sensitivity is applied.
+const RADIANS_PER_PIXEL: [f32; 2] = [1.0 / 1000.0, 1.0 / 1600.0];
+
+/// The biggest value an SDL gamepad axis reports.
+const AXIS_MAX: f32 = 32767.0;
+
+/// What each input does, and how the mouse and gamepad sticks look around.
+#[derive(Debug, Clone)]
+pub struct T {
+  bindings: Vec<(Input, Action)>,
+  /// Scales how far moving the mouse turns.
+  pub mouse_sensitivity: f32,
+  /// Look down when the mouse moves up.
+  pub invert_mouse: bool,
+  /// How far (from 0 to 1) a stick has to be pushed before it does anything.
+  pub stick_dead_zone: f32,
+  /// Stick positions past the dead zone are raised to this power, so small movements are finer.
+  pub stick_curve: f32,
+  /// Radians per second to turn with the look stick pushed all the way.
+  pub stick_look_speed: f32,
+}
+
+/// The default bindings.
+pub fn new() -> T {
+  let mut bindings = T {
+    bindings: Vec::new(),
+    mouse_sensitivity: 1.0,
+    invert_mouse: false,
+    stick_dead_zone: 0.2,
+    stick_curve: 2.0,
+    stick_look_speed: 3.0,
+  };
+  let defaults = [
+    (Action::MoveForward, Input::Key(Keycode::W)),
+    (Action::MoveBack, Input::Key(Keycode::S)),
+    (Action::MoveLeft, Input::Key(Keycode::A)),
+    (Action::MoveRight, Input::Key(Keycode::D)),
+    (Action::Jump, Input::Key(Keycode::Space)),
+    (Action::LookLeft, Input::Key(Keycode::Left)),
+    (Action::LookRight, Input::Key(Keycode::Right)),
+    (Action::LookUp, Input::Key(Keycode::Up)),
+    (Action::LookDown, Input::Key(Keycode::Down)),
+    (Action::Build, Input::Mouse(Mouse::Left)),
+    (Action::Dig, Input::Mouse(Mouse::Right)),
+    (Action::ToggleHud, Input::Key(Keycode::H)),
+    (Action::ToggleDebugHud, Input::Key(Keycode::F3)),
+    (Action::ToggleInputMode, Input::Key(Keycode::M)),
+    (Action::NextTreeSpecies, Input::Key(Keycode::T)),
+    (Action::SavePrefab, Input::Key(Keycode::C)),
+    (Action::PastePrefab, Input::Key(Keycode::V)),
+    (Action::RotatePrefab, Input::Key(Keycode::R)),
+    (Action::DecreaseLoadDistance, Input::Key(Keycode::Minus)),
+    (Action::IncreaseLoadDistance, Input::Key(Keycode::Equals)),
+    (Action::ToggleLoadPosition, Input::Key(Keycode::P)),
+    (Action::Jump, Input::Button(Button::A)),
+    (Action::Build, Input::Button(Button::RightShoulder)),
+    (Action::Dig, Input::Button(Button::LeftShoulder)),
+    (Action::ToggleHud, Input::Button(Button::Back)),
+  ];
+  for &(action, input) in &defaults {
+    bindings.bindings.push((input, action));
+  }
+  bindings
+}
+
+impl T {
+  /// Make `inputs` (and only them) perform `action`. They stop doing whatever they did before.
+  pub fn bind(&mut self, action: Action, inputs: &[Input]) {
+    self.bindings.retain(|&(input, a)| a != action && !inputs.contains(&input));
+    for &input in inputs {
+      self.bindings.push((input, action));
+    }
+  }
+
+  /// The action an input performs, if any.
+  pub fn action(&self, input: Input) -> Option<Action> {
+    self.bindings.iter()
+      .find(|&&(i, _)| i == input)
+      .map(|&(_, action)| action)
+  }
+
+  /// The inputs that perform an action.
+  pub fn inputs(&self, action: Action) -> Vec<Input> {
+    self.bindings.iter()
+      .filter(|&&(_, a)| a == action)
+      .map(|&(input, _)| input)
+      .collect()
+  }
+
+  /// How many radians to turn (laterally, then vertically) when the mouse moves by `dx`, `dy` pixels.
+  pub fn look(&self, dx: i32, dy: i32) -> Vector2<f32> {
+    let vertical = if self.invert_mouse { 1.0 } else { -1.0 };
+    Vector2::new(
+      -dx as f32 * RADIANS_PER_PIXEL[0] * self.mouse_sensitivity,
+      vertical * dy as f32 * RADIANS_PER_PIXEL[1] * self.mouse_sensitivity,
+    )
+  }
+
+  /// Where a stick is pushed, from raw axis values, after the dead zone and curve are applied.
+  /// The result is at most 1 long, and +y is up.
+  pub fn stick(&self, x: i16, y: i16) -> Vector2<f32> {
+    let raw = Vector2::new(x as f32 / AXIS_MAX, -(y as f32) / AXIS_MAX);
+    let length = raw.length();
+    if length <= self.stick_dead_zone {
+      return Vector2::new(0.0, 0.0)
+    }
+    // Rescale so the edge of the dead zone is 0, then curve.
+    let pushed = ((length.min(1.0) - self.stick_dead_zone) / (1.0 - self.stick_dead_zone)).powf(self.stick_curve);
+    raw.mul_s(pushed / length)
+  }
+
+  fn set(&mut self, name: &str, s: &str) -> Result<(), String> {
+    match name {
+      "mouse-sensitivity" => {
+        let sensitivity: f32 = try!(value(name, s));
+        if !(sensitivity > 0.0) {
+          return Err(format!("The mouse sensitivity must be positive: {}", sensitivity))
+        }
+        self.mouse_sensitivity = sensitivity;
+      },
+      "invert-mouse" => self.invert_mouse = try!(value(name, s)),
+      "stick-dead-zone" => {
+        let dead_zone: f32 = try!(value(name, s));
+        if !(dead_zone >= 0.0 && dead_zone < 1.0) {
+          return Err(format!("The stick dead zone must be at least 0 and less than 1: {}", dead_zone))
+        }
+        self.stick_dead_zone = dead_zone;
+      },
+      "stick-curve" => {
+        let curve: f32 = try!(value(name, s));
+        if !(curve > 0.0) {
+          return Err(format!("The stick curve must be positive: {}", curve))
+        }
+        self.stick_curve = curve;
+      },
+      "stick-look-speed" => self.stick_look_speed = try!(value(name, s)),
+      _ => {
+        let action =
+          match ACTIONS.iter().find(|&&(n, _)| n == name) {
+            None => {
+              let names: Vec<&str> = ACTIONS.iter().map(|&(n, _)| n).collect();
+              return Err(format!("Unrecognized action {:?}. Actions: {}", name, names.join(", ")))
+            },
+            Some(&(_, action)) => action,
+          };
+        let inputs: Vec<Input> =
+          try!(
+            s.split(',')
+            .filter(|input| !input.trim().is_empty())
+            .map(parse_input)
+            .collect()
+          );
+        self.bind(action, &inputs);
+      },
+    }
+    Ok(())
+  }
+}
+
+fn value<X: FromStr>(name: &str, value: &str) -> Result<X, String> {
+  X::from_str(value.trim()).map_err(|_| format!("Invalid value for {}: {:?}", name, value))
+}
+
+/// Apply the bindings in a file on top of `bindings`.
+pub fn read_file(bindings: &mut T, path: &Path) -> Result<(), String> {
+  let mut contents = String::new();
+  try!(
+    fs::File::open(path)
+    .and_then(|mut file| file.read_to_string(&mut contents))
+    .map_err(|err| format!("Couldn't read bindings from {:?}: {}", path, err))
+  );
+  parse(bindings, &contents).map_err(|err| format!("{:?}: {}", path, err))
+}
+
+/// Apply bindings in the file format on top of `bindings`.
+pub fn parse(bindings: &mut T, contents: &str) -> Result<(), String> {
+  for (i, line) in contents.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap().trim();
+    let s = try!(parts.next().ok_or_else(|| format!("line {}: expected action = inputs", i + 1)));
+    try!(bindings.set(name, s).map_err(|err| format!("line {}: {}", i + 1, err)));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+fn assert_close(x: Vector2<f32>, y: Vector2<f32>) {
+  assert!((x.x - y.x).abs() < 1e-4 && (x.y - y.y).abs() < 1e-4, "{:?} != {:?}", x, y);
+}
+
+#[test]
+fn default_bindings_map_inputs_to_actions() {
+  let bindings = new();
+  assert_eq!(bindings.action(Input::Key(Keycode::W)), Some(Action::MoveForward));
+  assert_eq!(bindings.action(Input::Key(Keycode::Space)), Some(Action::Jump));
+  assert_eq!(bindings.action(Input::Mouse(Mouse::Left)), Some(Action::Build));
+  assert_eq!(bindings.action(Input::Mouse(Mouse::Right)), Some(Action::Dig));
+  assert_eq!(bindings.action(Input::Key(Keycode::Q)), None);
+  assert_eq!(bindings.action(Input::Button(Button::A)), Some(Action::Jump));
+  assert_eq!(bindings.inputs(Action::Dig), vec!(Input::Mouse(Mouse::Right), Input::Button(Button::LeftShoulder)));
+
+  // Every action has a default.
+  for &(name, action) in &ACTIONS {
+    assert!(!bindings.inputs(action).is_empty(), "{} has no default binding", name);
+  }
+
+  assert_close(bindings.look(10, 16), Vector2::new(-0.01, -0.01));
+}
+
+#[test]
+fn bindings_files_remap_inputs() {
+  let mut bindings = new();
+  let contents = "\
+    # Arrows to move, and the mouse buttons swapped.\n\
+    move-forward = Up, w\n\
+    dig = mouse-left\n\
+    build = MOUSE-RIGHT, f\n\
+    jump = \n\
+    mouse-sensitivity = 2 # fast\n\
+    invert-mouse = true\n";
+  parse(&mut bindings, contents).unwrap();
+
+  assert_eq!(bindings.inputs(Action::MoveForward), vec!(Input::Key(Keycode::Up), Input::Key(Keycode::W)));
+  assert_eq!(bindings.action(Input::Key(Keycode::Up)), Some(Action::MoveForward));
+  // Up moved, so nothing looks up any more.
+  assert!(bindings.inputs(Action::LookUp).is_empty());
+  assert_eq!(bindings.action(Input::Mouse(Mouse::Left)), Some(Action::Dig));
+  assert_eq!(bindings.action(Input::Mouse(Mouse::Right)), Some(Action::Build));
+  assert_eq!(bindings.action(Input::Key(Keycode::F)), Some(Action::Build));
+  assert!(bindings.inputs(Action::Jump).is_empty());
+  assert_eq!(bindings.action(Input::Key(Keycode::Space)), None);
+
+  assert_close(bindings.look(10, 16), Vector2::new(-0.02, 0.02));
+
+  assert!(parse(&mut new(), "fly = space").is_err());
+  assert!(parse(&mut new(), "jump = hyperspace").is_err());
+  assert!(parse(&mut new(), "jump").is_err());
+  assert!(parse(&mut new(), "mouse-sensitivity = 0").is_err());
+  assert!(parse(&mut new(), "invert-mouse = sometimes").is_err());
+  assert!(parse(&mut new(), "stick-dead-zone = 1").is_err());
+}
+
+#[test]
+fn sticks_have_dead_zones_and_curves() {
+  let mut bindings = new();
+  parse(&mut bindings, "stick-dead-zone = 0.5\nstick-curve = 2\njump = pad-b\n").unwrap();
+  assert_eq!(bindings.action(Input::Button(Button::B)), Some(Action::Jump));
+  assert_eq!(bindings.action(Input::Button(Button::A)), None);
+
+  assert_close(bindings.stick(8000, -8000), Vector2::new(0.0, 0.0));
+  // Pushed all the way right.
+  assert_close(bindings.stick(32767, 0), Vector2::new(1.0, 0.0));
+  // Pushed 3/4 of the way up, i.e. halfway past the dead zone.
+  assert_close(bindings.stick(0, -24575), Vector2::new(0.0, 0.25));
+  // Corners are clamped to 1.
+  let corner = bindings.stick(32767, 32767);
+  assert_close(corner, Vector2::new(0.5f32.sqrt(), -0.5f32.sqrt()));
+}
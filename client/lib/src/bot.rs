@@ -0,0 +1,367 @@
+//! A headless client that plays by itself, for load testing a server without a GPU.
+//! It runs the same update thread and mesh workers as the real client, but throws away
+//! whatever would have gone to the view and audio threads.
+
+use cgmath::{Vector2, Vector3};
+use rand;
+use rand::SeedableRng;
+use std;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use stopwatch;
+use thread_scoped;
+use time;
+
+use common::protocol;
+
+use client;
+use mesh_pool;
+use metrics;
+use recording;
+use run;
+use server;
+use settings;
+use update_thread::update_thread;
+
+/// One thing for a bot to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  /// Add to the player's acceleration, like pressing a movement key. `walk 0 0 -1` is forward.
+  Walk(Vector3<f32>),
+  /// Turn the player by some radians, laterally and vertically.
+  Rotate(Vector2<f32>),
+  /// Jump.
+  Jump,
+  /// Dig where the player's looking, like right-clicking.
+  Dig,
+  /// Do nothing for some seconds.
+  Wait(f32),
+}
+
+/// What a bot does until it's done.
+#[derive(Debug, Clone)]
+pub enum Behaviour {
+  /// Run these commands in a loop.
+  Script(Vec<Command>),
+  /// Wander around and dig at random, seeded so runs are repeatable.
+  RandomWalk(u32),
+}
+
+/// Parse a script of commands, one per line:
+/// `walk X Y Z`, `rotate LATERAL VERTICAL`, `jump`, `dig` or `wait SECONDS`; `#` starts a comment.
+pub fn parse_script(script: &str) -> Result<Vec<Command>, String> {
+  let mut commands = Vec::new();
+  for (i, line) in script.lines().enumerate() {
+    let mut words = line.split('#').next().unwrap().split_whitespace();
+    let command =
+      match words.next() {
+        None => continue,
+        Some(command) => command,
+      };
+    let numbers: Result<Vec<f32>, _> = words.map(f32::from_str).collect();
+    let numbers = try!(numbers.map_err(|_| format!("line {}: expected numbers after {:?}", i + 1, command)));
+    let command =
+      match (command, numbers.len()) {
+        ("walk", 3) => Command::Walk(Vector3::new(numbers[0], numbers[1], numbers[2])),
+        ("rotate", 2) => Command::Rotate(Vector2::new(numbers[0], numbers[1])),
+        ("jump", 0) => Command::Jump,
+        ("dig", 0) => Command::Dig,
+        ("wait", 1) if numbers[0] >= 0.0 => Command::Wait(numbers[0]),
+        _ => return Err(format!("line {}: can't understand {:?}", i + 1, line.trim())),
+      };
+    commands.push(command);
+  }
+  if !commands.iter().any(|command| match *command { Command::Wait(_) => true, _ => false }) {
+    return Err(String::from("A script needs at least one wait, or it would flood the server"))
+  }
+  Ok(commands)
+}
+
+/// The next few things a randomly walking bot does: change direction, maybe jump or dig,
+/// then carry on for a bit. `walking` is the acceleration it currently has.
+fn random_commands<Rng: rand::Rng>(rng: &mut Rng, walking: &mut Vector3<f32>) -> Vec<Command> {
+  let mut commands = Vec::new();
+  // Undo the last walk before starting a new one, like releasing a key.
+  commands.push(Command::Walk(-*walking));
+  let directions = [
+    Vector3::new(0.0, 0.0, -1.0),
+    Vector3::new(0.0, 0.0, 1.0),
+    Vector3::new(-1.0, 0.0, 0.0),
+    Vector3::new(1.0, 0.0, 0.0),
+  ];
+  // Mostly forward.
+  *walking = if rng.gen_range(0, 3) == 0 { *rng.choose(&directions).unwrap() } else { directions[0] };
+  commands.push(Command::Walk(*walking));
+  commands.push(Command::Rotate(Vector2::new(rng.gen_range(-1.0, 1.0), rng.gen_range(-0.2, 0.2))));
+  if rng.gen_range(0, 4) == 0 {
+    commands.push(Command::Jump);
+  }
+  if rng.gen_range(0, 3) == 0 {
+    commands.push(Command::Dig);
+  }
+  commands.push(Command::Wait(rng.gen_range(0.5, 3.0)));
+  commands
+}
+
+/// One pass through a looping script. Walking adds to the player's acceleration, so whatever
+/// the script walked by is undone at the end, like releasing every key, before it starts again.
+fn script_pass(script: &[Command]) -> Vec<Command> {
+  let mut commands = script.to_vec();
+  let walking =
+    script.iter().fold(Vector3::new(0.0, 0.0, 0.0), |walking, command| {
+      match *command {
+        Command::Walk(v) => walking + v,
+        _ => walking,
+      }
+    });
+  if walking != Vector3::new(0.0, 0.0, 0.0) {
+    commands.push(Command::Walk(-walking));
+  }
+  commands
+}
+
+fn send(server: &server::T, client: &client::T, command: &Command) {
+  let message =
+    match *command {
+      Command::Walk(v) => protocol::ClientToServer::Walk(client.player_id, v),
+      Command::Rotate(r) => protocol::ClientToServer::RotatePlayer(client.player_id, r),
+      Command::Jump => {
+        server.talk.tell(&protocol::ClientToServer::StartJump(client.player_id));
+        protocol::ClientToServer::StopJump(client.player_id)
+      },
+      Command::Dig => protocol::ClientToServer::Remove(client.player_id),
+      Command::Wait(_) => return,
+    };
+  server.talk.tell(&message);
+}
+
+/// Sleep for up to `seconds`, returning early (and false) if it's time to quit.
+fn wait(quit: &Mutex<bool>, seconds: f32) -> bool {
+  let end = time::precise_time_ns() + (seconds as f64 * 1e9) as u64;
+  while time::precise_time_ns() < end {
+    if *quit.lock().unwrap() {
+      return false
+    }
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  !*quit.lock().unwrap()
+}
+
+fn drive(quit: &Mutex<bool>, server: &server::T, client: &client::T, behaviour: &Behaviour) {
+  let mut next_commands: Box<FnMut() -> Vec<Command>> =
+    match *behaviour {
+      Behaviour::Script(ref commands) => {
+        let commands = commands.clone();
+        Box::new(move || script_pass(&commands))
+      },
+      Behaviour::RandomWalk(seed) => {
+        let mut rng: rand::XorShiftRng = SeedableRng::from_seed([seed, 1, 2, 3]);
+        let mut walking = Vector3::new(0.0, 0.0, 0.0);
+        Box::new(move || random_commands(&mut rng, &mut walking))
+      },
+    };
+
+  loop {
+    for command in next_commands() {
+      match command {
+        Command::Wait(seconds) => {
+          if !wait(quit, seconds) {
+            return
+          }
+        },
+        command => send(server, client, &command),
+      }
+    }
+  }
+}
+
+/// Run the update thread and mesh workers headlessly until `quit` is set, with `recv_server`
+/// standing in for the server's messages. Returns the terrain latency, if any blocks were loaded.
+fn update_headless<RecvServer, UpdateServer>(
+  quit: &Mutex<bool>,
+  client: &client::T,
+  mut recv_server: RecvServer,
+  mut update_server: UpdateServer,
+) -> Option<metrics::Summary> where
+  RecvServer: FnMut() -> Option<protocol::ServerToClient>,
+  UpdateServer: FnMut(protocol::ClientToServer),
+{
+  let voxel_updates = Mutex::new(std::collections::VecDeque::new());
+
+  let mesh_threads: Vec<_> =
+    (0 .. mesh_pool::WORKER_COUNT)
+    .map(|_| {
+      unsafe {
+        thread_scoped::scoped(move || {
+          mesh_pool::worker_thread(quit, client);
+        })
+      }
+    })
+    .collect();
+
+  let stopwatch = {
+    let voxel_updates = &voxel_updates;
+    update_thread(
+      quit,
+      client,
+      &mut recv_server,
+      &mut || { voxel_updates.lock().unwrap().pop_front() },
+      &mut |_| {},
+      &mut |_| {},
+      &mut |_| {},
+      &mut update_server,
+      &mut |request_time, updates, reason| { voxel_updates.lock().unwrap().push_back((request_time, updates, reason)) },
+    );
+
+    stopwatch::clone()
+  };
+
+  for mesh_thread in mesh_threads {
+    mesh_thread.join();
+  }
+  stopwatch.print();
+  client.metrics.snapshot().block_load_ms
+}
+
+/// Connect to a server as a bot, and play for `duration`.
+/// Returns the terrain latency over the run, if any blocks were loaded.
+pub fn run(
+  listen_url: &str,
+  server_url: &str,
+  settings: &settings::T,
+  behaviour: &Behaviour,
+  duration: Duration,
+) -> Option<metrics::Summary> {
+  let quit = Mutex::new(false);
+  let quit = &quit;
+
+  let server = server::new(&server_url, &listen_url, settings.record.as_ref().map(|path| path.as_path()));
+
+  // Bots always ask the server for voxels, rather than reading them from disk.
+  let client = run::connect_client(&listen_url, &server, settings, false);
+  let client = &client;
+
+  let driver = {
+    let server = server.clone();
+    unsafe {
+      thread_scoped::scoped(move || {
+        drive(quit, &server, client, behaviour);
+      })
+    }
+  };
+
+  let timer = {
+    unsafe {
+      thread_scoped::scoped(move || {
+        std::thread::sleep(duration);
+        *quit.lock().unwrap() = true;
+      })
+    }
+  };
+
+  let latency = {
+    let server = server.clone();
+    let talk = server.talk.clone();
+    update_headless(quit, client, move || run::recv_server(client, &server), move |up| talk.tell(&up))
+  };
+
+  timer.join();
+  driver.join();
+  latency
+}
+
+/// How long to keep going after a replay runs out, so the last messages it sent get processed.
+const REPLAY_SETTLE_SECS: u64 = 2;
+
+/// Feed a recording of server messages (see `recording`) to a headless client, `speed` times
+/// faster than it was recorded. Whatever the client sends back is dropped.
+/// Returns the terrain latency over the replay, if any blocks were loaded.
+pub fn replay(
+  frames: Vec<recording::Frame>,
+  speed: f64,
+  settings: &settings::T,
+) -> Option<metrics::Summary> {
+  let quit = Mutex::new(false);
+  let quit = &quit;
+
+  let replay = Mutex::new(recording::replay(frames, speed));
+  let replay = &replay;
+
+  let client =
+    run::connect_client_with(
+      "replay",
+      &mut || {
+        replay.lock().unwrap().next_message()
+          .unwrap_or_else(|| panic!("The recording ends before a player was added"))
+      },
+      &mut |_| {},
+      settings,
+      false,
+    );
+  let client = &client;
+
+  let timer = {
+    unsafe {
+      thread_scoped::scoped(move || {
+        while !replay.lock().unwrap().is_finished() {
+          std::thread::sleep(Duration::from_millis(100));
+        }
+        std::thread::sleep(Duration::from_secs(REPLAY_SETTLE_SECS));
+        *quit.lock().unwrap() = true;
+      })
+    }
+  };
+
+  let latency = update_headless(quit, client, move || replay.lock().unwrap().try(), |_| {});
+  timer.join();
+  latency
+}
+
+#[test]
+fn parses_scripts() {
+  let script = "# Walk in a square.\nwalk 0 0 -1\nwait 1.5\n\nrotate 1.57 0 # turn\njump\ndig\n";
+  assert_eq!(
+    parse_script(script).unwrap(),
+    vec!(
+      Command::Walk(Vector3::new(0.0, 0.0, -1.0)),
+      Command::Wait(1.5),
+      Command::Rotate(Vector2::new(1.57, 0.0)),
+      Command::Jump,
+      Command::Dig,
+    )
+  );
+
+  assert!(parse_script("walk 1 0\nwait 1").is_err());
+  assert!(parse_script("fly\nwait 1").is_err());
+  assert!(parse_script("wait -1").is_err());
+  assert!(parse_script("dig").is_err());
+}
+
+#[test]
+fn looping_scripts_stop_walking_between_passes() {
+  let script = parse_script("walk 0 0 -1\nwait 1\nwalk 1 0 0\nwait 1\nwalk 0 0 1\nwait 1\n").unwrap();
+  let commands = script_pass(&script);
+  assert_eq!(&commands[.. script.len()], &script[..]);
+  assert_eq!(&commands[script.len() ..], &[Command::Walk(Vector3::new(-1.0, 0.0, 0.0))][..]);
+
+  let balanced = parse_script("walk 0 0 -1\nwait 1\nwalk 0 0 1\nwait 1\n").unwrap();
+  assert_eq!(script_pass(&balanced), balanced);
+}
+
+#[test]
+fn random_walks_keep_their_acceleration_bounded() {
+  let mut rng: rand::XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+  let mut walking = Vector3::new(0.0, 0.0, 0.0);
+  let mut acceleration = Vector3::new(0.0, 0.0, 0.0);
+  for _ in 0 .. 100 {
+    let commands = random_commands(&mut rng, &mut walking);
+    for command in &commands {
+      if let Command::Walk(v) = *command {
+        acceleration = acceleration + v;
+      }
+    }
+    assert_eq!(acceleration, walking);
+    assert!(commands.iter().any(|command| match *command { Command::Wait(_) => true, _ => false }));
+  }
+}
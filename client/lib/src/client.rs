@@ -5,23 +5,24 @@ use num::iter::range_inclusive;
 use rand;
 use rand::{Rng, SeedableRng};
 use std::sync::Mutex;
+use time;
 
 use common::entity_id;
 use common::id_allocator;
 use common::protocol;
 use common::surroundings_loader::SurroundingsLoader;
+use common::tree_species;
 use common::voxel;
 
 use block_position;
+use disk_cache;
+use metrics;
 use lod;
+use mesh_pool;
+use settings;
 use terrain_mesh;
 use terrain_buffers;
-
-/// The distances at which LOD switches.
-pub const LOD_THRESHOLDS: [i32; terrain_mesh::LOD_COUNT-1] = [2, 16, 32];
-
-// TODO: Remove this once our RAM usage doesn't skyrocket with load distance.
-const MAX_LOAD_DISTANCE: i32 = 80;
+use voxel_cache;
 
 /// The main client state.
 pub struct T {
@@ -30,38 +31,63 @@ pub struct T {
   pub player_position: Mutex<Point3<f32>>,
   pub last_footstep: Mutex<Point3<f32>>,
   pub load_position: Mutex<Option<Point3<f32>>>,
-  pub max_load_distance: i32,
+  /// Settings from the settings file and command line.
+  pub settings: settings::T,
+  /// How far away blocks are currently loaded.
+  pub max_load_distance: Mutex<i32>,
+  /// The farthest `max_load_distance` can go, given the terrain budget.
+  pub load_distance_limit: i32,
+  /// A new `max_load_distance` for the update thread to switch to.
+  pub requested_load_distance: Mutex<Option<i32>>,
   pub surroundings_loader: Mutex<SurroundingsLoader>,
   pub id_allocator: Mutex<id_allocator::T<entity_id::T>>,
   /// A record of all the blocks that have been loaded.
   pub loaded_blocks: Mutex<block_position::map::T<(terrain_mesh::T, lod::T)>>,
+  /// The LODs of its lower neighbours that each loaded block's seams were meshed against.
+  pub seam_lods: Mutex<block_position::map::T<terrain_mesh::NeighbourLods>>,
   /// Map each block to the number of voxels inside it that we have.
   pub block_voxels_loaded: Mutex<block_position::with_lod::map::T<u32>>,
   /// The voxels we have cached from the server.
-  // TODO: Should probably remove from this at some point.
   pub voxels: Mutex<voxel::tree::T>,
+  /// Which voxels are cached, for evicting them.
+  pub voxel_cache: Mutex<voxel_cache::T>,
+  #[allow(missing_docs)]
+  pub metrics: metrics::T,
+  /// Seeds grass placement; set from the world we're connected to.
+  pub grass_seed: Mutex<u64>,
+  /// Voxels saved to disk from this and earlier connections to the same world.
+  pub disk_cache: Mutex<disk_cache::T>,
+  /// Blocks waiting to be meshed, or meshed and waiting to be loaded.
+  pub mesh_pool: mesh_pool::T,
   /// The number of terrain requests that are outstanding,
   pub outstanding_terrain_requests: Mutex<u32>,
   pub rng: Mutex<rand::XorShiftRng>,
+  /// The kind of tree placed by the brush tool.
+  pub tree_species: Mutex<tree_species::T>,
+  /// The names of the prefabs saved on the server, as of the last time it told us.
+  pub prefabs: Mutex<Vec<String>>,
+  /// Radians about the y axis to rotate pasted prefabs by.
+  pub prefab_rotation: Mutex<f32>,
 }
 
 #[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: entity_id::T, position: Point3<f32>) -> T {
-  let mut load_distance = load_distance(terrain_buffers::POLYGON_BUDGET as i32);
-
-  if load_distance > MAX_LOAD_DISTANCE {
-    info!("load_distance {} capped at {}", load_distance, MAX_LOAD_DISTANCE);
-    load_distance = MAX_LOAD_DISTANCE;
+pub fn new(
+  client_id: protocol::ClientId,
+  player_id: entity_id::T,
+  position: Point3<f32>,
+  settings: settings::T,
+) -> T {
+  let polygon_budget = terrain_buffers::polygon_budget(settings.terrain_byte_budget);
+  let mut load_distance = load_distance(polygon_budget as i32, &settings);
+
+  if load_distance > settings.max_load_distance {
+    info!("load_distance {} capped at {}", load_distance, settings.max_load_distance);
+    load_distance = settings.max_load_distance;
   } else {
     info!("load_distance {}", load_distance);
   }
 
-  let surroundings_loader = {
-    SurroundingsLoader::new(
-      load_distance,
-      LOD_THRESHOLDS.iter().cloned().collect(),
-    )
-  };
+  let surroundings_loader = surroundings_loader(&settings, load_distance);
 
   let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
   let s1 = rng.next_u32();
@@ -76,26 +102,47 @@ pub fn new(client_id: protocol::ClientId, player_id: entity_id::T, position: Poi
     player_position: Mutex::new(position),
     last_footstep: Mutex::new(position),
     load_position: Mutex::new(None),
-    max_load_distance: load_distance,
+    settings: settings,
+    max_load_distance: Mutex::new(load_distance),
+    load_distance_limit: load_distance,
+    requested_load_distance: Mutex::new(None),
     surroundings_loader: Mutex::new(surroundings_loader),
     id_allocator: Mutex::new(id_allocator::new()),
     loaded_blocks: Mutex::new(block_position::map::new()),
+    seam_lods: Mutex::new(block_position::map::new()),
     block_voxels_loaded: Mutex::new(block_position::with_lod::map::new()),
     voxels: Mutex::new(voxel::tree::new()),
+    voxel_cache: Mutex::new(voxel_cache::new(time::precise_time_ns())),
+    metrics: metrics::new(),
+    grass_seed: Mutex::new(0),
+    disk_cache: Mutex::new(disk_cache::new()),
+    mesh_pool: mesh_pool::new(),
     outstanding_terrain_requests: Mutex::new(0),
     rng: Mutex::new(rng),
+    tree_species: Mutex::new(tree_species::T::Oak),
+    prefabs: Mutex::new(Vec::new()),
+    prefab_rotation: Mutex::new(0.0),
   }
 }
 
 unsafe impl Sync for T {}
 
-fn load_distance(mut polygon_budget: i32) -> i32 {
+/// A `SurroundingsLoader` for loading blocks out to `load_distance` with the LOD thresholds in `settings`.
+pub fn surroundings_loader(settings: &settings::T, load_distance: i32) -> SurroundingsLoader {
+  SurroundingsLoader::new(
+    load_distance,
+    settings.lod_thresholds.iter().cloned().collect(),
+  )
+}
+
+fn load_distance(mut polygon_budget: i32, settings: &settings::T) -> i32 {
   // TODO: This should try to account for VRAM not used on a per-poly basis.
 
   let mut load_distance = 0;
   let mut prev_threshold = 0;
   let mut prev_square = 0;
-  for (&threshold, &quality) in LOD_THRESHOLDS.iter().zip(terrain_mesh::EDGE_SAMPLES.iter()) {
+  for (lod, &threshold) in settings.lod_thresholds.iter().enumerate() {
+    let quality = settings.resolutions.edge_samples(lod::T(lod as u32));
     let polygons_per_block = (quality * quality * 4) as i32;
     for i in range_inclusive(prev_threshold, threshold) {
       let i = 2 * i + 1;
@@ -116,7 +163,7 @@ fn load_distance(mut polygon_budget: i32) -> i32 {
   loop {
     let square = width * width;
     // The "to infinity and beyond" quality.
-    let quality = terrain_mesh::EDGE_SAMPLES[LOD_THRESHOLDS.len()];
+    let quality = settings.resolutions.edge_samples(lod::T(settings.lod_thresholds.len() as u32));
     let polygons_per_block = (quality * quality * 4) as i32;
     let polygons_in_layer = (square - prev_square) * polygons_per_block;
     polygon_budget -= polygons_in_layer;
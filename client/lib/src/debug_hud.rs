@@ -0,0 +1,299 @@
+//! A toggleable overlay of debugging text: frame rate, where the player is, and how terrain
+//! loading and the connection to the server are doing.
+
+use cgmath::{Point3, Vector2};
+use gl;
+use gl::types::*;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::{Texture2D, TextureUnit};
+use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
+
+use common::color::Color4;
+
+use block_position;
+use client;
+use fontloader::FontLoader;
+use lod;
+use shaders::texture::TextureShader;
+use vertex::TextureVertex;
+
+/// The most lines of text the overlay can show.
+const MAX_LINES: usize = 16;
+const VERTICES_PER_LINE: usize = 6;
+/// How often the text is rebuilt while it's showing.
+const REFRESH_INTERVAL_NS: u64 = 250_000_000;
+/// Pixels between the text and the corner of the window.
+const MARGIN: f32 = 8.0;
+/// About how far the player's eyes are above their feet.
+const EYE_HEIGHT: f32 = 2.0;
+
+/// What the overlay shows.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub struct Stats {
+  pub fps: f32,
+  pub position: Point3<f32>,
+  pub block: block_position::T,
+  /// The LOD the block under the player is loaded at, if it's loaded.
+  pub lod_underfoot: Option<lod::T>,
+  pub outstanding_terrain_requests: u32,
+  /// How many blocks are loaded at each LOD, finest first.
+  pub loaded_blocks: Vec<usize>,
+  /// The round trip time of the last answered ping.
+  pub ping_ms: Option<f64>,
+}
+
+/// Collect the overlay's stats from the client.
+pub fn gather(client: &client::T, fps: f32) -> Stats {
+  let position = *client.player_position.lock().unwrap();
+  let underfoot = block_position::of_world_position(&Point3::new(position.x, position.y - EYE_HEIGHT, position.z));
+
+  let mut lod_underfoot = None;
+  let mut loaded_blocks = Vec::new();
+  for (block_position, &(_, lod)) in client.loaded_blocks.lock().unwrap().iter() {
+    if *block_position == underfoot {
+      lod_underfoot = Some(lod);
+    }
+    let lod::T(i) = lod;
+    let i = i as usize;
+    if loaded_blocks.len() <= i {
+      loaded_blocks.resize(i + 1, 0);
+    }
+    loaded_blocks[i] += 1;
+  }
+
+  Stats {
+    fps: fps,
+    position: position,
+    block: block_position::of_world_position(&position),
+    lod_underfoot: lod_underfoot,
+    outstanding_terrain_requests: *client.outstanding_terrain_requests.lock().unwrap(),
+    loaded_blocks: loaded_blocks,
+    ping_ms: client.metrics.snapshot().ping_ms,
+  }
+}
+
+/// The overlay's text, one line per string.
+pub fn lines(stats: &Stats) -> Vec<String> {
+  let block = stats.block.as_pnt();
+  let underfoot =
+    match stats.lod_underfoot {
+      None => String::from("not loaded"),
+      Some(lod::T(lod)) => format!("LOD {}", lod),
+    };
+  let per_lod: Vec<String> =
+    stats.loaded_blocks.iter()
+    .enumerate()
+    .map(|(lod, count)| format!("LOD {}: {}", lod, count))
+    .collect();
+  let ping =
+    match stats.ping_ms {
+      None => String::from("-"),
+      Some(ping_ms) => format!("{:.1} ms", ping_ms),
+    };
+
+  vec!(
+    format!("FPS: {:.1}", stats.fps),
+    format!("Position: ({:.1}, {:.1}, {:.1})", stats.position.x, stats.position.y, stats.position.z),
+    format!("Block: ({}, {}, {}), underfoot {}", block.x, block.y, block.z, underfoot),
+    format!("Outstanding terrain requests: {}", stats.outstanding_terrain_requests),
+    format!(
+      "Loaded blocks: {} ({})",
+      stats.loaded_blocks.iter().fold(0, |sum, x| sum + x),
+      per_lod.join(", "),
+    ),
+    format!("Ping: {}", ping),
+  )
+}
+
+/// Counts frames, to work out the frame rate over about the last second.
+struct FrameCounter {
+  window_start: u64,
+  frames: u32,
+  fps: f32,
+}
+
+impl FrameCounter {
+  fn frame(&mut self, now: u64) {
+    self.frames += 1;
+    let elapsed = now - self.window_start;
+    if elapsed >= 1_000_000_000 {
+      self.fps = self.frames as f32 * 1_000_000_000.0 / elapsed as f32;
+      self.frames = 0;
+      self.window_start = now;
+    }
+  }
+}
+
+/// The overlay's text, rendered into textures and drawn in HUD coordinates.
+pub struct T<'a> {
+  /// Whether to show the overlay.
+  pub show: bool,
+  fonts: FontLoader,
+  frame_counter: FrameCounter,
+  last_refresh: Option<u64>,
+  /// One texture per line.
+  textures: Vec<Texture2D<'a>>,
+  /// A quad for each line, in the same order as `textures`.
+  quads: GLArray<'a, TextureVertex>,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b:'a>(
+  gl: &'a mut GLContext,
+  shader: &TextureShader<'a>,
+) -> T<'b> {
+  let buffer = GLBuffer::new(gl, MAX_LINES * VERTICES_PER_LINE);
+  let mut quads =
+    GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    );
+
+  // Fill the buffer up front, so refreshing the text only ever updates it.
+  let empty =
+    TextureVertex {
+      world_position: Point3::new(0.0, 0.0, 0.0),
+      texture_position: Vector2::new(0.0, 0.0),
+    };
+  quads.push(gl, &[empty; MAX_LINES * VERTICES_PER_LINE]);
+
+  T {
+    show: false,
+    fonts: FontLoader::new(),
+    frame_counter: FrameCounter { window_start: 0, frames: 0, fps: 0.0 },
+    last_refresh: None,
+    textures: Vec::new(),
+    quads: quads,
+  }
+}
+
+/// Two triangles covering `size` pixels, `top_left` pixels from the top left corner of a window,
+/// in HUD coordinates. The texture's first row goes at the top.
+fn quad(window_size: Vector2<i32>, top_left: Vector2<f32>, size: (u32, u32)) -> [TextureVertex; VERTICES_PER_LINE] {
+  // The HUD spans [-aspect, aspect] horizontally and [-1, 1] vertically.
+  let scale = 2.0 / window_size.y as f32;
+  let aspect = window_size.x as f32 / window_size.y as f32;
+  let x0 = -aspect + top_left.x * scale;
+  let y1 = 1.0 - top_left.y * scale;
+  let x1 = x0 + size.0 as f32 * scale;
+  let y0 = y1 - size.1 as f32 * scale;
+
+  let vtx = |x, y, u, v| {
+    TextureVertex {
+      world_position: Point3::new(x, y, 0.0),
+      texture_position: Vector2::new(u, v),
+    }
+  };
+  [
+    vtx(x0, y0, 0.0, 1.0), vtx(x1, y1, 1.0, 0.0), vtx(x0, y1, 0.0, 0.0),
+    vtx(x0, y0, 0.0, 1.0), vtx(x1, y0, 1.0, 1.0), vtx(x1, y1, 1.0, 0.0),
+  ]
+}
+
+impl<'a> T<'a> {
+  #[allow(missing_docs)]
+  pub fn toggle(&mut self) {
+    self.show = !self.show;
+    // Don't show stale text.
+    self.last_refresh = None;
+  }
+
+  /// Count a rendered frame, and rebuild the text if it's showing and due.
+  pub fn frame(&mut self, gl: &mut GLContext, window_size: Vector2<i32>, client: &client::T, now: u64) {
+    self.frame_counter.frame(now);
+    if !self.show {
+      return
+    }
+    if let Some(last_refresh) = self.last_refresh {
+      if now - last_refresh < REFRESH_INTERVAL_NS {
+        return
+      }
+    }
+    self.last_refresh = Some(now);
+
+    let lines = lines(&gather(client, self.frame_counter.fps));
+    let color = Color4::of_rgba(0xFF, 0xFF, 0xFF, 0xFF);
+    let mut vertices = Vec::new();
+    let mut y = MARGIN;
+    self.textures.clear();
+    for line in lines.iter().take(MAX_LINES) {
+      let size = self.fonts.mono.size_of(line);
+      self.textures.push(self.fonts.mono.render(gl, line, color));
+      vertices.extend_from_slice(&quad(window_size, Vector2::new(MARGIN, y), size));
+      y += size.1 as f32;
+    }
+
+    self.quads.buffer.byte_buffer.bind(gl);
+    self.quads.buffer.update(gl, 0, &vertices);
+  }
+
+  /// Draw the text over whatever's been drawn so far.
+  pub fn draw(&self, gl: &mut GLContext, shader: &TextureShader, texture_unit: &TextureUnit) {
+    shader.shader.use_shader(gl);
+    let alpha_threshold = shader.shader.get_uniform_location("alpha_threshold");
+    unsafe {
+      gl::Uniform1f(alpha_threshold, 0.0);
+      gl::Disable(gl::DEPTH_TEST);
+      gl::ActiveTexture(texture_unit.gl_id());
+    }
+
+    self.quads.bind(gl);
+    for (i, texture) in self.textures.iter().enumerate() {
+      unsafe {
+        gl::BindTexture(gl::TEXTURE_2D, texture.handle.gl_id);
+        gl::DrawArrays(gl::TRIANGLES, (i * VERTICES_PER_LINE) as GLint, VERTICES_PER_LINE as GLsizei);
+      }
+    }
+
+    unsafe {
+      gl::Enable(gl::DEPTH_TEST);
+    }
+  }
+}
+
+#[test]
+fn formats_stats() {
+  let stats =
+    Stats {
+      fps: 29.96,
+      position: Point3::new(12.34, 40.0, -7.06),
+      block: block_position::new(1, 5, -1),
+      lod_underfoot: Some(lod::T(1)),
+      outstanding_terrain_requests: 2,
+      loaded_blocks: vec!(3, 0, 40),
+      ping_ms: None,
+    };
+  assert_eq!(
+    lines(&stats),
+    vec!(
+      "FPS: 30.0",
+      "Position: (12.3, 40.0, -7.1)",
+      "Block: (1, 5, -1), underfoot LOD 1",
+      "Outstanding terrain requests: 2",
+      "Loaded blocks: 43 (LOD 0: 3, LOD 1: 0, LOD 2: 40)",
+      "Ping: -",
+    )
+  );
+
+  let stats = Stats { lod_underfoot: None, ping_ms: Some(12.345), .. stats };
+  assert_eq!(lines(&stats)[2], "Block: (1, 5, -1), underfoot not loaded");
+  assert_eq!(lines(&stats)[5], "Ping: 12.3 ms");
+}
+
+#[test]
+fn counts_frames_per_second() {
+  let mut counter = FrameCounter { window_start: 0, frames: 0, fps: 0.0 };
+  for i in 1 .. 21 {
+    counter.frame(i * 50_000_000);
+  }
+  assert_eq!(counter.fps, 20.0);
+  counter.frame(1_500_000_000);
+  assert_eq!(counter.fps, 20.0);
+}
@@ -0,0 +1,302 @@
+//! Voxels received from a server, saved to disk per world so that revisiting an area (even after
+//! reconnecting) doesn't have to fetch them again.
+//!
+//! Each block's voxels are saved along with the versions of the regions they came from. The server
+//! tells us the current versions when we connect and whenever voxels change, so checking whether a
+//! saved block is still good doesn't take a round trip.
+//!
+//! New versions are held back until the voxels they go with are applied, so that old voxels are
+//! never saved under new versions.
+//!
+//! Deciding whether a block needs saving is quick, but writing it isn't, so writes are handed off
+//! (to the mesh pool) as `Save`s.
+
+use bincode;
+use bincode::rustc_serialize::{decode, encode};
+use cgmath::Point3;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use common::fnv_map;
+use common::protocol;
+use common::voxel;
+
+use block_position;
+use lod;
+use terrain_mesh;
+
+/// Where saved voxels go, relative to the working directory.
+pub const DIRECTORY: &'static str = "voxel_cache";
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct Entry {
+  versions: Vec<(Point3<i32>, u64)>,
+  voxels: Vec<(voxel::bounds::T, voxel::T)>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(missing_docs)]
+pub struct Stats {
+  pub hits: u64,
+  pub misses: u64,
+  pub writes: u64,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The directory for the current world's blocks, once we know which world it is.
+  directory: Option<PathBuf>,
+  /// The current versions of all the regions that aren't `protocol::UNEDITED_VERSION`.
+  versions: fnv_map::T<Point3<i32>, u64>,
+  /// Versions that have been received, each waiting on the voxel update that follows it.
+  pending_versions: VecDeque<Vec<(Point3<i32>, u64)>>,
+  /// The versions of the blocks we know are on disk, by block and lg(voxel size).
+  saved: fnv_map::T<(block_position::T, i16), Vec<(Point3<i32>, u64)>>,
+  stats: Stats,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    directory: None,
+    versions: fnv_map::new(),
+    pending_versions: VecDeque::new(),
+    saved: fnv_map::new(),
+    stats: Default::default(),
+  }
+}
+
+fn to_io_error(err: bincode::rustc_serialize::DecodingError) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+impl T {
+  /// Start saving and loading blocks for a world under `root`.
+  pub fn open(
+    &mut self,
+    root: &Path,
+    world: protocol::WorldId,
+    versions: Vec<(Point3<i32>, u64)>,
+  ) -> io::Result<()> {
+    let directory = root.join(format!("{:016x}", world.0));
+    try!(fs::create_dir_all(&directory));
+    info!("Using saved voxels in {:?}", directory);
+    self.directory = Some(directory);
+    self.versions = versions.into_iter().collect();
+    self.saved = fnv_map::new();
+    Ok(())
+  }
+
+  /// Hold on to new versions of regions until the voxels that go with them are applied.
+  pub fn push_versions(&mut self, versions: Vec<(Point3<i32>, u64)>) {
+    self.pending_versions.push_back(versions);
+  }
+
+  /// Apply the versions that go with the next voxel update from the server.
+  pub fn apply_next_versions(&mut self) {
+    match self.pending_versions.pop_front() {
+      None => warn!("Voxels were updated without new versions"),
+      Some(versions) => self.versions.extend(versions.into_iter()),
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    self.stats
+  }
+
+  /// Blocks are saved by the size of their voxels rather than their LOD,
+  /// so that changing which LOD has which resolution doesn't mix them up.
+  fn path(&self, block_position: &block_position::T, lg_size: i16) -> Option<PathBuf> {
+    self.directory.as_ref().map(|directory| {
+      let p = block_position.as_pnt();
+      directory.join(format!("lg{}", lg_size)).join(format!("{},{},{}", p.x, p.y, p.z))
+    })
+  }
+
+  /// The current versions of the regions a block's voxels come from, sorted.
+  fn versions_for(
+    &self,
+    resolutions: &terrain_mesh::Resolutions,
+    block_position: &block_position::T,
+    lod: lod::T,
+  ) -> Vec<(Point3<i32>, u64)> {
+    let mut regions: Vec<Point3<i32>> =
+      terrain_mesh::voxels_for_block(resolutions, block_position, lod).iter()
+      .map(protocol::version_region)
+      .collect();
+    regions.sort_by(|x, y| (x.x, x.y, x.z).cmp(&(y.x, y.y, y.z)));
+    regions.dedup();
+    regions.into_iter()
+      .map(|r| (r, self.versions.get(&r).cloned().unwrap_or(protocol::UNEDITED_VERSION)))
+      .collect()
+  }
+
+  /// A block's saved voxels, if they're still up to date.
+  pub fn load(
+    &mut self,
+    resolutions: &terrain_mesh::Resolutions,
+    block_position: &block_position::T,
+    lod: lod::T,
+  ) -> Option<Vec<(voxel::bounds::T, voxel::T)>> {
+    let lg_size = resolutions.lg_sample_size(lod);
+    let path =
+      match self.path(block_position, lg_size) {
+        None => return None,
+        Some(path) => path,
+      };
+
+    let entry = fs::File::open(&path).and_then(|mut file| {
+      let mut bytes = Vec::new();
+      try!(file.read_to_end(&mut bytes));
+      decode::<Entry>(&bytes).map_err(to_io_error)
+    });
+    let entry =
+      match entry {
+        Ok(entry) => entry,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+          self.stats.misses += 1;
+          return None
+        },
+        Err(err) => {
+          warn!("Couldn't load saved voxels from {:?}: {}", path, err);
+          self.stats.misses += 1;
+          return None
+        },
+      };
+
+    if entry.versions != self.versions_for(resolutions, block_position, lod) {
+      debug!("Saved voxels for {:?} at {:?} are out of date", block_position, lod);
+      self.stats.misses += 1;
+      return None
+    }
+
+    self.stats.hits += 1;
+    self.saved.insert((*block_position, lg_size), entry.versions);
+    Some(entry.voxels)
+  }
+
+  /// Start saving a block's voxels, unless what's saved is already up to date.
+  pub fn save(
+    &self,
+    resolutions: &terrain_mesh::Resolutions,
+    block_position: &block_position::T,
+    lod: lod::T,
+  ) -> Option<Save> {
+    let lg_size = resolutions.lg_sample_size(lod);
+    let path =
+      match self.path(block_position, lg_size) {
+        None => return None,
+        Some(path) => path,
+      };
+
+    let versions = self.versions_for(resolutions, block_position, lod);
+    if self.saved.get(&(*block_position, lg_size)) == Some(&versions) {
+      return None
+    }
+
+    Some(Save {
+      path: path,
+      resolutions: *resolutions,
+      block_position: *block_position,
+      lod: lod,
+      versions: versions,
+    })
+  }
+
+  /// Note that a `Save` was written, as long as we're still in the world it was for.
+  fn saved(&mut self, save: Save) {
+    let is_current =
+      match self.directory {
+        None => false,
+        Some(ref directory) => save.path.starts_with(directory),
+      };
+    if is_current {
+      self.stats.writes += 1;
+      let lg_size = save.resolutions.lg_sample_size(save.lod);
+      self.saved.insert((save.block_position, lg_size), save.versions);
+    }
+  }
+}
+
+/// A block's voxels waiting to be written to disk.
+pub struct Save {
+  path: PathBuf,
+  resolutions: terrain_mesh::Resolutions,
+  block_position: block_position::T,
+  lod: lod::T,
+  /// The versions of the block's regions when the save was started.
+  versions: Vec<(Point3<i32>, u64)>,
+}
+
+impl Save {
+  /// Write the block's voxels, then record it in `cache`, which isn't locked while writing.
+  /// All of `terrain_mesh::voxels_for_block` for the block must be in `voxels`, as they were when
+  /// the save was started.
+  pub fn write(self, voxels: &voxel::tree::T, cache: &Mutex<T>) {
+    let bytes = {
+      let entry =
+        Entry {
+          versions: self.versions.clone(),
+          voxels:
+            terrain_mesh::voxels_for_block(&self.resolutions, &self.block_position, self.lod).into_iter()
+            .map(|bounds| (bounds, *voxels.get(&bounds).unwrap()))
+            .collect(),
+        };
+      encode(&entry, bincode::SizeLimit::Infinite).unwrap()
+    };
+    let written =
+      self.path.parent().map_or(Ok(()), fs::create_dir_all)
+      .and_then(|()| fs::File::create(&self.path))
+      .and_then(|mut file| file.write_all(&bytes));
+    match written {
+      Ok(()) => cache.lock().unwrap().saved(self),
+      Err(err) => warn!("Couldn't save voxels to {:?}: {}", self.path, err),
+    }
+  }
+}
+
+#[test]
+fn saved_blocks_are_only_loaded_while_up_to_date() {
+  let pid = unsafe { ::libc::getpid() };
+  let root = ::std::env::temp_dir().join(format!("playform-disk-cache-test-{}", pid));
+  let _ = fs::remove_dir_all(&root);
+
+  let block = block_position::new(1, -2, 3);
+  let resolutions: terrain_mesh::Resolutions = Default::default();
+  let lod = lod::T(2);
+  let mut voxels = voxel::tree::new();
+  for bounds in terrain_mesh::voxels_for_block(&resolutions, &block, lod) {
+    voxels.get_mut_or_create(&bounds).force_branches().data = Some(voxel::Volume(voxel::Material::Stone));
+  }
+
+  let cache = Mutex::new(new());
+  assert!(cache.lock().unwrap().load(&resolutions, &block, lod).is_none());
+  cache.lock().unwrap().open(&root, protocol::WorldId(7), Vec::new()).unwrap();
+  let save = cache.lock().unwrap().save(&resolutions, &block, lod).unwrap();
+  save.write(&voxels, &cache);
+  assert_eq!(cache.lock().unwrap().stats().writes, 1);
+  // It's up to date now.
+  assert!(cache.lock().unwrap().save(&resolutions, &block, lod).is_none());
+
+  // Reconnecting to the same world finds it.
+  let mut cache = new();
+  cache.open(&root, protocol::WorldId(7), Vec::new()).unwrap();
+  let loaded = cache.load(&resolutions, &block, lod).unwrap();
+  assert_eq!(loaded.len(), terrain_mesh::voxels_for_block(&resolutions, &block, lod).len());
+
+  // But not once one of its regions has been edited, nor in another world.
+  let region = protocol::version_region(&loaded[0].0);
+  cache.push_versions(vec!((region, 12)));
+  assert!(cache.load(&resolutions, &block, lod).is_some());
+  cache.apply_next_versions();
+  assert!(cache.load(&resolutions, &block, lod).is_none());
+  cache.open(&root, protocol::WorldId(8), Vec::new()).unwrap();
+  assert!(cache.load(&resolutions, &block, lod).is_none());
+
+  fs::remove_dir_all(&root).unwrap();
+}
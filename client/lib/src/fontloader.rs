@@ -5,6 +5,7 @@ use ttf;
 
 #[allow(missing_docs)]
 pub struct FontLoader {
+  #[allow(dead_code)]
   pub sans : ttf::Font,
   pub mono : ttf::Font,
 }
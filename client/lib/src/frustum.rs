@@ -0,0 +1,117 @@
+//! View frustum culling. This is just math, so it can be tested without a GL context.
+
+use cgmath::{Aabb3, Matrix4, Point, Point3, Vector, Vector3};
+
+/// The points `p` with `normal . p + distance >= 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HalfSpace {
+  normal: Vector3<f32>,
+  distance: f32,
+}
+
+impl HalfSpace {
+  fn of_row(row: [f32; 4]) -> HalfSpace {
+    HalfSpace {
+      normal: Vector3::new(row[0], row[1], row[2]),
+      distance: row[3],
+    }
+  }
+
+  fn signed_distance(&self, p: &Point3<f32>) -> f32 {
+    self.normal.dot(&p.to_vec()) + self.distance
+  }
+}
+
+/// The region of space that a projection matrix maps into clip space, i.e. what a camera can see.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct T {
+  half_spaces: [HalfSpace; 6],
+}
+
+fn row(m: &Matrix4<f32>, i: usize) -> [f32; 4] {
+  // cgmath matrices are stored as columns.
+  match i {
+    0 => [m.x.x, m.y.x, m.z.x, m.w.x],
+    1 => [m.x.y, m.y.y, m.z.y, m.w.y],
+    2 => [m.x.z, m.y.z, m.z.z, m.w.z],
+    _ => [m.x.w, m.y.w, m.z.w, m.w.w],
+  }
+}
+
+/// The frustum of a projection matrix like `Camera::projection_matrix`.
+pub fn of_projection(projection: &Matrix4<f32>) -> T {
+  // A point is visible when -w <= x, y, z <= w in clip space, which is one half space per bound.
+  let w = row(projection, 3);
+  let plus = |i| {
+    let r = row(projection, i);
+    HalfSpace::of_row([w[0] + r[0], w[1] + r[1], w[2] + r[2], w[3] + r[3]])
+  };
+  let minus = |i| {
+    let r = row(projection, i);
+    HalfSpace::of_row([w[0] - r[0], w[1] - r[1], w[2] - r[2], w[3] - r[3]])
+  };
+  T {
+    half_spaces: [plus(0), minus(0), plus(1), minus(1), plus(2), minus(2)],
+  }
+}
+
+impl T {
+  /// Whether any of `bounds` might be visible. This is conservative: boxes near the corners of
+  /// the frustum can be reported as visible when they aren't.
+  pub fn intersects(&self, bounds: &Aabb3<f32>) -> bool {
+    self.half_spaces.iter().all(|half_space| {
+      // The corner of the box farthest into the half space.
+      let corner =
+        Point3::new(
+          if half_space.normal.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+          if half_space.normal.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+          if half_space.normal.z >= 0.0 { bounds.max.z } else { bounds.min.z },
+        );
+      half_space.signed_distance(&corner) >= 0.0
+    })
+  }
+}
+
+#[cfg(test)]
+fn cube(x: f32, y: f32, z: f32) -> Aabb3<f32> {
+  Aabb3::new(Point3::new(x - 1.0, y - 1.0, z - 1.0), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+}
+
+#[cfg(test)]
+fn camera() -> ::camera::Camera {
+  let mut camera = ::camera::Camera::unit();
+  camera.fov = ::cgmath::perspective(::cgmath::rad(::std::f32::consts::PI / 2.0), 1.0, 0.1, 100.0);
+  camera
+}
+
+#[test]
+fn culls_boxes_outside_the_frustum() {
+  // Looking down -z with a 90 degree field of view.
+  let frustum = of_projection(&camera().projection_matrix());
+
+  assert!(frustum.intersects(&cube(0.0, 0.0, -10.0)));
+  assert!(frustum.intersects(&cube(8.0, -8.0, -10.0)));
+  // Straddling the near plane, and the edge of the view.
+  assert!(frustum.intersects(&cube(0.0, 0.0, 0.0)));
+  assert!(frustum.intersects(&cube(10.5, 0.0, -10.0)));
+
+  // Behind, beside, above and beyond the far plane.
+  assert!(!frustum.intersects(&cube(0.0, 0.0, 10.0)));
+  assert!(!frustum.intersects(&cube(20.0, 0.0, -10.0)));
+  assert!(!frustum.intersects(&cube(0.0, 20.0, -10.0)));
+  assert!(!frustum.intersects(&cube(0.0, 0.0, -200.0)));
+}
+
+#[test]
+fn follows_the_camera() {
+  let mut camera = camera();
+  camera.translate_to(Point3::new(100.0, 0.0, 0.0));
+  // Turn left, to look down -x.
+  camera.rotate_lateral(::std::f32::consts::PI / 2.0);
+  let frustum = of_projection(&camera.projection_matrix());
+
+  assert!(frustum.intersects(&cube(90.0, 0.0, 0.0)));
+  assert!(!frustum.intersects(&cube(110.0, 0.0, 0.0)));
+  assert!(!frustum.intersects(&cube(100.0, 0.0, -10.0)));
+  assert!(!frustum.intersects(&cube(50.0, 0.0, 60.0)));
+}
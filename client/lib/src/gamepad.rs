@@ -0,0 +1,165 @@
+//! Gamepad sticks: the left one walks and the right one looks around.
+//! Gamepad buttons go through `bindings` like keys do.
+//! With several gamepads plugged in, each stick follows whichever pad is pushing it furthest.
+
+use cgmath::{Vector, Vector2, Vector3};
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, GameController};
+use sdl2_sys;
+
+use common::fnv_map;
+
+use bindings;
+
+/// Raw axis values of one gamepad's sticks.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sticks {
+  left: (i16, i16),
+  right: (i16, i16),
+}
+
+/// The open gamepads, and where their sticks are.
+pub struct T {
+  /// Kept open so SDL sends their events. Keyed, like `sticks`, by joystick instance ID, which is
+  /// what SDL's controller events identify them by.
+  controllers: fnv_map::T<i32, GameController>,
+  sticks: fnv_map::T<i32, Sticks>,
+  /// The walk acceleration the left sticks have asked for so far.
+  walking: Vector3<f32>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    controllers: fnv_map::new(),
+    sticks: fnv_map::new(),
+    walking: Vector3::new(0.0, 0.0, 0.0),
+  }
+}
+
+fn instance_id(controller: &GameController) -> i32 {
+  unsafe {
+    let joystick = sdl2_sys::controller::SDL_GameControllerGetJoystick(controller.raw());
+    sdl2_sys::joystick::SDL_JoystickInstanceID(joystick)
+  }
+}
+
+/// The stick that's pushed furthest, breaking ties by instance ID so the choice is stable.
+fn furthest<Stick>(sticks: &fnv_map::T<i32, Sticks>, stick: Stick) -> (i16, i16) where
+  Stick: Fn(&Sticks) -> (i16, i16),
+{
+  let magnitude = |(x, y): (i16, i16)| (x as i32) * (x as i32) + (y as i32) * (y as i32);
+  let mut furthest = None;
+  for (&id, sticks) in sticks.iter() {
+    let s = stick(sticks);
+    let further =
+      match furthest {
+        None => true,
+        Some((furthest_id, f)) => (magnitude(s), -id) > (magnitude(f), -furthest_id),
+      };
+    if further {
+      furthest = Some((id, s));
+    }
+  }
+  furthest.map_or((0, 0), |(_, s)| s)
+}
+
+impl T {
+  /// Open a newly connected gamepad, `which` being its device index.
+  pub fn open(&mut self, subsystem: &GameControllerSubsystem, which: u32) {
+    match subsystem.open(which) {
+      Ok(controller) => {
+        info!("Opened gamepad {}", controller.name());
+        self.controllers.insert(instance_id(&controller), controller);
+      },
+      Err(err) => warn!("Couldn't open gamepad {}: {:?}", which, err),
+    }
+  }
+
+  /// Record a stick moving on the gamepad with instance ID `which`. Triggers are ignored.
+  pub fn axis(&mut self, which: i32, axis: Axis, value: i16) {
+    let sticks = self.sticks.entry(which).or_insert_with(Default::default);
+    match axis {
+      Axis::LeftX => sticks.left.0 = value,
+      Axis::LeftY => sticks.left.1 = value,
+      Axis::RightX => sticks.right.0 = value,
+      Axis::RightY => sticks.right.1 = value,
+      Axis::TriggerLeft | Axis::TriggerRight => {},
+    }
+  }
+
+  /// The gamepad with instance ID `which` was unplugged. Close it, and let go of its sticks so the
+  /// player doesn't keep walking.
+  pub fn disconnected(&mut self, which: i32) {
+    self.controllers.remove(&which);
+    self.sticks.remove(&which);
+  }
+
+  /// The change in walk acceleration to send, if the left sticks have moved since it was last sent.
+  /// Walking is additive, like holding several keys.
+  pub fn walk(&mut self, bindings: &bindings::T) -> Option<Vector3<f32>> {
+    let (x, y) = furthest(&self.sticks, |sticks| sticks.left);
+    let stick = bindings.stick(x, y);
+    // Up on the stick is forward, which is -z.
+    let walking = Vector3::new(stick.x, 0.0, -stick.y);
+    let delta = walking.sub_v(&self.walking);
+    if delta == Vector3::new(0.0, 0.0, 0.0) {
+      return None
+    }
+    self.walking = walking;
+    Some(delta)
+  }
+
+  /// How many radians to turn (laterally, then vertically) over `dt` seconds, if a right stick
+  /// is pushed.
+  pub fn look(&self, bindings: &bindings::T, dt: f32) -> Option<Vector2<f32>> {
+    let (x, y) = furthest(&self.sticks, |sticks| sticks.right);
+    let stick = bindings.stick(x, y);
+    if stick == Vector2::new(0.0, 0.0) {
+      return None
+    }
+    // Positive lateral rotation is to the left.
+    Some(Vector2::new(-stick.x, stick.y).mul_s(bindings.stick_look_speed * dt))
+  }
+}
+
+#[test]
+fn sticks_walk_and_look() {
+  let bindings = bindings::new();
+  let mut gamepad = new();
+  assert_eq!(gamepad.walk(&bindings), None);
+  assert_eq!(gamepad.look(&bindings, 0.5), None);
+
+  // Forward, all the way.
+  gamepad.axis(0, Axis::LeftY, -32767);
+  assert_eq!(gamepad.walk(&bindings), Some(Vector3::new(0.0, 0.0, -1.0)));
+  assert_eq!(gamepad.walk(&bindings), None);
+
+  // Right, all the way: stop going forward and start going right.
+  gamepad.axis(0, Axis::LeftY, 0);
+  gamepad.axis(0, Axis::LeftX, 32767);
+  assert_eq!(gamepad.walk(&bindings), Some(Vector3::new(1.0, 0.0, 1.0)));
+
+  gamepad.disconnected(0);
+  assert_eq!(gamepad.walk(&bindings), Some(Vector3::new(-1.0, 0.0, 0.0)));
+
+  gamepad.axis(0, Axis::RightX, 32767);
+  assert_eq!(gamepad.look(&bindings, 0.5), Some(Vector2::new(-bindings.stick_look_speed * 0.5, 0.0)));
+}
+
+#[test]
+fn unplugging_one_pad_leaves_the_others_alone() {
+  let bindings = bindings::new();
+  let mut gamepad = new();
+
+  // Pad 1 is plugged in but idle; pad 2 walks forward.
+  gamepad.axis(1, Axis::LeftY, 0);
+  gamepad.axis(2, Axis::LeftY, -32767);
+  assert_eq!(gamepad.walk(&bindings), Some(Vector3::new(0.0, 0.0, -1.0)));
+
+  gamepad.disconnected(1);
+  assert_eq!(gamepad.walk(&bindings), None);
+
+  gamepad.disconnected(2);
+  assert_eq!(gamepad.walk(&bindings), Some(Vector3::new(0.0, 0.0, 1.0)));
+}
@@ -10,6 +10,7 @@ use client;
 use lod;
 use terrain_mesh;
 use view_update::ClientToView;
+use voxel_cache;
 
 #[inline(never)]
 fn updated_block_positions(
@@ -61,6 +62,7 @@ fn updated_block_positions(
 }
 
 pub fn all_voxels_loaded(
+  resolutions: &terrain_mesh::Resolutions,
   block_voxels_loaded: &block_position::with_lod::map::T<u32>,
   block_position: block_position::T,
   lod: lod::T,
@@ -71,7 +73,7 @@ pub fn all_voxels_loaded(
       Some(x) => x,
     };
 
-  let edge_samples = terrain_mesh::EDGE_SAMPLES[lod.0 as usize] as u32 + 2;
+  let edge_samples = resolutions.edge_samples(lod) as u32 + 2;
   let samples = edge_samples * edge_samples * edge_samples;
   assert!(*block_voxels_loaded <= samples, "{:?}", block_position);
   *block_voxels_loaded == samples
@@ -88,6 +90,7 @@ pub fn load_voxel<UpdateBlock>(
 {
   let player_position =
     block_position::of_world_position(&client.player_position.lock().unwrap());
+  let max_load_distance = *client.max_load_distance.lock().unwrap();
 
   let mut voxels = client.voxels.lock().unwrap();
   let mut block_voxels_loaded = client.block_voxels_loaded.lock().unwrap();
@@ -105,20 +108,15 @@ pub fn load_voxel<UpdateBlock>(
     *old_voxel = voxel;
   }
 
+  if new_voxel_loaded {
+    client.voxel_cache.lock().unwrap().insert(bounds);
+  }
+
   trace!("voxel bounds {:?}", bounds);
 
   // The LOD of the blocks that should be updated.
   // This doesn't necessarily match the LOD they're loaded at.
-  let mut updated_lod = None;
-  for lod in 0..terrain_mesh::LOD_COUNT as u32 {
-    let lod = lod::T(lod);
-
-    let lg_size = terrain_mesh::LG_SAMPLE_SIZE[lod.0 as usize];
-    if lg_size == bounds.lg_size {
-      updated_lod = Some(lod);
-      break
-    }
-  }
+  let updated_lod = client.settings.resolutions.lod_of_lg_sample_size(bounds.lg_size);
 
   for block_position in updated_block_positions(&bounds).into_iter() {
     trace!("block_position {:?}", block_position);
@@ -137,7 +135,7 @@ pub fn load_voxel<UpdateBlock>(
 
     let distance = surroundings_loader::distance_between(player_position.as_pnt(), &block_position.as_pnt());
 
-    if distance > client.max_load_distance {
+    if distance > max_load_distance {
       debug!(
         "Not loading {:?}: too far away from player at {:?}.",
         bounds,
@@ -146,8 +144,8 @@ pub fn load_voxel<UpdateBlock>(
       continue;
     }
 
-    let lod = lod_index(distance);
-    let lg_size = terrain_mesh::LG_SAMPLE_SIZE[lod.0 as usize];
+    let lod = lod_index(&client.settings.lod_thresholds, distance);
+    let lg_size = client.settings.resolutions.lg_sample_size(lod);
     if lg_size != bounds.lg_size {
       debug!(
         "{:?} is not the desired LOD {:?}.",
@@ -157,62 +155,225 @@ pub fn load_voxel<UpdateBlock>(
       continue;
     }
 
-    if all_voxels_loaded(&block_voxels_loaded, block_position, lod) {
+    if all_voxels_loaded(&client.settings.resolutions, &block_voxels_loaded, block_position, lod) {
       update_block(block_position, lod);
     }
   }
 }
 
+/// The LODs to mesh a block's seams against: those of its loaded lower neighbours, as long as we
+/// still have their voxels, and otherwise the block's own.
+fn neighbour_lods(
+  client: &client::T,
+  block_position: &block_position::T,
+  lod: lod::T,
+) -> terrain_mesh::NeighbourLods {
+  let loaded_blocks = client.loaded_blocks.lock().unwrap();
+  let block_voxels_loaded = client.block_voxels_loaded.lock().unwrap();
+  let mut neighbour_lods = terrain_mesh::uniform_lods(lod);
+  for (i, neighbour_lod) in neighbour_lods.iter_mut().enumerate() {
+    let neighbour = terrain_mesh::lower_neighbour(block_position, i);
+    if let Some(&(_, loaded_lod)) = loaded_blocks.get(&neighbour) {
+      if all_voxels_loaded(&client.settings.resolutions, &block_voxels_loaded, neighbour, loaded_lod) {
+        *neighbour_lod = loaded_lod;
+      }
+    }
+  }
+  neighbour_lods
+}
+
+/// Queue a block to be meshed by the mesh pool. Once it's done, `finish_block` loads it.
+#[inline(never)]
+pub fn load_block(
+  client: &client::T,
+  block_position: &block_position::T,
+  lod: lod::T,
+) {
+  debug!("generate {:?} at {:?}", block_position, lod);
+  let player_position =
+    block_position::of_world_position(&client.player_position.lock().unwrap());
+  let distance = surroundings_loader::distance_between(player_position.as_pnt(), block_position.as_pnt());
+  let neighbour_lods = neighbour_lods(client, block_position, lod);
+  let prev =
+    match client.loaded_blocks.lock().unwrap().get(block_position) {
+      None => terrain_mesh::empty(),
+      Some(&(ref mesh, _)) => mesh.clone(),
+    };
+  let resolutions = &client.settings.resolutions;
+  let save = client.disk_cache.lock().unwrap().save(resolutions, block_position, lod);
+  let voxels = client.voxels.lock().unwrap();
+  let grass_rules =
+    terrain_mesh::GrassRules {
+      density: client.settings.grass_density,
+      seed: *client.grass_seed.lock().unwrap(),
+    };
+  client.mesh_pool.push(&voxels, resolutions, block_position, lod, &neighbour_lods, &prev, &grass_rules, save, distance);
+}
+
+/// Remesh a loaded block if its seams no longer match its neighbours' LODs.
+fn remesh_seams(
+  client: &client::T,
+  block_position: &block_position::T,
+) {
+  if client.mesh_pool.is_meshing(block_position) {
+    // It'll be checked again once it's done.
+    return
+  }
+  let lod =
+    match client.loaded_blocks.lock().unwrap().get(block_position) {
+      None => return,
+      Some(&(_, lod)) => lod,
+    };
+  {
+    let block_voxels_loaded = client.block_voxels_loaded.lock().unwrap();
+    if !all_voxels_loaded(&client.settings.resolutions, &block_voxels_loaded, *block_position, lod) {
+      return
+    }
+  }
+  let neighbour_lods = neighbour_lods(client, block_position, lod);
+  if client.seam_lods.lock().unwrap().get(block_position) != Some(&neighbour_lods) {
+    load_block(client, block_position, lod);
+  }
+}
+
+/// Load a block that the mesh pool has finished meshing.
 #[inline(never)]
-pub fn load_block<UpdateView>(
+pub fn finish_block<UpdateView>(
   client: &client::T,
   update_view: &mut UpdateView,
   block_position: &block_position::T,
   lod: lod::T,
+  neighbour_lods: &terrain_mesh::NeighbourLods,
+  mesh_block: terrain_mesh::T,
 ) where
   UpdateView: FnMut(ClientToView),
 {
-  debug!("generate {:?} at {:?}", block_position, lod);
-  let voxels = client.voxels.lock().unwrap();
-  let mut rng = client.rng.lock().unwrap();
-  let mesh_block = terrain_mesh::generate(&voxels, &block_position, lod, &client.id_allocator, &mut *rng);
+  client.seam_lods.lock().unwrap().insert(*block_position, *neighbour_lods);
 
   let mut updates = Vec::new();
 
-  // TODO: Rc instead of clone.
-  match client.loaded_blocks.lock().unwrap().entry(*block_position) {
-    Vacant(entry) => {
-      entry.insert((mesh_block.clone(), lod));
-    },
-    Occupied(mut entry) => {
-      {
+  // Only swap out the triangles and grass that actually changed, so edits don't churn the whole
+  // block. Unchanged triangles kept their IDs when the block was meshed from its loaded mesh.
+  let added =
+    match client.loaded_blocks.lock().unwrap().entry(*block_position) {
+      Vacant(entry) => {
+        entry.insert((mesh_block.clone(), lod));
+        mesh_block
+      },
+      Occupied(mut entry) => {
+        let changes = terrain_mesh::changes(&entry.get().0, &mesh_block);
         // The mesh_block removal code is duplicated in update_thread.
-
-        let &(ref prev_block, _) = entry.get();
-        for id in &prev_block.grass_ids {
-          updates.push(ClientToView::RemoveGrass(*id));
+        for &id in &changes.removed_grass_ids {
+          updates.push(ClientToView::RemoveGrass(id));
         }
-        for &id in &prev_block.ids {
+        for &id in &changes.removed_ids {
           updates.push(ClientToView::RemoveTerrain(id));
         }
-      }
-      entry.insert((mesh_block.clone(), lod));
-    },
-  };
+        entry.insert((mesh_block, lod));
+        changes.added
+      },
+    };
 
-  if !mesh_block.ids.is_empty() {
-    updates.push(ClientToView::AddBlock(*block_position, mesh_block, lod));
+  if !added.ids.is_empty() {
+    updates.push(ClientToView::AddBlock(*block_position, added, lod));
   }
 
   update_view(ClientToView::Atomic(updates));
+
+  // Blocks own the seams on their lower faces, so this block's seams might have been meshed
+  // against stale neighbours, and its upper neighbours' seams might have been meshed against it.
+  remesh_seams(client, block_position);
+  for i in 0 .. terrain_mesh::LOWER_NEIGHBOURS.len() {
+    remesh_seams(client, &terrain_mesh::upper_neighbour(block_position, i));
+  }
+}
+
+/// Evict cached voxels that are out of range, or over budget, along with their bookkeeping.
+#[inline(never)]
+pub fn evict_voxels(client: &client::T) {
+  let center = {
+    let load_position = *client.load_position.lock().unwrap();
+    let load_position = load_position.unwrap_or_else(|| *client.player_position.lock().unwrap());
+    block_position::of_world_position(&load_position)
+  };
+
+  let max_load_distance = *client.max_load_distance.lock().unwrap();
+
+  // Loaded blocks need their own voxels and their neighbours' to stay fully loaded, or they'd
+  // never be remeshed again. Taken first, since `neighbour_lods` locks `loaded_blocks` before
+  // `block_voxels_loaded`.
+  let mut in_use = block_position::set::new();
+  for block in client.loaded_blocks.lock().unwrap().keys() {
+    let p = block.as_pnt();
+    for dx in -1 .. 2 {
+    for dy in -1 .. 2 {
+    for dz in -1 .. 2 {
+      in_use.insert(block_position::new(p.x + dx, p.y + dy, p.z + dz));
+    }}}
+  }
+
+  let mut voxels = client.voxels.lock().unwrap();
+  let mut block_voxels_loaded = client.block_voxels_loaded.lock().unwrap();
+  let mut voxel_cache = client.voxel_cache.lock().unwrap();
+
+  let blocks =
+    voxel_cache.blocks_to_evict(
+      &center,
+      max_load_distance,
+      voxel_cache::MEMORY_BUDGET,
+      |block| in_use.contains(block),
+    );
+  for block in &blocks {
+    for bounds in voxel_cache.remove_block(block) {
+      voxels.get_mut_or_create(&bounds).force_branches().data = None;
+
+      // Undo the counting that load_voxel did.
+      let lod =
+        match client.settings.resolutions.lod_of_lg_sample_size(bounds.lg_size) {
+          None => continue,
+          Some(lod) => lod,
+        };
+      for block_position in updated_block_positions(&bounds) {
+        let remove =
+          match block_voxels_loaded.get_mut(&(block_position, lod)) {
+            None => false,
+            Some(count) => {
+              *count -= 1;
+              *count == 0
+            },
+          };
+        if remove {
+          block_voxels_loaded.remove(&(block_position, lod));
+        }
+      }
+    }
+  }
+
+  if voxel_cache.should_compact() {
+    // Setting voxels to None doesn't free the tree nodes they were in, so start a fresh tree.
+    let mut compacted = voxel::tree::new();
+    for bounds in voxel_cache.compact() {
+      let voxel = *voxels.get(&bounds).unwrap();
+      compacted.get_mut_or_create(&bounds).force_branches().data = Some(voxel);
+    }
+    *voxels = compacted;
+  }
+
+  if !blocks.is_empty() {
+    let stats = voxel_cache.stats();
+    debug!(
+      "Evicted {} blocks; {} voxels in {} blocks (~{} MiB) remain",
+      blocks.len(), stats.voxels, stats.blocks, stats.bytes() >> 20,
+    );
+  }
 }
 
-pub fn lod_index(distance: i32) -> lod::T {
+pub fn lod_index(lod_thresholds: &[i32], distance: i32) -> lod::T {
   assert!(distance >= 0);
   let mut lod = 0;
   while
-    lod < client::LOD_THRESHOLDS.len()
-    && client::LOD_THRESHOLDS[lod] < distance
+    lod < lod_thresholds.len()
+    && lod_thresholds[lod] < distance
   {
     lod += 1;
   }
@@ -0,0 +1,187 @@
+//! Write meshed terrain out as OBJ or PLY, for debugging generation and for external renders.
+
+use cgmath::{Aabb3, Point3, Vector3};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use common::id_allocator;
+use common::voxel;
+
+use block_position;
+use lod;
+use terrain_mesh;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Format {
+  Obj,
+  Ply,
+}
+
+impl Format {
+  /// Guess the format from a file extension.
+  pub fn of_path(path: &Path) -> Option<Format> {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("obj") => Some(Format::Obj),
+      Some("ply") => Some(Format::Ply),
+      _ => None,
+    }
+  }
+}
+
+/// The number of LODs that can be exported.
+pub const LOD_COUNT: u32 = terrain_mesh::LOD_COUNT as u32;
+
+// Material ids in meshes are `voxel::Material as i32`.
+fn material_name(id: i32) -> String {
+  match voxel::Material::of_id(id) {
+    Some(material) => String::from(material.name()),
+    None => format!("Material{}", id),
+  }
+}
+
+/// The blocks overlapping some world-space bounds.
+fn blocks_in(bounds: &Aabb3<i32>) -> Vec<block_position::T> {
+  let width = terrain_mesh::WIDTH;
+  let div_floor = |x: i32| if x < 0 { (x - width + 1) / width } else { x / width };
+  let div_ceil = |x: i32| div_floor(x + width - 1);
+
+  let mut blocks = Vec::new();
+  for x in div_floor(bounds.min.x) .. div_ceil(bounds.max.x) {
+  for y in div_floor(bounds.min.y) .. div_ceil(bounds.max.y) {
+  for z in div_floor(bounds.min.z) .. div_ceil(bounds.max.z) {
+    blocks.push(block_position::new(x, y, z));
+  }}}
+  blocks
+}
+
+/// The voxels that `write` needs loaded to mesh the blocks overlapping `bounds` at an LOD.
+pub fn voxels_needed(bounds: &Aabb3<i32>, lod: u32) -> Vec<voxel::bounds::T> {
+  let mut voxels = Vec::new();
+  for block in blocks_in(bounds) {
+    voxels.extend(terrain_mesh::voxels_for_block(&Default::default(), &block, lod::T(lod)));
+  }
+  voxels
+}
+
+struct Triangle {
+  material: i32,
+  vertices: [Point3<f32>; 3],
+  normals: [Vector3<f32>; 3],
+}
+
+/// Mesh the blocks overlapping `bounds` at an LOD, sorted by material.
+fn triangles(voxels: &voxel::tree::T, bounds: &Aabb3<i32>, lod: u32) -> Vec<Triangle> {
+  let id_allocator = Mutex::new(id_allocator::new());
+
+  let mut triangles = Vec::new();
+  for block in blocks_in(bounds) {
+    let mesh = terrain_mesh::generate(
+        voxels,
+        &Default::default(),
+        &block,
+        lod::T(lod),
+        &terrain_mesh::uniform_lods(lod::T(lod)),
+        &terrain_mesh::empty(),
+        &id_allocator,
+        &Default::default(),
+      );
+    for i in 0 .. mesh.materials.len() {
+      let v = &mesh.vertex_coordinates[i];
+      let n = &mesh.normals[i];
+      triangles.push(Triangle {
+        material: mesh.materials[i],
+        vertices: [v.v1, v.v2, v.v3],
+        normals: [n.v1, n.v2, n.v3],
+      });
+    }
+  }
+
+  triangles.sort_by(|x, y| x.material.cmp(&y.material));
+  triangles
+}
+
+fn write_obj<W: Write>(w: &mut W, triangles: &[Triangle]) -> io::Result<()> {
+  try!(writeln!(w, "# Playform terrain export"));
+  for triangle in triangles {
+    for v in &triangle.vertices {
+      try!(writeln!(w, "v {} {} {}", v.x, v.y, v.z));
+    }
+    for n in &triangle.normals {
+      try!(writeln!(w, "vn {} {} {}", n.x, n.y, n.z));
+    }
+  }
+
+  let mut material = None;
+  for (i, triangle) in triangles.iter().enumerate() {
+    if material != Some(triangle.material) {
+      material = Some(triangle.material);
+      let name = material_name(triangle.material);
+      try!(writeln!(w, "g {}", name));
+      try!(writeln!(w, "usemtl {}", name));
+    }
+    // OBJ indices start at 1.
+    let first = 3 * i + 1;
+    try!(writeln!(w, "f {0}//{0} {1}//{1} {2}//{2}", first, first + 1, first + 2));
+  }
+  Ok(())
+}
+
+fn write_ply<W: Write>(w: &mut W, triangles: &[Triangle]) -> io::Result<()> {
+  try!(writeln!(w, "ply"));
+  try!(writeln!(w, "format ascii 1.0"));
+  try!(writeln!(w, "comment Playform terrain export"));
+  let mut material = None;
+  for triangle in triangles {
+    if material != Some(triangle.material) {
+      material = Some(triangle.material);
+      try!(writeln!(w, "comment material {} {}", triangle.material, material_name(triangle.material)));
+    }
+  }
+  try!(writeln!(w, "element vertex {}", 3 * triangles.len()));
+  for property in &["x", "y", "z", "nx", "ny", "nz"] {
+    try!(writeln!(w, "property float {}", property));
+  }
+  try!(writeln!(w, "element face {}", triangles.len()));
+  try!(writeln!(w, "property list uchar int vertex_indices"));
+  try!(writeln!(w, "property uchar material"));
+  try!(writeln!(w, "end_header"));
+
+  for triangle in triangles {
+    for (v, n) in triangle.vertices.iter().zip(triangle.normals.iter()) {
+      try!(writeln!(w, "{} {} {} {} {} {}", v.x, v.y, v.z, n.x, n.y, n.z));
+    }
+  }
+  for (i, triangle) in triangles.iter().enumerate() {
+    try!(writeln!(w, "3 {} {} {} {}", 3 * i, 3 * i + 1, 3 * i + 2, triangle.material));
+  }
+  Ok(())
+}
+
+/// Mesh the blocks overlapping `bounds` at an LOD, and write them out grouped by material.
+/// All of `voxels_needed(bounds, lod)` must be loaded into `voxels`.
+pub fn write<W: Write>(
+  w: &mut W,
+  format: Format,
+  voxels: &voxel::tree::T,
+  bounds: &Aabb3<i32>,
+  lod: u32,
+) -> io::Result<()> {
+  assert!(lod < LOD_COUNT);
+  let triangles = triangles(voxels, bounds, lod);
+  match format {
+    Format::Obj => write_obj(w, &triangles),
+    Format::Ply => write_ply(w, &triangles),
+  }
+}
+
+#[test]
+fn blocks_in_rounds_outward() {
+  let width = terrain_mesh::WIDTH;
+  let blocks = blocks_in(&Aabb3::new(Point3::new(-1, 0, 0), Point3::new(width + 1, 1, width)));
+  assert_eq!(blocks.len(), 3);
+  assert_eq!(blocks[0], block_position::new(-1, 0, 0));
+  assert_eq!(blocks[2], block_position::new(1, 0, 0));
+}
@@ -0,0 +1,291 @@
+//! A pool of worker threads that mesh terrain blocks, so the update thread doesn't have to.
+//! Blocks are meshed from snapshots of their voxels, nearest blocks first, and results for
+//! blocks that were unloaded or re-requested in the meantime are thrown away.
+//! The workers also save blocks' voxels to the disk cache, from the same snapshots.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use stopwatch;
+use time;
+
+use common::entity_id;
+use common::id_allocator;
+use common::voxel;
+
+use block_position;
+use client;
+use disk_cache;
+use lod;
+use metrics;
+use terrain_mesh;
+
+/// The number of meshing threads to run.
+pub const WORKER_COUNT: usize = 3;
+
+/// How long idle workers wait before checking whether they should quit.
+const IDLE_WAIT_MS: u64 = 100;
+
+struct Job {
+  block_position: block_position::T,
+  lod: lod::T,
+  neighbour_lods: terrain_mesh::NeighbourLods,
+  version: u64,
+  voxels: voxel::tree::T,
+  resolutions: terrain_mesh::Resolutions,
+  /// The block's current mesh, whose unchanged triangles keep their IDs.
+  prev: terrain_mesh::T,
+  grass_rules: terrain_mesh::GrassRules,
+  save: Option<disk_cache::Save>,
+}
+
+struct Meshed {
+  block_position: block_position::T,
+  lod: lod::T,
+  neighbour_lods: terrain_mesh::NeighbourLods,
+  version: u64,
+  mesh: terrain_mesh::T,
+}
+
+/// Order things so that the nearest ones come out of a `BinaryHeap` first.
+struct Nearest<X> {
+  distance: i32,
+  x: X,
+}
+
+impl<X> PartialEq for Nearest<X> {
+  fn eq(&self, other: &Nearest<X>) -> bool {
+    self.distance == other.distance
+  }
+}
+
+impl<X> Eq for Nearest<X> {}
+
+impl<X> PartialOrd for Nearest<X> {
+  fn partial_cmp(&self, other: &Nearest<X>) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<X> Ord for Nearest<X> {
+  fn cmp(&self, other: &Nearest<X>) -> Ordering {
+    // BinaryHeap pops the greatest element first.
+    other.distance.cmp(&self.distance)
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  jobs: Mutex<BinaryHeap<Nearest<Job>>>,
+  job_added: Condvar,
+  meshed: Mutex<BinaryHeap<Nearest<Meshed>>>,
+  /// The version of the latest request for each block with meshing in progress.
+  /// Anything meshed for an older version is stale.
+  latest: Mutex<block_position::map::T<u64>>,
+  next_version: Mutex<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    jobs: Mutex::new(BinaryHeap::new()),
+    job_added: Condvar::new(),
+    meshed: Mutex::new(BinaryHeap::new()),
+    latest: Mutex::new(block_position::map::new()),
+    next_version: Mutex::new(0),
+  }
+}
+
+impl T {
+  /// Queue a block to be meshed at an LOD, superseding any earlier requests for it, and its
+  /// voxels to be saved if `save` is given. `prev` is the block's current mesh, if any.
+  /// See `terrain_mesh::generate`.
+  /// All of `terrain_mesh::voxels_for_mesh(resolutions, block_position, lod, neighbour_lods)`
+  /// must be in `voxels`.
+  pub fn push(
+    &self,
+    voxels: &voxel::tree::T,
+    resolutions: &terrain_mesh::Resolutions,
+    block_position: &block_position::T,
+    lod: lod::T,
+    neighbour_lods: &terrain_mesh::NeighbourLods,
+    prev: &terrain_mesh::T,
+    grass_rules: &terrain_mesh::GrassRules,
+    save: Option<disk_cache::Save>,
+    distance: i32,
+  ) {
+    let mut snapshot = voxel::tree::new();
+    for bounds in terrain_mesh::voxels_for_mesh(resolutions, block_position, lod, neighbour_lods) {
+      let voxel = *voxels.get(&bounds).unwrap_or_else(|| panic!("No voxel at {:?}", bounds));
+      snapshot.get_mut_or_create(&bounds).force_branches().data = Some(voxel);
+    }
+
+    let version = {
+      let mut next_version = self.next_version.lock().unwrap();
+      let version = *next_version;
+      *next_version += 1;
+      version
+    };
+    self.latest.lock().unwrap().insert(*block_position, version);
+
+    let job =
+      Job {
+        block_position: *block_position,
+        lod: lod,
+        neighbour_lods: *neighbour_lods,
+        version: version,
+        voxels: snapshot,
+        resolutions: *resolutions,
+        prev: prev.clone(),
+        grass_rules: *grass_rules,
+        save: save,
+      };
+    self.jobs.lock().unwrap().push(Nearest { distance: distance, x: job });
+    self.job_added.notify_one();
+  }
+
+  /// Forget about any meshing in progress for a block, e.g. because it's been unloaded.
+  pub fn cancel(&self, block_position: &block_position::T) {
+    self.latest.lock().unwrap().remove(block_position);
+  }
+
+  /// Forget about any meshing in progress for the blocks that don't satisfy `keep`.
+  pub fn retain<Keep: FnMut(&block_position::T) -> bool>(&self, mut keep: Keep) {
+    let mut latest = self.latest.lock().unwrap();
+    let cancelled: Vec<block_position::T> = latest.keys().filter(|b| !keep(*b)).cloned().collect();
+    for block_position in &cancelled {
+      latest.remove(block_position);
+    }
+  }
+
+  /// Whether a block is waiting to be meshed, or to be loaded once it has been.
+  pub fn is_meshing(&self, block_position: &block_position::T) -> bool {
+    self.latest.lock().unwrap().contains_key(block_position)
+  }
+
+  fn is_latest(&self, block_position: &block_position::T, version: u64) -> bool {
+    self.latest.lock().unwrap().get(block_position) == Some(&version)
+  }
+
+  /// Wait a little while for a job, and do it if there is one.
+  /// Returns whether any work was done.
+  pub fn work(
+    &self,
+    id_allocator: &Mutex<id_allocator::T<entity_id::T>>,
+    disk_cache: &Mutex<disk_cache::T>,
+    metrics: &metrics::T,
+  ) -> bool {
+    let Nearest { distance, x: mut job } = {
+      let mut jobs = self.jobs.lock().unwrap();
+      if jobs.is_empty() {
+        jobs = self.job_added.wait_timeout(jobs, Duration::from_millis(IDLE_WAIT_MS)).unwrap().0;
+      }
+      match jobs.pop() {
+        None => return false,
+        Some(job) => job,
+      }
+    };
+
+    // Stale or not, these voxels were current when the save was started.
+    if let Some(save) = job.save.take() {
+      stopwatch::time("mesh_pool.save", || {
+        save.write(&job.voxels, disk_cache);
+      });
+    }
+
+    if !self.is_latest(&job.block_position, job.version) {
+      debug!("Skipping stale mesh job for {:?}", job.block_position);
+      return true
+    }
+
+    let start = time::precise_time_ns();
+    let mesh =
+      terrain_mesh::generate(
+        &job.voxels,
+        &job.resolutions,
+        &job.block_position,
+        job.lod,
+        &job.neighbour_lods,
+        &job.prev,
+        id_allocator,
+        &job.grass_rules,
+      );
+    metrics.record_meshing(time::precise_time_ns() - start);
+    let meshed =
+      Meshed {
+        block_position: job.block_position,
+        lod: job.lod,
+        neighbour_lods: job.neighbour_lods,
+        version: job.version,
+        mesh: mesh,
+      };
+    self.meshed.lock().unwrap().push(Nearest { distance: distance, x: meshed });
+    true
+  }
+
+  /// Take the nearest finished mesh that's still wanted, along with the neighbour LODs it was meshed with.
+  pub fn pop(&self) -> Option<(block_position::T, lod::T, terrain_mesh::NeighbourLods, terrain_mesh::T)> {
+    loop {
+      let meshed =
+        match self.meshed.lock().unwrap().pop() {
+          None => return None,
+          Some(Nearest { x, .. }) => x,
+        };
+
+      let mut latest = self.latest.lock().unwrap();
+      if latest.get(&meshed.block_position) == Some(&meshed.version) {
+        latest.remove(&meshed.block_position);
+        return Some((meshed.block_position, meshed.lod, meshed.neighbour_lods, meshed.mesh))
+      }
+      debug!("Discarding stale mesh for {:?} at {:?}", meshed.block_position, meshed.lod);
+    }
+  }
+}
+
+/// Mesh blocks queued in the client's pool until we're told to quit.
+pub fn worker_thread(quit: &Mutex<bool>, client: &client::T) {
+  while !*quit.lock().unwrap() {
+    stopwatch::time("mesh_pool.work", || {
+      client.mesh_pool.work(&client.id_allocator, &client.disk_cache, &client.metrics);
+    });
+  }
+}
+
+#[cfg(test)]
+fn empty_voxels(blocks: &[(block_position::T, lod::T)]) -> voxel::tree::T {
+  let mut voxels = voxel::tree::new();
+  for &(block, lod) in blocks {
+    for bounds in terrain_mesh::voxels_for_block(&Default::default(), &block, lod) {
+      voxels.get_mut_or_create(&bounds).force_branches().data = Some(voxel::Volume(voxel::Material::Empty));
+    }
+  }
+  voxels
+}
+
+#[test]
+fn nearest_blocks_come_first_and_stale_ones_are_dropped() {
+  let near = block_position::new(0, 0, 0);
+  let far = block_position::new(4, 0, 0);
+  let voxels = empty_voxels(&[(near, lod::T(0)), (near, lod::T(1)), (far, lod::T(1))]);
+  let id_allocator = Mutex::new(id_allocator::new());
+  let disk_cache = Mutex::new(disk_cache::new());
+  let metrics = metrics::new();
+
+  let resolutions: terrain_mesh::Resolutions = Default::default();
+  let pool = new();
+  pool.push(&voxels, &resolutions, &far, lod::T(1), &terrain_mesh::uniform_lods(lod::T(1)), &terrain_mesh::empty(), &Default::default(), None, 4);
+  pool.push(&voxels, &resolutions, &near, lod::T(1), &terrain_mesh::uniform_lods(lod::T(1)), &terrain_mesh::empty(), &Default::default(), None, 0);
+  // Supersedes the request before it.
+  pool.push(&voxels, &resolutions, &near, lod::T(0), &terrain_mesh::uniform_lods(lod::T(0)), &terrain_mesh::empty(), &Default::default(), None, 0);
+  while pool.work(&id_allocator, &disk_cache, &metrics) {}
+
+  assert_eq!(pool.pop().map(|(b, l, _, _)| (b, l)), Some((near, lod::T(0))));
+  assert_eq!(pool.pop().map(|(b, l, _, _)| (b, l)), Some((far, lod::T(1))));
+  assert!(pool.pop().is_none());
+
+  pool.push(&voxels, &resolutions, &far, lod::T(1), &terrain_mesh::uniform_lods(lod::T(1)), &terrain_mesh::empty(), &Default::default(), None, 4);
+  pool.cancel(&far);
+  while pool.work(&id_allocator, &disk_cache, &metrics) {}
+  assert!(pool.pop().is_none());
+}
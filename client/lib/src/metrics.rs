@@ -0,0 +1,251 @@
+//! Client performance metrics: how long terrain takes to load and mesh, how much voxel data
+//! arrives, how backed up the queues between threads are, and how long frames take.
+//! They can be written out as JSON or CSV, or served live in the Prometheus text format.
+
+use rustc_serialize::json;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use common::prometheus;
+use common::protocol;
+use common::samples;
+
+pub use common::samples::Summary;
+
+/// How many updates are waiting in each of the queues between threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, RustcEncodable)]
+#[allow(missing_docs)]
+pub struct QueueDepths {
+  pub voxel_updates: usize,
+  pub view_updates0: usize,
+  pub view_updates1: usize,
+  pub audio_updates: usize,
+}
+
+/// The last ping sent to the server.
+struct Ping {
+  sent_at: Option<u64>,
+  answered: bool,
+  round_trip_ns: Option<u64>,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  block_loads: Mutex<samples::T>,
+  meshing: Mutex<samples::T>,
+  frames: Mutex<samples::T>,
+  voxel_bytes_received: Mutex<u64>,
+  queue_depths: Mutex<QueueDepths>,
+  ping: Mutex<Ping>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    block_loads: Mutex::new(samples::new()),
+    meshing: Mutex::new(samples::new()),
+    frames: Mutex::new(samples::new()),
+    voxel_bytes_received: Mutex::new(0),
+    queue_depths: Mutex::new(Default::default()),
+    ping: Mutex::new(Ping { sent_at: None, answered: false, round_trip_ns: None }),
+  }
+}
+
+impl T {
+  /// Record the time between requesting some voxels and loading them.
+  pub fn record_block_load(&self, requested_at: u64, loaded_at: u64) {
+    self.block_loads.lock().unwrap().push(loaded_at.saturating_sub(requested_at));
+  }
+
+  /// Record how long meshing a block took.
+  pub fn record_meshing(&self, ns: u64) {
+    self.meshing.lock().unwrap().push(ns);
+  }
+
+  /// Record the time between a frame and the one before it.
+  pub fn record_frame(&self, ns: u64) {
+    self.frames.lock().unwrap().push(ns);
+  }
+
+  /// Record a message from the server, `bytes` long on the wire.
+  pub fn record_received(&self, message: &protocol::ServerToClient, bytes: usize) {
+    if let protocol::ServerToClient::Voxels(..) = *message {
+      *self.voxel_bytes_received.lock().unwrap() += bytes as u64;
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn set_queue_depths(&self, queue_depths: QueueDepths) {
+    *self.queue_depths.lock().unwrap() = queue_depths;
+  }
+
+  /// Whether it's been at least `interval` nanoseconds since the last ping was sent at `now`.
+  /// If so, a ping is taken to be sent now.
+  pub fn start_ping(&self, now: u64, interval: u64) -> bool {
+    let mut ping = self.ping.lock().unwrap();
+    let due =
+      match ping.sent_at {
+        None => true,
+        Some(sent_at) => now - sent_at >= interval,
+      };
+    if due {
+      ping.sent_at = Some(now);
+      ping.answered = false;
+    }
+    due
+  }
+
+  /// Record the server answering the last ping at `now`.
+  pub fn record_pong(&self, now: u64) {
+    let mut ping = self.ping.lock().unwrap();
+    if let (Some(sent_at), false) = (ping.sent_at, ping.answered) {
+      ping.round_trip_ns = Some(now - sent_at);
+      ping.answered = true;
+    }
+  }
+
+  /// The metrics as they are now.
+  pub fn snapshot(&self) -> Snapshot {
+    Snapshot {
+      block_load_ms: self.block_loads.lock().unwrap().summary(),
+      meshing_ms: self.meshing.lock().unwrap().summary(),
+      frame_ms: self.frames.lock().unwrap().summary(),
+      voxel_bytes_received: *self.voxel_bytes_received.lock().unwrap(),
+      queue_depths: *self.queue_depths.lock().unwrap(),
+      ping_ms: self.ping.lock().unwrap().round_trip_ns.map(|ns| ns as f64 / 1_000_000.0),
+    }
+  }
+}
+
+/// The metrics at some point in time.
+#[derive(Debug, Clone, RustcEncodable)]
+#[allow(missing_docs)]
+pub struct Snapshot {
+  /// From requesting voxels to loading them.
+  pub block_load_ms: Option<Summary>,
+  /// Meshing a block, on a mesh worker.
+  pub meshing_ms: Option<Summary>,
+  /// Between rendered frames.
+  pub frame_ms: Option<Summary>,
+  pub voxel_bytes_received: u64,
+  pub queue_depths: QueueDepths,
+  /// The round trip time of the last answered ping.
+  pub ping_ms: Option<f64>,
+}
+
+impl Snapshot {
+  fn summaries(&self) -> [(&'static str, &'static str, Option<Summary>); 3] {
+    [
+      ("block_load_ms", "Milliseconds from requesting voxels to loading them.", self.block_load_ms),
+      ("meshing_ms", "Milliseconds spent meshing a block.", self.meshing_ms),
+      ("frame_ms", "Milliseconds between rendered frames.", self.frame_ms),
+    ]
+  }
+
+  fn queue_depths(&self) -> [(&'static str, usize); 4] {
+    let q = &self.queue_depths;
+    [
+      ("voxel_updates", q.voxel_updates),
+      ("view_updates0", q.view_updates0),
+      ("view_updates1", q.view_updates1),
+      ("audio_updates", q.audio_updates),
+    ]
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_json(&self) -> String {
+    format!("{}\n", json::as_pretty_json(self))
+  }
+
+  /// One `metric,value` row per number. Summaries that have no samples yet are left out.
+  pub fn to_csv(&self) -> String {
+    let mut csv = String::from("metric,value\n");
+    for &(name, _, summary) in &self.summaries() {
+      if let Some(s) = summary {
+        csv.push_str(&format!("{}.count,{}\n", name, s.count));
+        csv.push_str(&format!("{}.mean,{}\n", name, s.mean_ms));
+        csv.push_str(&format!("{}.median,{}\n", name, s.median_ms));
+        csv.push_str(&format!("{}.p95,{}\n", name, s.p95_ms));
+        csv.push_str(&format!("{}.max,{}\n", name, s.max_ms));
+      }
+    }
+    csv.push_str(&format!("voxel_bytes_received,{}\n", self.voxel_bytes_received));
+    if let Some(ping_ms) = self.ping_ms {
+      csv.push_str(&format!("ping_ms,{}\n", ping_ms));
+    }
+    for &(queue, depth) in &self.queue_depths() {
+      csv.push_str(&format!("queue_depth.{},{}\n", queue, depth));
+    }
+    csv
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_prometheus(&self) -> String {
+    let mut text = prometheus::new();
+    for &(name, help, summary) in &self.summaries() {
+      if let Some(ref s) = summary {
+        samples::to_prometheus(&mut text, &format!("playform_client_{}", name), help, s);
+      }
+    }
+    text.metric("playform_client_voxel_bytes_received", "counter", "Bytes of voxels received from the server.");
+    text.sample("playform_client_voxel_bytes_received", &[], self.voxel_bytes_received as f64);
+    if let Some(ping_ms) = self.ping_ms {
+      text.metric("playform_client_ping_ms", "gauge", "Milliseconds for the last ping to the server to be answered.");
+      text.sample("playform_client_ping_ms", &[], ping_ms);
+    }
+    text.metric("playform_client_queue_depth", "gauge", "Updates waiting to be handled by another thread.");
+    for &(queue, depth) in &self.queue_depths() {
+      text.sample("playform_client_queue_depth", &[("queue", queue)], depth as f64);
+    }
+    text.into_string()
+  }
+
+  /// Write the metrics to `path`, as CSV if it ends in `.csv`, and otherwise as JSON.
+  pub fn write(&self, path: &Path) -> io::Result<()> {
+    let contents =
+      match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => self.to_csv(),
+        _ => self.to_json(),
+      };
+    let mut file = try!(File::create(path));
+    file.write_all(contents.as_bytes())
+  }
+}
+
+#[test]
+fn exports_snapshots() {
+  let metrics = new();
+  metrics.record_block_load(1_000_000, 3_000_000);
+  metrics.record_received(&protocol::ServerToClient::Ping, 100);
+  metrics.record_received(&protocol::ServerToClient::Voxels(None, Vec::new(), protocol::VoxelReason::Updated), 100);
+  metrics.set_queue_depths(QueueDepths { view_updates1: 7, .. Default::default() });
+  assert!(metrics.start_ping(10_000_000, 1_000_000_000));
+  assert!(!metrics.start_ping(20_000_000, 1_000_000_000));
+  metrics.record_pong(14_000_000);
+  metrics.record_pong(18_000_000);
+  for _ in 0 .. samples::MAX_SAMPLES + 1 {
+    metrics.record_frame(1);
+  }
+  let snapshot = metrics.snapshot();
+  assert_eq!(snapshot.frame_ms.unwrap().count, samples::MAX_SAMPLES);
+  assert!(snapshot.meshing_ms.is_none());
+
+  let csv = snapshot.to_csv();
+  assert!(csv.starts_with("metric,value\nblock_load_ms.count,1\nblock_load_ms.mean,2\n"));
+  assert!(csv.contains("\nvoxel_bytes_received,100\n"));
+  assert!(csv.contains("\nqueue_depth.view_updates1,7\n"));
+  assert!(csv.contains("\nping_ms,4\n"));
+
+  let json = json::Json::from_str(&snapshot.to_json()).unwrap();
+  assert_eq!(json.find_path(&["block_load_ms", "max_ms"]).and_then(|x| x.as_f64()), Some(2.0));
+  assert_eq!(json.find("meshing_ms"), Some(&json::Json::Null));
+  assert_eq!(json.find_path(&["queue_depths", "view_updates1"]).and_then(|x| x.as_u64()), Some(7));
+
+  let prometheus = snapshot.to_prometheus();
+  assert!(prometheus.contains("\nplayform_client_block_load_ms{quantile=\"0.95\"} 2\n"));
+  assert!(prometheus.contains("\nplayform_client_voxel_bytes_received 100\n"));
+  assert!(prometheus.contains("\nplayform_client_queue_depth{queue=\"view_updates1\"} 7\n"));
+}
@@ -50,14 +50,19 @@ mod audio_thread;
 mod block_position;
 mod camera;
 mod client;
+mod debug_hud;
+mod disk_cache;
+mod fontloader;
+mod frustum;
+mod gamepad;
 mod hud;
 mod light;
 mod load_terrain;
 mod lod;
+mod mesh_pool;
 mod mob_buffers;
 mod player_buffers;
 mod process_event;
-mod record_book;
 mod render;
 mod run;
 mod server;
@@ -66,10 +71,19 @@ mod shaders;
 mod grass_buffers;
 mod terrain_buffers;
 mod terrain_mesh;
+mod ttf;
 mod update_thread;
 mod vertex;
 mod view;
 mod view_thread;
 mod view_update;
+mod voxel_cache;
+
+pub mod bindings;
+pub mod bot;
+pub mod mesh_export;
+pub mod metrics;
+pub mod recording;
+pub mod settings;
 
 pub use run::run;
@@ -1,18 +1,25 @@
 //! SDL input event processing code.
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Aabb3, Point3, Vector2, Vector3};
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::mouse::Mouse;
+use std::cmp;
 use std::f32::consts::PI;
 use stopwatch;
+use time;
 
 use common::entity_id;
 use common::protocol;
 
+use bindings::{Action, Input};
 use client;
 use view;
 
+/// Half the width of the box of voxels around the player saved by the prefab tool.
+const PREFAB_CAPTURE_RADIUS: i32 = 8;
+
+/// How many blocks the load distance changes by per key press.
+const LOAD_DISTANCE_STEP: i32 = 4;
+
 #[allow(missing_docs)]
 pub fn process_event<UpdateServer>(
   update_server: &mut UpdateServer,
@@ -21,36 +28,57 @@ pub fn process_event<UpdateServer>(
   event: Event,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
+  let bindings = &client.settings.bindings;
   match event {
-    Event::KeyDown{keycode, repeat, ..} => {
-      keycode.map(|keycode| {
-        if !repeat {
-          key_press(update_server, view, client, keycode);
-        }
-      });
+    Event::KeyDown{keycode: Some(keycode), repeat: false, ..} => {
+      if let Some(action) = bindings.action(Input::Key(keycode)) {
+        start_action(update_server, view, client, action);
+      }
     },
-    Event::KeyUp{keycode, repeat, ..} => {
-      keycode.map(|keycode| {
-        if !repeat {
-          key_release(client.player_id, update_server, keycode);
-        }
-      });
+    Event::KeyUp{keycode: Some(keycode), repeat: false, ..} => {
+      if let Some(action) = bindings.action(Input::Key(keycode)) {
+        stop_action(client.player_id, update_server, action);
+      }
+    },
+    Event::MouseButtonDown{mouse_btn, ..} => {
+      if let Some(action) = bindings.action(Input::Mouse(mouse_btn)) {
+        start_action(update_server, view, client, action);
+      }
+    },
+    Event::MouseButtonUp{mouse_btn, ..} => {
+      if let Some(action) = bindings.action(Input::Mouse(mouse_btn)) {
+        stop_action(client.player_id, update_server, action);
+      }
     },
     Event::MouseMotion{xrel, yrel, ..} => {
-      mouse_move(client.player_id, update_server, view, xrel, yrel);
+      look(client.player_id, update_server, view, bindings.look(xrel, yrel));
     },
-    Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+    Event::ControllerButtonDown{button, ..} => {
+      if let Some(action) = bindings.action(Input::Button(button)) {
+        start_action(update_server, view, client, action);
+      }
+    },
+    Event::ControllerButtonUp{button, ..} => {
+      if let Some(action) = bindings.action(Input::Button(button)) {
+        stop_action(client.player_id, update_server, action);
+      }
+    },
+    Event::ControllerAxisMotion{which, axis, value, ..} => {
+      view.gamepad.axis(which, axis, value);
+    },
+    Event::ControllerDeviceRemoved{which, ..} => {
+      view.gamepad.disconnected(which);
     },
     _ => {},
   }
 }
 
-fn key_press<UpdateServer>(
+/// Start doing something, e.g. because its key was pressed.
+pub fn start_action<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
   client: &client::T,
-  key: Keycode,
+  action: Action,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
   use common::protocol::ClientToServer::*;
@@ -81,102 +109,134 @@ fn key_press<UpdateServer>(
     }
   };
 
-  stopwatch::time("event.key_press", || {
-    match key {
-      Keycode::A => {
+  stopwatch::time("event.start_action", || {
+    match action {
+      Action::MoveLeft => {
         update_server(Walk(client.player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
+      Action::MoveRight => {
         update_server(Walk(client.player_id, Vector3::new(1.0, 0.0, 0.0)));
       },
-      Keycode::Space => {
+      Action::Jump => {
         update_server(StartJump(client.player_id));
       },
-      Keycode::W => {
+      Action::MoveForward => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
-      Keycode::S => {
+      Action::MoveBack => {
         update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::Left => {
+      Action::LookLeft => {
         lr(update_server, view, 1.0);
       },
-      Keycode::Right => {
+      Action::LookRight => {
         lr(update_server, view, -1.0);
       },
-      Keycode::Up => {
+      Action::LookUp => {
         ud(update_server, view, 1.0);
       },
-      Keycode::Down => {
+      Action::LookDown => {
         ud(update_server, view, -1.0);
       },
-      Keycode::H => {
+      Action::Build => {
+        update_server(Add(client.player_id, *client.tree_species.lock().unwrap()));
+      },
+      Action::Dig => {
+        update_server(Remove(client.player_id));
+      },
+      Action::ToggleHud => {
         view.show_hud = !view.show_hud;
       },
-      Keycode::M => {
+      Action::ToggleDebugHud => {
+        view.debug_hud.toggle();
+      },
+      Action::ToggleInputMode => {
         view.input_mode =
           match view.input_mode {
             view::InputMode::Camera => view::InputMode::Sun,
             view::InputMode::Sun => view::InputMode::Camera,
           };
       },
-      Keycode::P => {
+      Action::NextTreeSpecies => {
+        let mut tree_species = client.tree_species.lock().unwrap();
+        *tree_species = tree_species.next();
+        info!("Placing {:?} trees", *tree_species);
+      },
+      Action::SavePrefab => {
+        let position = *client.player_position.lock().unwrap();
+        let center = Point3::new(position.x.floor() as i32, position.y.floor() as i32, position.z.floor() as i32);
+        let r = PREFAB_CAPTURE_RADIUS;
+        let bounds =
+          Aabb3::new(
+            Point3::new(center.x - r, center.y - r, center.z - r),
+            Point3::new(center.x + r, center.y + r, center.z + r),
+          );
+        let name = format!("prefab{}", time::get_time().sec);
+        update_server(SavePrefab(client.id, name, bounds));
+      },
+      Action::PastePrefab => {
+        // Timestamped names sort oldest-first, so paste the newest.
+        match client.prefabs.lock().unwrap().last() {
+          None => warn!("No prefabs to paste"),
+          Some(name) => {
+            let rotation = *client.prefab_rotation.lock().unwrap();
+            update_server(PastePrefab(client.player_id, name.clone(), rotation));
+          },
+        }
+      },
+      Action::RotatePrefab => {
+        let mut rotation = client.prefab_rotation.lock().unwrap();
+        *rotation = (*rotation + PI / 2.0) % (2.0 * PI);
+      },
+      Action::DecreaseLoadDistance => {
+        change_load_distance(client, -LOAD_DISTANCE_STEP);
+      },
+      Action::IncreaseLoadDistance => {
+        change_load_distance(client, LOAD_DISTANCE_STEP);
+      },
+      Action::ToggleLoadPosition => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
           None => *load_position = Some(*client.player_position.lock().unwrap()),
           Some(_) => *load_position = None,
         }
       },
-      _ => {},
     }
   })
 }
 
-fn mouse_press<UpdateServer>(
-  player_id: entity_id::T,
-  update_server: &mut UpdateServer,
-  mouse_btn: Mouse,
-) where UpdateServer: FnMut(protocol::ClientToServer)
-{
-  stopwatch::time("event.mouse_press", || {
-    match mouse_btn {
-      Mouse::Left => {
-        update_server(
-          protocol::ClientToServer::Add(player_id)
-        );
-      },
-      Mouse::Right => {
-        update_server(
-          protocol::ClientToServer::Remove(player_id)
-        );
-      },
-      _ => {},
-    }
-  })
+/// Ask the update thread to load blocks `delta` farther away, within the terrain budget.
+fn change_load_distance(client: &client::T, delta: i32) {
+  let mut requested = client.requested_load_distance.lock().unwrap();
+  let current = requested.unwrap_or_else(|| *client.max_load_distance.lock().unwrap());
+  let load_distance = cmp::max(1, cmp::min(client.load_distance_limit, current + delta));
+  info!("Requesting load distance {}", load_distance);
+  *requested = Some(load_distance);
 }
 
-fn key_release<UpdateServer>(
+/// Stop doing something, e.g. because its key was released.
+pub fn stop_action<UpdateServer>(
   player_id: entity_id::T,
   update_server: &mut UpdateServer,
-  key: Keycode,
+  action: Action,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
-  stopwatch::time("event.key_release", || {
-    match key {
-      // accelerations are negated from those in key_press.
-      Keycode::A => {
+  stopwatch::time("event.stop_action", || {
+    match action {
+      // accelerations are negated from those in start_action.
+      Action::MoveLeft => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
       },
-      Keycode::D => {
+      Action::MoveRight => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
       },
-      Keycode::Space => {
+      Action::Jump => {
         update_server(protocol::ClientToServer::StopJump(player_id));
       },
-      Keycode::W => {
+      Action::MoveForward => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
       },
-      Keycode::S => {
+      Action::MoveBack => {
         update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
       },
       _ => {}
@@ -184,20 +244,33 @@ fn key_release<UpdateServer>(
   })
 }
 
-// x and y are relative to last position.
-fn mouse_move<UpdateServer>(
-  player_id: entity_id::T,
+/// Walk and look around with the gamepad sticks, `dt_ns` after the last time this was called.
+pub fn update_gamepad<UpdateServer>(
   update_server: &mut UpdateServer,
   view: &mut view::T,
-  dx: i32, dy: i32,
+  client: &client::T,
+  dt_ns: u64,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
-  stopwatch::time("event.mouse_move", || {
-    let d = Vector2::new(dx, dy);
-    // To-radians coefficient. Numbers closer to zero dull the mouse movement more.
-    let to_radians = Vector2::new(-1.0 / 1000.0, -1.0 / 1600.0);
-    let r = Vector2::new(d.x as f32 * to_radians.x, d.y as f32 * to_radians.y);
+  let bindings = &client.settings.bindings;
+  if let Some(walk) = view.gamepad.walk(bindings) {
+    update_server(protocol::ClientToServer::Walk(client.player_id, walk));
+  }
+  let r = view.gamepad.look(bindings, dt_ns as f32 / 1_000_000_000.0);
+  if let Some(r) = r {
+    look(client.player_id, update_server, view, r);
+  }
+}
 
+/// Turn the player and camera by `r` radians, laterally and then vertically.
+fn look<UpdateServer>(
+  player_id: entity_id::T,
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  r: Vector2<f32>,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  stopwatch::time("event.look", || {
     update_server(protocol::ClientToServer::RotatePlayer(player_id, r));
     view.camera.rotate_lateral(r.x);
     view.camera.rotate_vertical(r.y);
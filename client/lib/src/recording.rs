@@ -0,0 +1,200 @@
+//! Record the messages a client receives from the server, and replay them later in place of a
+//! live server, so client bugs can be reproduced from the exact sequence of messages that caused them.
+//!
+//! A recording is a series of frames, each the nanoseconds since recording started and the length
+//! of the message (both little-endian), followed by the message as it came off the socket.
+
+use bincode;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use time;
+
+use common::protocol;
+
+/// A recorded message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+  /// When the message arrived, in nanoseconds since recording started.
+  pub at_ns: u64,
+  /// The encoded `protocol::ServerToClient`.
+  pub message: Vec<u8>,
+}
+
+fn write_u64<W: Write>(w: &mut W, x: u64) -> io::Result<()> {
+  let mut bytes = [0; 8];
+  for (i, byte) in bytes.iter_mut().enumerate() {
+    *byte = (x >> (8 * i)) as u8;
+  }
+  w.write_all(&bytes)
+}
+
+/// Read a little-endian u64, or `None` if the input ends first.
+fn read_u64<R: Read>(r: &mut R) -> io::Result<Option<u64>> {
+  let mut bytes = [0; 8];
+  let mut read = 0;
+  while read < bytes.len() {
+    match try!(r.read(&mut bytes[read ..])) {
+      0 => return Ok(None),
+      n => read += n,
+    }
+  }
+  Ok(Some(bytes.iter().rev().fold(0, |x, &byte| (x << 8) | byte as u64)))
+}
+
+/// Writes frames to a recording as messages arrive.
+pub struct Writer {
+  file: BufWriter<File>,
+  started_at: u64,
+}
+
+/// Start a recording at `path`, replacing anything already there.
+pub fn create(path: &Path) -> io::Result<Writer> {
+  let file = try!(File::create(path));
+  Ok(Writer {
+    file: BufWriter::new(file),
+    started_at: time::precise_time_ns(),
+  })
+}
+
+impl Writer {
+  /// Record a message that just arrived.
+  pub fn write(&mut self, message: &[u8]) -> io::Result<()> {
+    let at_ns = time::precise_time_ns() - self.started_at;
+    self.write_frame(at_ns, message)
+  }
+
+  fn write_frame(&mut self, at_ns: u64, message: &[u8]) -> io::Result<()> {
+    try!(write_u64(&mut self.file, at_ns));
+    try!(write_u64(&mut self.file, message.len() as u64));
+    try!(self.file.write_all(message));
+    // Flush every frame, so recordings of crashes are complete.
+    self.file.flush()
+  }
+}
+
+/// Read all the frames of a recording. If it was cut off mid-frame (e.g. by a crash),
+/// the frames before that are still returned.
+pub fn read(path: &Path) -> io::Result<Vec<Frame>> {
+  let mut file = BufReader::new(try!(File::open(path)));
+  let mut frames = Vec::new();
+  loop {
+    let frame = {
+      let at_ns = match try!(read_u64(&mut file)) { None => break, Some(x) => x };
+      let len = match try!(read_u64(&mut file)) { None => break, Some(x) => x };
+      let mut message = Vec::new();
+      if try!((&mut file).take(len).read_to_end(&mut message)) as u64 != len {
+        break
+      }
+      Frame { at_ns: at_ns, message: message }
+    };
+    frames.push(frame);
+  }
+  if frames.is_empty() {
+    warn!("{:?} has no complete frames", path);
+  }
+  Ok(frames)
+}
+
+/// Plays back a recording, releasing each message once its time comes.
+pub struct Replay {
+  frames: VecDeque<Frame>,
+  started_at: u64,
+  speed: f64,
+}
+
+/// Replay some frames, `speed` times faster than they were recorded.
+pub fn replay(frames: Vec<Frame>, speed: f64) -> Replay {
+  assert!(speed > 0.0);
+  Replay {
+    frames: frames.into_iter().collect(),
+    started_at: time::precise_time_ns(),
+    speed: speed,
+  }
+}
+
+fn decode(frame: Frame) -> protocol::ServerToClient {
+  bincode::rustc_serialize::decode(&frame.message).unwrap()
+}
+
+impl Replay {
+  /// The next message, whether or not it's due yet.
+  pub fn next_message(&mut self) -> Option<protocol::ServerToClient> {
+    self.frames.pop_front().map(decode)
+  }
+
+  /// The next message, if it's due. This can stand in for `server::recv::T::try`.
+  pub fn try(&mut self) -> Option<protocol::ServerToClient> {
+    let elapsed_ns = time::precise_time_ns() - self.started_at;
+    self.pop_due(elapsed_ns).map(decode)
+  }
+
+  fn pop_due(&mut self, elapsed_ns: u64) -> Option<Frame> {
+    let due =
+      match self.frames.front() {
+        None => false,
+        Some(frame) => frame.at_ns as f64 <= elapsed_ns as f64 * self.speed,
+      };
+    if due {
+      self.frames.pop_front()
+    } else {
+      None
+    }
+  }
+
+  /// Whether every message has been replayed.
+  pub fn is_finished(&self) -> bool {
+    self.frames.is_empty()
+  }
+}
+
+#[test]
+fn recordings_round_trip_and_replay_on_time() {
+  use bincode::rustc_serialize::encode;
+
+  // Unique per process, so concurrent test runs don't clobber each other's files.
+  let pid = unsafe { ::libc::getpid() };
+  let path = ::std::env::temp_dir().join(format!("playform-recording-test-{}.bin", pid));
+  let ping = encode(&protocol::ServerToClient::Ping, bincode::SizeLimit::Infinite).unwrap();
+  {
+    let mut writer = create(&path).unwrap();
+    writer.write_frame(0, &ping).unwrap();
+    writer.write_frame(2_000_000_000, &[1, 2, 3]).unwrap();
+    writer.write_frame(4_000_000_000, &[]).unwrap();
+  }
+  // Cut off mid-frame.
+  File::create(&path.with_extension("cut")).unwrap()
+    .write_all(&{
+      let mut bytes = Vec::new();
+      File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+      bytes.pop();
+      bytes
+    })
+    .unwrap();
+
+  let frames = read(&path).unwrap();
+  assert_eq!(
+    frames,
+    vec!(
+      Frame { at_ns: 0, message: ping.clone() },
+      Frame { at_ns: 2_000_000_000, message: vec!(1, 2, 3) },
+      Frame { at_ns: 4_000_000_000, message: vec!() },
+    )
+  );
+  assert_eq!(read(&path.with_extension("cut")).unwrap(), frames[.. 2].to_vec());
+
+  // Twice as fast.
+  let mut playback = replay(frames, 2.0);
+  assert_eq!(playback.pop_due(0).map(|frame| frame.at_ns), Some(0));
+  assert!(playback.pop_due(999_999_999).is_none());
+  assert_eq!(playback.pop_due(1_000_000_000).map(|frame| frame.at_ns), Some(2_000_000_000));
+  assert!(!playback.is_finished());
+  assert_eq!(playback.pop_due(5_000_000_000).map(|frame| frame.at_ns), Some(4_000_000_000));
+  assert!(playback.is_finished());
+  assert!(playback.pop_due(5_000_000_000).is_none());
+
+  ::std::fs::remove_file(&path).unwrap();
+  ::std::fs::remove_file(&path.with_extension("cut")).unwrap();
+}
@@ -2,6 +2,7 @@
 
 use camera::{Camera, set_camera};
 use cgmath;
+use frustum;
 use light::{set_sun, set_ambient_light};
 use gl;
 use std;
@@ -86,7 +87,8 @@ pub fn render(
   set_ambient_light(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.sun);
   set_camera(&mut rndr.shaders.terrain_shader.shader, &mut rndr.gl, &rndr.camera);
   set_eye_position(&mut rndr.shaders.terrain_shader.shader, &rndr.camera);
-  rndr.terrain_buffers.draw(&mut rndr.gl);
+  let frustum = frustum::of_projection(&rndr.camera.projection_matrix());
+  rndr.terrain_buffers.draw(&mut rndr.gl, &frustum);
 
   rndr.shaders.mob_shader.shader.use_shader(&mut rndr.gl);
   set_camera(&mut rndr.shaders.mob_shader.shader, &mut rndr.gl, &rndr.camera);
@@ -100,4 +102,8 @@ pub fn render(
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
   }
+
+  if rndr.debug_hud.show {
+    rndr.debug_hud.draw(&mut rndr.gl, &rndr.shaders.texture_shader, &rndr.misc_texture_unit);
+  }
 }
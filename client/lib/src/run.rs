@@ -1,21 +1,35 @@
 use std;
-use std::io::Write;
 use std::sync::{Mutex};
 use stopwatch;
 use thread_scoped;
 
+use common::prometheus;
 use common::protocol;
 
 use audio_loader;
 use audio_thread;
 use client;
+use mesh_pool;
+use metrics;
 use server;
-use record_book;
+use server_update;
+use settings;
 use update_thread::update_thread;
 use view_thread::view_thread;
 
+/// How often the metrics file is rewritten, in monitor thread ticks (seconds).
+const METRICS_WRITE_INTERVAL: u32 = 10;
+
+fn write_metrics(client: &client::T) {
+  if let Some(ref path) = client.settings.metrics_path {
+    if let Err(err) = client.metrics.snapshot().write(path) {
+      warn!("Couldn't write metrics to {:?}: {}", path, err);
+    }
+  }
+}
+
 #[allow(missing_docs)]
-pub fn run(listen_url: &str, server_url: &str) {
+pub fn run(listen_url: &str, server_url: &str, settings: &settings::T) {
   let voxel_updates = Mutex::new(std::collections::VecDeque::new());
   let view_updates0 = Mutex::new(std::collections::VecDeque::new());
   let view_updates1 = Mutex::new(std::collections::VecDeque::new());
@@ -24,25 +38,65 @@ pub fn run(listen_url: &str, server_url: &str) {
   let quit = Mutex::new(false);
   let quit = &quit;
 
-  let server = server::new(&server_url, &listen_url);
+  let server = server::new(&server_url, &listen_url, settings.record.as_ref().map(|path| path.as_path()));
 
-  let client = connect_client(&listen_url, &server);
+  let client = connect_client(&listen_url, &server, settings, true);
   let client = &client;
 
   {
     let monitor_thread = {
       unsafe {
         thread_scoped::scoped(|| {
+          let mut ticks = 0;
           while !*quit.lock().unwrap() {
-            info!("Outstanding voxel updates: {}", voxel_updates.lock().unwrap().len());
-            info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
-            info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
+            let queue_depths =
+              metrics::QueueDepths {
+                voxel_updates: voxel_updates.lock().unwrap().len(),
+                view_updates0: view_updates0.lock().unwrap().len(),
+                view_updates1: view_updates1.lock().unwrap().len(),
+                audio_updates: audio_updates.lock().unwrap().len(),
+              };
+            client.metrics.set_queue_depths(queue_depths);
+            info!("Outstanding updates: {:?}", queue_depths);
+            let voxel_stats = client.voxel_cache.lock().unwrap().stats();
+            info!(
+              "Cached voxels: {} in {} blocks (~{} MiB); {} evicted in {} blocks",
+              voxel_stats.voxels,
+              voxel_stats.blocks,
+              voxel_stats.bytes() >> 20,
+              voxel_stats.evicted_voxels,
+              voxel_stats.evicted_blocks,
+            );
+            let disk_stats = client.disk_cache.lock().unwrap().stats();
+            info!(
+              "Saved voxels: {} blocks loaded, {} missed, {} written",
+              disk_stats.hits,
+              disk_stats.misses,
+              disk_stats.writes,
+            );
+
+            ticks += 1;
+            if ticks % METRICS_WRITE_INTERVAL == 0 {
+              write_metrics(client);
+            }
             std::thread::sleep(std::time::Duration::from_secs(1));
           }
         })
       }
     };
 
+    let metrics_thread =
+      settings.metrics_address.as_ref().map(|address| {
+        unsafe {
+          thread_scoped::scoped(move || {
+            let served = prometheus::serve(address, quit, || client.metrics.snapshot().to_prometheus());
+            if let Err(err) = served {
+              warn!("Couldn't serve metrics at {}: {}", address, err);
+            }
+          })
+        }
+      });
+
     let audio_thread = {
       let audio_updates = &audio_updates;
       unsafe {
@@ -57,6 +111,18 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest));
 
+    let mesh_threads: Vec<_> =
+      (0 .. mesh_pool::WORKER_COUNT)
+      .map(|_| {
+        let client = &client;
+        unsafe {
+          thread_scoped::scoped(move || {
+            mesh_pool::worker_thread(quit, client);
+          })
+        }
+      })
+      .collect();
+
     let update_thread = {
       let client = &client;
       let view_updates0 = &view_updates0;
@@ -69,7 +135,7 @@ pub fn run(listen_url: &str, server_url: &str) {
           update_thread(
             quit,
             client,
-            &mut || { server.listen.try() },
+            &mut || { recv_server(client, &server) },
             &mut || { voxel_updates.lock().unwrap().pop_front() },
             &mut |up| { view_updates0.lock().unwrap().push_back(up) },
             &mut |up| { view_updates1.lock().unwrap().push_back(up) },
@@ -78,21 +144,6 @@ pub fn run(listen_url: &str, server_url: &str) {
             &mut |request_time, updates, reason| { voxel_updates.lock().unwrap().push_back((request_time, updates, reason)) },
           );
 
-          let mut recorded = record_book::thread_local::clone();
-          recorded.block_loads.sort_by(|x, y| x.loaded_at.cmp(&y.loaded_at));
-
-          let mut file = std::fs::File::create("block_loads.out").unwrap();
-
-          file.write_all(b"records = [").unwrap();
-          for (i, record) in recorded.block_loads.iter().enumerate() {
-            if i > 0 {
-              file.write_all(b", ").unwrap();
-            }
-            file.write_fmt(format_args!("[{}; {}; {}; {}]", record.requested_at, record.responded_at, record.processed_at, record.loaded_at)).unwrap();
-          }
-          file.write_all(b"];\n").unwrap();
-          file.write_fmt(format_args!("plot([1:{}], records);", recorded.block_loads.len())).unwrap();
-
           stopwatch::clone()
         })
       }
@@ -116,25 +167,77 @@ pub fn run(listen_url: &str, server_url: &str) {
 
     audio_thread.join();
     monitor_thread.join();
+    if let Some(metrics_thread) = metrics_thread {
+      metrics_thread.join();
+    }
+    for mesh_thread in mesh_threads {
+      mesh_thread.join();
+    }
 
     let stopwatch = update_thread.join();
 
     stopwatch.print();
+    write_metrics(client);
   }
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+/// Receive a message from the server, if there is one, and count it in the client's metrics.
+pub fn recv_server(client: &client::T, server: &server::T) -> Option<protocol::ServerToClient> {
+  server.listen.try_sized().map(|(msg, bytes)| {
+    client.metrics.record_received(&msg, bytes);
+    msg
+  })
+}
+
+/// Connect to a server and add a player. Voxels received from the server are only saved to disk
+/// if `save_voxels` is set.
+pub fn connect_client(listen_url: &str, server: &server::T, settings: &settings::T, save_voxels: bool) -> client::T {
+  connect_client_with(
+    listen_url,
+    &mut || server.listen.wait(),
+    &mut |msg| server.talk.tell(&msg),
+    settings,
+    save_voxels,
+  )
+}
+
+/// `connect_client`, with the messages to and from the server going through closures,
+/// e.g. to connect to a recording.
+pub fn connect_client_with<RecvServer, UpdateServer>(
+  listen_url: &str,
+  recv_server: &mut RecvServer,
+  update_server: &mut UpdateServer,
+  settings: &settings::T,
+  save_voxels: bool,
+) -> client::T where
+  RecvServer: FnMut() -> protocol::ServerToClient,
+  UpdateServer: FnMut(protocol::ClientToServer),
+{
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  update_server(protocol::ClientToServer::Init(listen_url.to_owned()));
+  let mut world = None;
   loop {
-    match server.listen.wait() {
+    match recv_server() {
       protocol::ServerToClient::LeaseId(client_id) => {
-        server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
+        update_server(protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
-          match server.listen.wait() {
+          match recv_server() {
+            protocol::ServerToClient::World(world_id, versions) => {
+              world = Some((world_id, versions));
+            },
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              update_server(protocol::ClientToServer::ListPrefabs(client_id));
+              let client = client::new(client_id, player_id, position, settings.clone());
+              match world {
+                None => warn!("The server didn't say which world it's running; not saving voxels."),
+                Some((world_id, versions)) => {
+                  if save_voxels {
+                    server_update::enter_world(&client, world_id, versions);
+                  }
+                },
+              }
+              return client;
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -1,7 +1,10 @@
 use std;
+use std::path::Path;
 
 use common::socket::{SendSocket, ReceiveSocket};
 
+use recording;
+
 pub mod send {
   use std::sync::mpsc::Sender;
 
@@ -33,8 +36,13 @@ pub mod recv {
 
   impl T {
     pub fn try(&self) -> Option<protocol::ServerToClient> {
+      self.try_sized().map(|(msg, _)| msg)
+    }
+
+    /// `try`, along with how many bytes the message was.
+    pub fn try_sized(&self) -> Option<(protocol::ServerToClient, usize)> {
       match self.0.try_recv() {
-        Ok(msg) => Some(bincode::rustc_serialize::decode(&msg).unwrap()),
+        Ok(msg) => Some((bincode::rustc_serialize::decode(&msg).unwrap(), msg.len())),
         Err(TryRecvError::Empty) => None,
         e => {
           e.unwrap();
@@ -58,13 +66,21 @@ pub struct T {
 
 unsafe impl Send for T {}
 
+/// Connect to a server, recording every message it sends to `record`, if it's set.
 pub fn new(
   server_url: &str,
   listen_url: &str,
+  record: Option<&Path>,
 ) -> T {
   let (send_send, send_recv) = std::sync::mpsc::channel();
   let (recv_send, recv_recv) = std::sync::mpsc::channel();
 
+  let mut recording =
+    record.map(|path| {
+      recording::create(path)
+        .unwrap_or_else(|err| panic!("Couldn't record server messages to {:?}: {}", path, err))
+    });
+
   let _recv_thread ={
     let listen_url = listen_url.to_owned();
     let recv_send = recv_send.clone();
@@ -77,7 +93,18 @@ pub fn new(
       loop {
         match listen_socket.read() {
           None => break,
-          Some(msg) => recv_send.send(msg).unwrap(),
+          Some(msg) => {
+            let failed =
+              match recording {
+                None => None,
+                Some(ref mut recording) => recording.write(&msg).err(),
+              };
+            if let Some(err) = failed {
+              warn!("Stopped recording server messages: {}", err);
+              recording = None;
+            }
+            recv_send.send(msg).unwrap()
+          },
         }
       }
     })
@@ -1,6 +1,7 @@
 use cgmath;
 use cgmath::{Aabb3, Point, Point3, EuclideanVector};
 use rand::Rng;
+use std::path::Path;
 use stopwatch;
 use time;
 
@@ -11,6 +12,7 @@ use common::voxel;
 use audio_loader;
 use audio_thread;
 use client;
+use disk_cache;
 use light;
 use vertex::ColoredVertex;
 use view_update::ClientToView;
@@ -19,17 +21,15 @@ pub const TRIANGLES_PER_BOX: u32 = 12;
 pub const VERTICES_PER_TRIANGLE: u32 = 3;
 pub const TRIANGLE_VERTICES_PER_BOX: u32 = TRIANGLES_PER_BOX * VERTICES_PER_TRIANGLE;
 
-pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueBlockUpdates>(
+pub fn apply_server_update<UpdateView, UpdateAudio, EnqueueBlockUpdates>(
   client: &client::T,
   update_view: &mut UpdateView,
   update_audio: &mut UpdateAudio,
-  update_server: &mut UpdateServer,
   enqueue_block_updates: &mut EnqueueBlockUpdates,
   update: protocol::ServerToClient,
 ) where
   UpdateView: FnMut(ClientToView),
   UpdateAudio: FnMut(audio_thread::Message),
-  UpdateServer: FnMut(protocol::ClientToServer),
   EnqueueBlockUpdates: FnMut(Option<u64>, Vec<(voxel::bounds::T, voxel::T)>, protocol::VoxelReason),
 {
   stopwatch::time("apply_server_update", move || {
@@ -37,8 +37,12 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueBlockUp
       protocol::ServerToClient::LeaseId(_) => {
         warn!("Client ID has already been leased.");
       },
+      protocol::ServerToClient::World(world, versions) => {
+        warn!("Switching to world {:?} while connected.", world);
+        enter_world(client, world, versions);
+      },
       protocol::ServerToClient::Ping => {
-        update_server(protocol::ClientToServer::Ping(client.id));
+        client.metrics.record_pong(time::precise_time_ns());
       },
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
@@ -81,6 +85,9 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueBlockUp
 
         enqueue_block_updates(request_time, voxels, reason);
       },
+      protocol::ServerToClient::VoxelVersions(versions) => {
+        client.disk_cache.lock().unwrap().push_versions(versions);
+      },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
           let player_position = *client.player_position.lock().unwrap();
@@ -91,11 +98,24 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueBlockUp
             update_audio(audio_thread::Message::PlayOneShot(audio_loader::SoundId::Footstep(idx)));
           }
         }
-      }
+      },
+      protocol::ServerToClient::Prefabs(names) => {
+        info!("Prefabs: {:?}", names);
+        *client.prefabs.lock().unwrap() = names;
+      },
     }
   })
 }
 
+/// Start using a world: seed grass from it, and use the voxels saved for it.
+pub fn enter_world(client: &client::T, world: protocol::WorldId, versions: Vec<(Point3<i32>, u64)>) {
+  *client.grass_seed.lock().unwrap() = world.0;
+  let root = Path::new(disk_cache::DIRECTORY);
+  if let Err(err) = client.disk_cache.lock().unwrap().open(root, world, versions) {
+    warn!("Couldn't open saved voxels for {:?}: {}", world, err);
+  }
+}
+
 fn to_triangles(
   bounds: &Aabb3<f32>,
   c: &Color4<f32>,
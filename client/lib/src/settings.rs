@@ -0,0 +1,237 @@
+//! Client settings, read from a settings file and the command line.
+//!
+//! A settings file has one `name = value` per line, using the names of the flags without their
+//! leading `--`; `#` starts a comment.
+
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bindings;
+use terrain_mesh;
+
+/// Settings are read from here first, if it exists.
+pub const DEFAULT_PATH: &'static str = "client_settings.txt";
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+pub struct T {
+  /// The distances at which LOD switches.
+  pub lod_thresholds: [i32; terrain_mesh::LOD_COUNT - 1],
+  /// How finely blocks are sampled at each LOD.
+  pub resolutions: terrain_mesh::Resolutions,
+  /// The farthest blocks are ever loaded, whatever the budget allows.
+  // TODO: Remove this once our RAM usage doesn't skyrocket with load distance.
+  pub max_load_distance: i32,
+  /// VRAM bytes for terrain polygons.
+  pub terrain_byte_budget: usize,
+  /// Scales how much grass grows.
+  pub grass_density: f32,
+  /// Record every message from the server here, to replay later.
+  pub record: Option<PathBuf>,
+  /// Write performance metrics here, as JSON (or CSV, if it ends in `.csv`).
+  pub metrics_path: Option<PathBuf>,
+  /// Serve performance metrics at this address, in the Prometheus text format.
+  pub metrics_address: Option<String>,
+  /// What the keys and mouse do.
+  pub bindings: bindings::T,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    lod_thresholds: [2, 16, 32],
+    resolutions: Default::default(),
+    max_load_distance: 80,
+    terrain_byte_budget: 64_000_000,
+    grass_density: 1.0,
+    record: None,
+    metrics_path: None,
+    metrics_address: None,
+    bindings: bindings::new(),
+  }
+}
+
+/// How to use the flags understood by `parse`.
+pub const USAGE: &'static str = "\
+  --settings PATH              Read settings from a file (default: client_settings.txt).
+  --lod-thresholds A,B,C       Block distances at which LOD switches.
+  --lg-edge-samples A,B,C,D    lg(voxels along a block's edge) for each LOD.
+  --max-load-distance N        Never load blocks farther away than this.
+  --terrain-byte-budget N      VRAM bytes for terrain polygons.
+  --grass-density X            Scale how much grass grows (0 for none; default 1).
+  --record PATH                Record the messages from the server, for replaying with tools/bot.
+  --metrics PATH               Write performance metrics to a .json or .csv file, every few seconds.
+  --metrics-address HOST:PORT  Serve performance metrics over HTTP, for Prometheus.
+  --bindings PATH              Read key and mouse bindings from a file (default: bindings.txt).";
+
+/// The settings that take a value, as flags.
+const FLAGS: [&'static str; 10] = [
+  "--settings",
+  "--lod-thresholds",
+  "--lg-edge-samples",
+  "--max-load-distance",
+  "--terrain-byte-budget",
+  "--grass-density",
+  "--record",
+  "--metrics",
+  "--metrics-address",
+  "--bindings",
+];
+
+fn value<X: FromStr>(name: &str, value: &str) -> Result<X, String> {
+  X::from_str(value.trim()).map_err(|_| format!("Invalid value for {}: {:?}", name, value))
+}
+
+fn list<X: FromStr + Copy>(name: &str, s: &str, into: &mut [X]) -> Result<(), String> {
+  let parsed: Vec<X> = try!(s.split(',').map(|x| value(name, x)).collect());
+  if parsed.len() != into.len() {
+    return Err(format!("{} needs {} comma-separated values, got {:?}", name, into.len(), s))
+  }
+  for (into, x) in into.iter_mut().zip(parsed.into_iter()) {
+    *into = x;
+  }
+  Ok(())
+}
+
+fn set(settings: &mut T, name: &str, s: &str) -> Result<(), String> {
+  match name {
+    "settings" => try!(read_file(settings, Path::new(s.trim()))),
+    "lod-thresholds" => try!(list(name, s, &mut settings.lod_thresholds)),
+    "lg-edge-samples" => {
+      let mut lg_edge_samples = [0; terrain_mesh::LOD_COUNT];
+      try!(list(name, s, &mut lg_edge_samples));
+      settings.resolutions = try!(terrain_mesh::resolutions(lg_edge_samples));
+    },
+    "max-load-distance" => settings.max_load_distance = try!(value(name, s)),
+    "terrain-byte-budget" => settings.terrain_byte_budget = try!(value(name, s)),
+    "grass-density" => settings.grass_density = try!(value(name, s)),
+    "record" => settings.record = Some(PathBuf::from(s.trim())),
+    "metrics" => settings.metrics_path = Some(PathBuf::from(s.trim())),
+    "metrics-address" => settings.metrics_address = Some(String::from(s.trim())),
+    "bindings" => try!(bindings::read_file(&mut settings.bindings, Path::new(s.trim()))),
+    _ => return Err(format!("Unrecognized setting {:?}. Options:\n{}", name, USAGE)),
+  }
+  Ok(())
+}
+
+fn read_file(settings: &mut T, path: &Path) -> Result<(), String> {
+  let mut contents = String::new();
+  try!(
+    fs::File::open(path)
+    .and_then(|mut file| file.read_to_string(&mut contents))
+    .map_err(|err| format!("Couldn't read settings from {:?}: {}", path, err))
+  );
+
+  for (i, line) in contents.lines().enumerate() {
+    let line = line.split('#').next().unwrap().trim();
+    if line.is_empty() {
+      continue
+    }
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next().unwrap().trim();
+    let s = try!(parts.next().ok_or_else(|| format!("{:?} line {}: expected name = value", path, i + 1)));
+    try!(set(settings, name, s));
+  }
+  Ok(())
+}
+
+fn validate(settings: &T) -> Result<(), String> {
+  let thresholds = &settings.lod_thresholds;
+  if thresholds[0] < 0 || thresholds.windows(2).any(|w| w[0] >= w[1]) {
+    return Err(format!("LOD thresholds must be increasing and not negative: {:?}", thresholds))
+  }
+  if settings.max_load_distance < 1 {
+    return Err(String::from("The max load distance must be at least 1"))
+  }
+  if settings.terrain_byte_budget == 0 {
+    return Err(String::from("The terrain byte budget can't be zero"))
+  }
+  if !(settings.grass_density >= 0.0) {
+    return Err(format!("The grass density can't be negative: {}", settings.grass_density))
+  }
+  Ok(())
+}
+
+/// Parse the flags in `USAGE` on top of the defaults and `DEFAULT_PATH`,
+/// returning any arguments that aren't settings.
+pub fn parse_known<Args>(mut args: Args) -> Result<(T, Vec<String>), String> where
+  Args: Iterator<Item=String>,
+{
+  let mut settings = new();
+  if Path::new(bindings::DEFAULT_PATH).exists() {
+    try!(bindings::read_file(&mut settings.bindings, Path::new(bindings::DEFAULT_PATH)));
+  }
+  if Path::new(DEFAULT_PATH).exists() {
+    try!(read_file(&mut settings, Path::new(DEFAULT_PATH)));
+  }
+
+  let mut unknown = Vec::new();
+  while let Some(flag) = args.next() {
+    if FLAGS.iter().any(|f| *f == flag) {
+      let s = try!(args.next().ok_or_else(|| format!("{} needs a value", flag)));
+      try!(set(&mut settings, &flag[2..], &s));
+    } else {
+      unknown.push(flag);
+    }
+  }
+
+  try!(validate(&settings));
+  Ok((settings, unknown))
+}
+
+/// Parse the flags in `USAGE` on top of the defaults and `DEFAULT_PATH`.
+pub fn parse<Args>(args: Args) -> Result<T, String> where
+  Args: Iterator<Item=String>,
+{
+  let (settings, unknown) = try!(parse_known(args));
+  match unknown.first() {
+    None => Ok(settings),
+    Some(flag) => Err(format!("Unrecognized argument {:?}. Options:\n{}", flag, USAGE)),
+  }
+}
+
+#[test]
+fn parses_flags_and_files() {
+  use std::io::Write;
+
+  let pid = unsafe { ::libc::getpid() };
+  let path = ::std::env::temp_dir().join(format!("playform-settings-test-{}.txt", pid));
+  fs::File::create(&path).unwrap()
+    .write_all(b"# Far, coarse terrain.\nmax-load-distance = 120\nlg-edge-samples = 2, 1, 0\n").unwrap();
+
+  let args = vec!(
+    String::from("--lod-thresholds"), String::from("4,20,40"),
+    String::from("--seed"), String::from("3"),
+  );
+  let (settings, unknown) = parse_known(args.into_iter()).unwrap();
+  assert_eq!(settings.lod_thresholds, [4, 20, 40]);
+  assert_eq!(unknown, vec!(String::from("--seed"), String::from("3")));
+
+  // Only three LODs' worth of resolutions.
+  let args = vec!(String::from("--settings"), path.to_str().unwrap().to_owned());
+  assert!(parse(args.into_iter()).is_err());
+
+  fs::File::create(&path).unwrap()
+    .write_all(b"max-load-distance = 120 # blocks\nlg-edge-samples = 3,2,0,-1\n").unwrap();
+  let args = vec!(String::from("--settings"), path.to_str().unwrap().to_owned());
+  assert!(parse(args.into_iter()).is_err());
+
+  fs::File::create(&path).unwrap()
+    .write_all(b"max-load-distance = 120 # blocks\n\nlg-edge-samples = 3,2,1,0\n").unwrap();
+  let args = vec!(
+    String::from("--settings"), path.to_str().unwrap().to_owned(),
+    String::from("--terrain-byte-budget"), String::from("1000"),
+    String::from("--grass-density"), String::from("0.5"),
+  );
+  let settings = parse(args.into_iter()).unwrap();
+  assert_eq!(settings.max_load_distance, 120);
+  assert_eq!(settings.terrain_byte_budget, 1000);
+  assert_eq!(settings.grass_density, 0.5);
+  assert!(parse(vec!(String::from("--grass-density"), String::from("-1")).into_iter()).is_err());
+
+  assert!(parse(vec!(String::from("--lod-thresholds"), String::from("3,2,1")).into_iter()).is_err());
+
+  fs::remove_file(&path).unwrap();
+}
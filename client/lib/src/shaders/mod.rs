@@ -15,6 +15,8 @@ mod sky;
 mod dirt;
 mod grass;
 mod leaves;
+mod sand;
+mod snow;
 mod stone;
 
 use camera;
@@ -45,7 +47,7 @@ impl<'a> Shaders<'a> {
     let terrain_shader = self::terrain::TerrainShader::new(gl, near, far);
     let mob_shader = self::color::ColorShader::new(gl, near, far);
     let mut hud_color_shader = self::color::ColorShader::new(gl, 0.0, 1.0);
-    let texture_shader = self::texture::TextureShader::new(gl);
+    let mut texture_shader = self::texture::TextureShader::new(gl);
     let grass_billboard = self::grass_billboard::new(gl, near, far);
     let sky = self::sky::new(gl);
 
@@ -62,6 +64,11 @@ impl<'a> Shaders<'a> {
       gl,
       &hud_camera,
     );
+    camera::set_camera(
+      &mut texture_shader.shader,
+      gl,
+      &hud_camera,
+    );
 
     match gl.get_error() {
       gl::NO_ERROR => {},
@@ -0,0 +1,35 @@
+pub fn snow() -> String {
+  struct Wave {
+    freq: f32,
+    amp: f32,
+  }
+
+  let waves = [
+    Wave { freq: 1.0 / 4.0, amp: 1.0 },
+    Wave { freq:      16.0, amp: 0.2 },
+  ];
+
+  let mut contents = String::new();
+  for wave in &waves {
+    contents.push_str(format!(r#"
+    {{
+      float freq = {};
+      float amp = {};
+
+      float dnoise = cnoise(freq * world_position);
+      noise += dnoise * amp;
+      total_amp += amp;
+    }}
+    "#, wave.freq, wave.amp).as_str());
+  }
+
+  format!(r#"
+    float total_amp = 0.0;
+    float noise = 0.0;
+    {}
+    noise /= total_amp;
+    noise = (noise + 1) / 2;
+
+    return mix(vec3(0.85, 0.85, 0.9), vec3(1.0, 1.0, 1.0), noise);
+  "#, contents)
+}
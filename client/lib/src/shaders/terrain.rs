@@ -98,6 +98,14 @@ impl<'a> TerrainShader<'a> {
           {}
         }}
 
+        vec3 sand() {{
+          {}
+        }}
+
+        vec3 snow() {{
+          {}
+        }}
+
         // http://www.neilmendoza.com/glsl-rotation-about-an-arbitrary-axis/
         mat3 rotationMatrix(vec3 axis, float angle)
         {{
@@ -148,6 +156,17 @@ impl<'a> TerrainShader<'a> {
           }} else if (material == 5) {{
             base_color = vec4(0, 0, 0, 1);
             shininess = 40;
+          }} else if (material == 6) {{
+            base_color = vec4(0.1, 0.3, 0.6, 0.6);
+            shininess = 40;
+          }} else if (material == 7) {{
+            base_color = vec4(sand(), 1);
+            normal = bump_map(8, 8, normal);
+          }} else if (material == 8) {{
+            base_color = vec4(snow(), 1);
+            shininess = 40;
+          }} else if (material == 9) {{
+            base_color = vec4(dirt(), 1);
           }} else {{
             base_color = vec4(0.5, 0, 0.5, 0.5);
             shininess = 1;
@@ -175,6 +194,8 @@ impl<'a> TerrainShader<'a> {
         ::shaders::bark::bark(),
         ::shaders::leaves::leaves(),
         ::shaders::stone::stone(),
+        ::shaders::sand::sand(),
+        ::shaders::snow::snow(),
       )),
     );
     TerrainShader {
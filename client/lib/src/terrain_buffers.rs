@@ -2,12 +2,15 @@
 
 use gl;
 use gl::types::*;
-use cgmath::{Point3, Vector3};
+use cgmath::{Aabb3, Point3, Vector3};
+use std::f32;
 
 use common::entity_id;
 use common::fnv_map;
 use common::id_allocator;
 
+use block_position;
+use frustum;
 use shaders::terrain::TerrainShader;
 use terrain_mesh::Triangle;
 use yaglw::gl_context::GLContext;
@@ -20,18 +23,41 @@ const VERTICES_PER_TRIANGLE: u32 = 3;
 use std::mem;
 
 // VRAM bytes
-pub const BYTE_BUDGET: usize = 64_000_000;
 pub const POLYGON_COST: usize = 100;
-pub const POLYGON_BUDGET: usize = BYTE_BUDGET / POLYGON_COST;
+
+/// The number of polygons that fit in a VRAM budget, in bytes.
+pub fn polygon_budget(byte_budget: usize) -> usize {
+  byte_budget / POLYGON_COST
+}
+
+/// The extent of a block's triangles in VRAM.
+struct Block {
+  bounds: Aabb3<f32>,
+  /// Whether triangles have been removed since `bounds` was computed, so it might be too big.
+  bounds_stale: bool,
+  triangles: usize,
+}
+
+/// What `draw` drew last time, which is still right until the camera or the buffers change.
+struct Visible {
+  frustum: frustum::T,
+  firsts: Vec<GLint>,
+  counts: Vec<GLsizei>,
+}
 
 /// Struct for loading/unloading/maintaining terrain data in VRAM.
 pub struct TerrainBuffers<'a> {
   id_to_index: fnv_map::T<entity_id::T, usize>,
   index_to_id: Vec<entity_id::T>,
+  /// The block each triangle belongs to, ordered like `index_to_id`.
+  index_to_block: Vec<block_position::T>,
+  /// The bounds of each triangle, ordered like `index_to_id`.
+  index_to_bounds: Vec<Aabb3<f32>>,
+  blocks: block_position::map::T<Block>,
+  visible: Option<Visible>,
 
   // TODO: Use yaglw's ArrayHandle.
   empty_array: GLuint,
-  length: usize,
 
   // Per-triangle buffers
 
@@ -54,21 +80,25 @@ impl<'a> TerrainBuffers<'a> {
   #[allow(missing_docs)]
   pub fn new<'b>(
     gl: &'b mut GLContext,
+    polygon_budget: usize,
   ) -> Self where
     'a: 'b,
   {
     TerrainBuffers {
       id_to_index: fnv_map::new(),
       index_to_id: Vec::new(),
+      index_to_block: Vec::new(),
+      index_to_bounds: Vec::new(),
+      blocks: block_position::map::new(),
+      visible: None,
       empty_array: unsafe {
         let mut empty_array = 0;
         gl::GenVertexArrays(1, &mut empty_array);
         empty_array
       },
-      length: 0,
-      vertex_positions: BufferTexture::new(gl, gl::R32F, POLYGON_BUDGET),
-      normals: BufferTexture::new(gl, gl::R32F, POLYGON_BUDGET),
-      materials: BufferTexture::new(gl, gl::R32UI, POLYGON_BUDGET),
+      vertex_positions: BufferTexture::new(gl, gl::R32F, polygon_budget),
+      normals: BufferTexture::new(gl, gl::R32F, polygon_budget),
+      materials: BufferTexture::new(gl, gl::R32UI, polygon_budget),
     }
   }
 
@@ -97,10 +127,11 @@ impl<'a> TerrainBuffers<'a> {
     bind("materials", self.materials.handle.gl_id);
   }
 
-  /// Add a series of entites into VRAM.
+  /// Add a block's triangles into VRAM.
   pub fn push(
     &mut self,
     gl: &mut GLContext,
+    block_position: &block_position::T,
     vertices: &[Triangle<Point3<GLfloat>>],
     normals: &[Triangle<Vector3<GLfloat>>],
     ids: &[entity_id::T],
@@ -118,16 +149,25 @@ impl<'a> TerrainBuffers<'a> {
     let success = self.normals.buffer.push(gl, normals);
     assert!(success);
 
-    for &id in ids.iter() {
+    for (&id, triangle) in ids.iter().zip(vertices.iter()) {
       self.id_to_index.insert(id, self.index_to_id.len());
       self.index_to_id.push(id);
+      self.index_to_block.push(*block_position);
+      self.index_to_bounds.push(bounds_of(&[*triangle]));
     }
 
+    let bounds = bounds_of(vertices);
+    let block =
+      self.blocks.entry(*block_position)
+      .or_insert_with(|| Block { bounds: bounds, bounds_stale: false, triangles: 0 });
+    block.bounds = union(&block.bounds, &bounds);
+    block.triangles += ids.len();
+    self.visible = None;
+
     self.materials.buffer.byte_buffer.bind(gl);
     let success = self.materials.buffer.push(gl, materials);
     assert!(success);
 
-    self.length += VERTICES_PER_TRIANGLE as usize * ids.len();
   }
 
   // TODO: Make this take many ids as a parameter, to reduce `bind`s.
@@ -139,12 +179,23 @@ impl<'a> TerrainBuffers<'a> {
     self.index_to_id.swap_remove(idx);
     self.id_to_index.remove(&id);
 
+    self.index_to_bounds.swap_remove(idx);
+    let block_position = self.index_to_block.swap_remove(idx);
+    let block_is_empty = {
+      let block = self.blocks.get_mut(&block_position).unwrap();
+      block.triangles -= 1;
+      block.bounds_stale = true;
+      block.triangles == 0
+    };
+    if block_is_empty {
+      self.blocks.remove(&block_position);
+    }
+    self.visible = None;
+
     if id != swapped_id {
       self.id_to_index.insert(swapped_id, idx);
     }
 
-    self.length -= 3;
-
     self.vertex_positions.buffer.byte_buffer.bind(gl);
     self.vertex_positions.buffer.swap_remove(gl, idx, 1);
 
@@ -155,11 +206,153 @@ impl<'a> TerrainBuffers<'a> {
     self.materials.buffer.swap_remove(gl, idx, 1);
   }
 
-  /// Draw the terrain.
-  pub fn draw(&self, _gl: &mut GLContext) {
+  /// Draw the terrain in blocks that intersect `frustum`.
+  pub fn draw(&mut self, _gl: &mut GLContext, frustum: &frustum::T) {
+    let up_to_date = self.visible.as_ref().map_or(false, |visible| visible.frustum == *frustum);
+    if !up_to_date {
+      refresh_bounds(&mut self.blocks, &self.index_to_block, &self.index_to_bounds);
+
+      let mut visible = block_position::set::new();
+      for (block_position, block) in &self.blocks {
+        if frustum.intersects(&block.bounds) {
+          visible.insert(*block_position);
+        }
+      }
+
+      let (firsts, counts) = visible_ranges(&self.index_to_block, |b| visible.contains(b));
+      self.visible = Some(Visible { frustum: *frustum, firsts: firsts, counts: counts });
+    }
+
+    let visible = self.visible.as_ref().unwrap();
+    if visible.firsts.is_empty() {
+      return
+    }
+
     unsafe {
       gl::BindVertexArray(self.empty_array);
-      gl::DrawArrays(gl::TRIANGLES, 0, self.length as GLint);
+      gl::MultiDrawArrays(
+        gl::TRIANGLES,
+        visible.firsts.as_ptr(),
+        visible.counts.as_ptr(),
+        visible.firsts.len() as GLsizei,
+      );
     }
   }
 }
+
+fn bounds_of(vertices: &[Triangle<Point3<GLfloat>>]) -> Aabb3<f32> {
+  let mut min = Point3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+  let mut max = Point3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+  for triangle in vertices {
+    for v in &[triangle.v1, triangle.v2, triangle.v3] {
+      min = Point3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z));
+      max = Point3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z));
+    }
+  }
+  Aabb3 { min: min, max: max }
+}
+
+fn union(x: &Aabb3<f32>, y: &Aabb3<f32>) -> Aabb3<f32> {
+  Aabb3 {
+    min: Point3::new(x.min.x.min(y.min.x), x.min.y.min(y.min.y), x.min.z.min(y.min.z)),
+    max: Point3::new(x.max.x.max(y.max.x), x.max.y.max(y.max.y), x.max.z.max(y.max.z)),
+  }
+}
+
+/// Shrink the bounds of blocks that have lost triangles to fit the triangles they have left.
+fn refresh_bounds(
+  blocks: &mut block_position::map::T<Block>,
+  index_to_block: &[block_position::T],
+  index_to_bounds: &[Aabb3<f32>],
+) {
+  if !blocks.values().any(|block| block.bounds_stale) {
+    return
+  }
+
+  for block in blocks.values_mut().filter(|block| block.bounds_stale) {
+    block.bounds = bounds_of(&[]);
+  }
+  for (block_position, bounds) in index_to_block.iter().zip(index_to_bounds.iter()) {
+    let block = blocks.get_mut(block_position).unwrap();
+    if block.bounds_stale {
+      block.bounds = union(&block.bounds, bounds);
+    }
+  }
+  for block in blocks.values_mut() {
+    block.bounds_stale = false;
+  }
+}
+
+/// The first vertex and vertex count of each contiguous run of triangles in visible blocks.
+fn visible_ranges<IsVisible>(
+  index_to_block: &[block_position::T],
+  mut is_visible: IsVisible,
+) -> (Vec<GLint>, Vec<GLsizei>) where
+  IsVisible: FnMut(&block_position::T) -> bool,
+{
+  let mut firsts = Vec::new();
+  let mut counts = Vec::new();
+  let mut run_start = None;
+  // The visibility of the block of the triangle before this one.
+  let mut last: Option<(block_position::T, bool)> = None;
+  for (i, block_position) in index_to_block.iter().enumerate() {
+    let visible =
+      match last {
+        Some((last_block, visible)) if last_block == *block_position => visible,
+        _ => is_visible(block_position),
+      };
+    last = Some((*block_position, visible));
+
+    match (run_start, visible) {
+      (None, true) => run_start = Some(i),
+      (Some(start), false) => {
+        firsts.push((start * VERTICES_PER_TRIANGLE as usize) as GLint);
+        counts.push(((i - start) * VERTICES_PER_TRIANGLE as usize) as GLsizei);
+        run_start = None;
+      },
+      _ => {},
+    }
+  }
+  if let Some(start) = run_start {
+    firsts.push((start * VERTICES_PER_TRIANGLE as usize) as GLint);
+    counts.push(((index_to_block.len() - start) * VERTICES_PER_TRIANGLE as usize) as GLsizei);
+  }
+  (firsts, counts)
+}
+
+#[test]
+fn draws_runs_of_visible_triangles() {
+  let a = block_position::new(0, 0, 0);
+  let b = block_position::new(1, 0, 0);
+  let c = block_position::new(2, 0, 0);
+  // Swap removes leave blocks' triangles interleaved.
+  let index_to_block = [a, a, b, c, a, b, b, c];
+
+  let (firsts, counts) = visible_ranges(&index_to_block, |x| *x != b);
+  assert_eq!(firsts, vec!(0, 9, 21));
+  assert_eq!(counts, vec!(6, 6, 3));
+
+  let (firsts, counts) = visible_ranges(&index_to_block, |_| true);
+  assert_eq!(firsts, vec!(0));
+  assert_eq!(counts, vec!(24));
+
+  let (firsts, _) = visible_ranges(&index_to_block, |_| false);
+  assert!(firsts.is_empty());
+}
+
+#[test]
+fn removing_triangles_shrinks_block_bounds() {
+  let a = block_position::new(0, 0, 0);
+  let b = block_position::new(1, 0, 0);
+  let at = |x: f32| Aabb3 { min: Point3::new(x, 0.0, 0.0), max: Point3::new(x + 1.0, 1.0, 1.0) };
+
+  let mut blocks = block_position::map::new();
+  blocks.insert(a, Block { bounds: union(&at(0.0), &at(4.0)), bounds_stale: true, triangles: 1 });
+  blocks.insert(b, Block { bounds: at(8.0), bounds_stale: false, triangles: 1 });
+  // The triangle at x = 4 was removed from `a`.
+  refresh_bounds(&mut blocks, &[b, a], &[at(8.0), at(0.0)]);
+
+  assert_eq!(blocks[&a].bounds.max.x, 1.0);
+  assert!(!blocks[&a].bounds_stale);
+  assert_eq!(blocks[&b].bounds.min.x, 8.0);
+}
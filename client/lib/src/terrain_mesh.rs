@@ -1,12 +1,17 @@
 //! Data structure for a small block of terrain.
 
 use cgmath;
+use fnv;
 use cgmath::{Point, Point3, Vector3, Vector, EuclideanVector, Matrix, Rotation, Aabb, Aabb3};
 use isosurface_extraction::dual_contouring;
 use num::iter::range_inclusive;
 use rand;
 use rand::Rng;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::f32;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::sync::{Arc, Mutex};
 use stopwatch;
 
@@ -25,25 +30,60 @@ pub const LG_WIDTH: i16 = 3;
 /// The width of a block of terrain.
 pub const WIDTH: i32 = 1 << LG_WIDTH;
 
-/// lg(EDGE_SAMPLES)
+/// The default lg(edge samples) for each LOD.
 // NOTE: If there are duplicates here, weird invariants will fail.
 // Just remove the LODs if you don't want duplicates.
-pub const LG_EDGE_SAMPLES: [u16; LOD_COUNT] = [3, 2, 1, 0];
-/// The number of voxels along an axis within a block, indexed by LOD.
-pub const EDGE_SAMPLES: [u16; LOD_COUNT] = [
-  1 << LG_EDGE_SAMPLES[0],
-  1 << LG_EDGE_SAMPLES[1],
-  1 << LG_EDGE_SAMPLES[2],
-  1 << LG_EDGE_SAMPLES[3],
-];
+pub const DEFAULT_LG_EDGE_SAMPLES: [u16; LOD_COUNT] = [3, 2, 1, 0];
 
-/// The width of a voxel within a block, indexed by LOD.
-pub const LG_SAMPLE_SIZE: [i16; LOD_COUNT] = [
-  LG_WIDTH - LG_EDGE_SAMPLES[0] as i16,
-  LG_WIDTH - LG_EDGE_SAMPLES[1] as i16,
-  LG_WIDTH - LG_EDGE_SAMPLES[2] as i16,
-  LG_WIDTH - LG_EDGE_SAMPLES[3] as i16,
-];
+/// How finely blocks are sampled at each LOD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolutions {
+  lg_edge_samples: [u16; LOD_COUNT],
+}
+
+/// Construct `Resolutions` from lg(edge samples) for each LOD.
+/// Each LOD has to be coarser than the one before it, and no finer than one sample per unit.
+pub fn resolutions(lg_edge_samples: [u16; LOD_COUNT]) -> Result<Resolutions, String> {
+  if lg_edge_samples[0] > LG_WIDTH as u16 {
+    return Err(format!("Blocks can have at most 2^{} samples along an edge", LG_WIDTH))
+  }
+  for lod in 1 .. LOD_COUNT {
+    if lg_edge_samples[lod] >= lg_edge_samples[lod - 1] {
+      return Err(format!("Each LOD must have fewer samples than the one before it: {:?}", lg_edge_samples))
+    }
+  }
+  Ok(Resolutions { lg_edge_samples: lg_edge_samples })
+}
+
+impl Default for Resolutions {
+  fn default() -> Resolutions {
+    resolutions(DEFAULT_LG_EDGE_SAMPLES).unwrap()
+  }
+}
+
+impl Resolutions {
+  /// lg(edge_samples(lod))
+  pub fn lg_edge_samples(&self, lod: lod::T) -> u16 {
+    self.lg_edge_samples[lod.0 as usize]
+  }
+
+  /// The number of voxels along an axis within a block at an LOD.
+  pub fn edge_samples(&self, lod: lod::T) -> u16 {
+    1 << self.lg_edge_samples(lod)
+  }
+
+  /// The width of a voxel within a block at an LOD.
+  pub fn lg_sample_size(&self, lod: lod::T) -> i16 {
+    LG_WIDTH - self.lg_edge_samples(lod) as i16
+  }
+
+  /// The LOD whose voxels have a given size, if any.
+  pub fn lod_of_lg_sample_size(&self, lg_size: i16) -> Option<lod::T> {
+    (0 .. LOD_COUNT as u32)
+      .map(lod::T)
+      .find(|&lod| self.lg_sample_size(lod) == lg_size)
+  }
+}
 
 #[derive(Debug, Copy, Clone, RustcEncodable, RustcDecodable)]
 /// [T; 3], but serializable.
@@ -121,6 +161,31 @@ pub fn voxels_in(bounds: &Aabb3<i32>, lg_size: i16) -> Vec<voxel::bounds::T> {
   voxels
 }
 
+/// The voxels `generate` needs to mesh a block at a given LOD.
+pub fn voxels_for_block(
+  resolutions: &Resolutions,
+  block_position: &block_position::T,
+  lod: lod::T,
+) -> Vec<voxel::bounds::T> {
+  let lg_size = resolutions.lg_sample_size(lod);
+  let voxel_size = 1 << lg_size;
+  voxels_in(
+    &Aabb3::new(
+      Point3::new(
+        (block_position.as_pnt().x << LG_WIDTH) - voxel_size,
+        (block_position.as_pnt().y << LG_WIDTH) - voxel_size,
+        (block_position.as_pnt().z << LG_WIDTH) - voxel_size,
+      ),
+      Point3::new(
+        ((block_position.as_pnt().x + 1) << LG_WIDTH) + voxel_size,
+        ((block_position.as_pnt().y + 1) << LG_WIDTH) + voxel_size,
+        ((block_position.as_pnt().z + 1) << LG_WIDTH) + voxel_size,
+      ),
+    ),
+    lg_size,
+  )
+}
+
 mod voxel_storage {
   use isosurface_extraction::dual_contouring;
 
@@ -161,11 +226,69 @@ mod voxel_storage {
   }
 }
 
-fn place_grass<T, Rng: rand::Rng>(
-  polygon: &dual_contouring::polygon::T<T>,
-  rng: &mut Rng,
+/// Where grass grows, and how much.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrassRules {
+  /// Scales how many tufts grow on each triangle.
+  pub density: f32,
+  /// Seeds grass placement, so everyone in a world sees the same grass.
+  pub seed: u64,
+}
+
+impl Default for GrassRules {
+  fn default() -> GrassRules {
+    GrassRules {
+      density: 1.0,
+      seed: 0,
+    }
+  }
+}
+
+/// How many tufts of grass grow on a triangle of a material, on flat ground.
+/// Only `Terrain` is grassy.
+fn material_grass_density(material: voxel::Material) -> f32 {
+  match material {
+    voxel::Material::Terrain => 1.0,
+    _ => 0.0,
+  }
+}
+
+/// Scales grass density by slope: full up to about 35 degrees, thinning out to none at about 65.
+fn slope_grass_density(normal: &Vector3<f32>) -> f32 {
+  ((normal.y - 0.4) / 0.4).max(0.0).min(1.0)
+}
+
+/// An RNG that gives the same numbers for a triangle whenever and wherever it's meshed.
+fn triangle_rng(v: &[Point3<f32>; 3], seed: u64) -> rand::XorShiftRng {
+  let mut hasher = fnv::FnvHasher::default();
+  seed.hash(&mut hasher);
+  for p in v {
+    for &x in &[p.x, p.y, p.z] {
+      ((x * 256.0).round() as i32).hash(&mut hasher);
+    }
+  }
+  let hash = hasher.finish();
+  // XorShift can't be seeded with all zeros.
+  rand::SeedableRng::from_seed([hash as u32, (hash >> 32) as u32, 0x9e3779b9, 0x7f4a7c15])
+}
+
+fn place_grass(
+  v: &[Point3<f32>; 3],
+  material: voxel::Material,
+  rules: &GrassRules,
 ) -> Vec<Grass> {
-  let v = &polygon.vertices;
+  let normal = v[1].sub_p(&v[0]).cross(&v[2].sub_p(&v[0])).normalize();
+  // Degenerate triangles have NaN normals, which `max` turns into no grass.
+  let density = rules.density * material_grass_density(material) * slope_grass_density(&normal);
+  let mut rng = triangle_rng(v, rules.seed);
+  let tufts = density.floor() as u32 + if rng.next_f32() < density.fract() { 1 } else { 0 };
+  (0 .. tufts).map(|_| place_tuft(v, &mut rng)).collect()
+}
+
+fn place_tuft<Rng: rand::Rng>(
+  v: &[Point3<f32>; 3],
+  rng: &mut Rng,
+) -> Grass {
   let normal = v[1].sub_p(&v[0]).cross(&v[2].sub_p(&v[0]));
   let to_middle =
     &v[0].to_vec()
@@ -250,30 +373,359 @@ fn place_grass<T, Rng: rand::Rng>(
     rng.gen_range(0, 9),
     rng.gen_range(0, 9),
   ];
-  vec!(
-    Grass {
-      model_matrix: model,
-      normal: normal,
-      tex_ids: billboard_indices,
+  Grass {
+    model_matrix: model,
+    normal: normal,
+    tex_ids: billboard_indices,
+  }
+}
+
+/// Offsets to the blocks below a block along one or two axes. The polygons joining a block to
+/// these neighbours are part of the block's mesh, so it has to know their LODs to join up with them.
+pub const LOWER_NEIGHBOURS: [[i32; 3]; 6] = [
+  [-1, 0, 0], [0, -1, 0], [0, 0, -1],
+  [-1, -1, 0], [-1, 0, -1], [0, -1, -1],
+];
+
+/// The LODs of a block's `LOWER_NEIGHBOURS`, as far as its mesh is concerned.
+pub type NeighbourLods = [lod::T; 6];
+
+/// `NeighbourLods` for a block whose neighbours are all at its own LOD.
+pub fn uniform_lods(lod: lod::T) -> NeighbourLods {
+  [lod; 6]
+}
+
+/// The `i`th of a block's `LOWER_NEIGHBOURS`.
+pub fn lower_neighbour(block_position: &block_position::T, i: usize) -> block_position::T {
+  let offset = LOWER_NEIGHBOURS[i];
+  *block_position + Vector3::new(offset[0], offset[1], offset[2])
+}
+
+/// The block that has this one as its `i`th lower neighbour.
+pub fn upper_neighbour(block_position: &block_position::T, i: usize) -> block_position::T {
+  let offset = LOWER_NEIGHBOURS[i];
+  *block_position + Vector3::new(-offset[0], -offset[1], -offset[2])
+}
+
+/// The voxels `generate` needs to mesh a block at a given LOD, next to neighbours at `neighbour_lods`.
+pub fn voxels_for_mesh(
+  resolutions: &Resolutions,
+  block_position: &block_position::T,
+  lod: lod::T,
+  neighbour_lods: &NeighbourLods,
+) -> Vec<voxel::bounds::T> {
+  let mut voxels = voxels_for_block(resolutions, block_position, lod);
+  for (i, &neighbour_lod) in neighbour_lods.iter().enumerate() {
+    if neighbour_lod != lod {
+      voxels.extend(voxels_for_block(resolutions, &lower_neighbour(block_position, i), neighbour_lod));
     }
-  )
+  }
+  voxels
 }
 
-pub fn generate<Rng: rand::Rng>(
+/// The axes perpendicular to `axis`, ordered so that they're right-handed with it.
+fn perpendicular(axis: usize) -> (usize, usize) {
+  match axis {
+    0 => (1, 2),
+    1 => (2, 0),
+    _ => (0, 1),
+  }
+}
+
+fn axis_of(direction: dual_contouring::edge::Direction) -> usize {
+  match direction {
+    dual_contouring::edge::Direction::X => 0,
+    dual_contouring::edge::Direction::Y => 1,
+    dual_contouring::edge::Direction::Z => 2,
+  }
+}
+
+/// The LODs of the blocks around a block, by their offset from it.
+struct LodsAround<'a> {
+  lod: lod::T,
+  neighbour_lods: &'a NeighbourLods,
+}
+
+impl<'a> LodsAround<'a> {
+  fn at(&self, offset: [i32; 3]) -> lod::T {
+    if offset == [0, 0, 0] {
+      return self.lod
+    }
+    match LOWER_NEIGHBOURS.iter().position(|&o| o == offset) {
+      None => panic!("Block offset {:?} isn't a lower neighbour", offset),
+      Some(i) => self.neighbour_lods[i],
+    }
+  }
+
+  /// The points just inside the four cells around the edge of direction `axis` starting at world
+  /// coordinates `p`, in order around the axis, along with the LODs of the blocks they're in.
+  fn quadrants(
+    &self,
+    block_position: &block_position::T,
+    p: [i32; 3],
+    axis: usize,
+  ) -> [([i32; 3], lod::T); 4] {
+    let (u, v) = perpendicular(axis);
+    let b = block_position.as_pnt();
+    let b = [b.x, b.y, b.z];
+    let quadrant = |du, dv| {
+      let mut q = p;
+      q[u] += du;
+      q[v] += dv;
+      let offset = [(q[0] >> LG_WIDTH) - b[0], (q[1] >> LG_WIDTH) - b[1], (q[2] >> LG_WIDTH) - b[2]];
+      (q, self.at(offset))
+    };
+    [quadrant(-1, -1), quadrant(0, -1), quadrant(0, 0), quadrant(-1, 0)]
+  }
+}
+
+fn voxel_at(voxels: &voxel::tree::T, p: [i32; 3], lg_size: i16) -> Option<&voxel::T> {
+  voxels.get(&voxel::bounds::new(p[0] >> lg_size, p[1] >> lg_size, p[2] >> lg_size, lg_size))
+}
+
+/// Mesh the edges along a block's lower faces where it meets neighbours at other LODs.
+///
+/// Each edge is taken at the finest LOD of the cells around it, and joins up the vertices of those
+/// cells at their own LODs, the same way dual contouring an octree does. Where a coarser cell
+/// borders several finer ones, its vertex is shared by several polygons, which come out as
+/// triangles instead of quads. Since every block meets the neighbours below it the same way, the
+/// seams between LODs end up watertight.
+fn transition_polygons<AddPolygon>(
   voxels: &voxel::tree::T,
+  resolutions: &Resolutions,
+  block_position: &block_position::T,
+  lods: &LodsAround,
+  add_polygon: &mut AddPolygon,
+) where
+  AddPolygon: FnMut([Point3<f32>; 3], [Vector3<f32>; 3], voxel::Material),
+{
+  let lg_finest =
+    lods.neighbour_lods.iter()
+    .map(|&lod| resolutions.lg_sample_size(lod))
+    .fold(resolutions.lg_sample_size(lods.lod), cmp::min);
+  let step = 1 << lg_finest;
+  let low = block_position.as_pnt();
+  let low = [low.x << LG_WIDTH, low.y << LG_WIDTH, low.z << LG_WIDTH];
+
+  for axis in 0 .. 3 {
+    let (u, v) = perpendicular(axis);
+    for i in 0 .. WIDTH / step {
+    for j in 0 .. WIDTH / step {
+    'edges: for k in 0 .. WIDTH / step {
+      let p = [low[0] + i * step, low[1] + j * step, low[2] + k * step];
+      if p[u] != low[u] && p[v] != low[v] {
+        // Edges away from the lower faces are all inside this block.
+        continue
+      }
+
+      let quadrants = lods.quadrants(block_position, p, axis);
+      if quadrants.iter().all(|&(_, lod)| lod == lods.lod) {
+        // Dual contouring took care of this one.
+        continue
+      }
+
+      let lg_size =
+        quadrants.iter()
+        .map(|&(_, lod)| resolutions.lg_sample_size(lod))
+        .fold(i16::max_value(), cmp::min);
+      let mask = (1 << lg_size) - 1;
+      if p.iter().any(|&x| x & mask != 0) {
+        // Not an edge at this resolution.
+        continue
+      }
+
+      let mut end = p;
+      end[axis] += 1 << lg_size;
+      let materials =
+        match (voxel_at(voxels, p, lg_size), voxel_at(voxels, end, lg_size)) {
+          (Some(low), Some(high)) => (corner_material(low), corner_material(high)),
+          _ => {
+            warn!("Missing voxels for the edge at {:?} in {:?}", p, block_position);
+            continue
+          },
+        };
+      let (material, facing_up) =
+        match (materials.0.is_solid(), materials.1.is_solid()) {
+          (true, false) => (materials.0, true),
+          (false, true) => (materials.1, false),
+          _ => continue,
+        };
+
+      // The vertices of the distinct cells around the edge.
+      let mut cells: Vec<(voxel::bounds::T, Point3<f32>, Vector3<f32>)> = Vec::with_capacity(4);
+      for &(q, lod) in quadrants.iter() {
+        let lg_size = resolutions.lg_sample_size(lod);
+        let bounds = voxel::bounds::new(q[0] >> lg_size, q[1] >> lg_size, q[2] >> lg_size, lg_size);
+        if cells.last().map_or(false, |&(ref last, _, _)| *last == bounds) {
+          continue
+        }
+        match voxels.get(&bounds) {
+          Some(&voxel::Surface(ref surface)) =>
+            cells.push((bounds, surface.surface_vertex.to_world_vertex(&bounds), surface.normal.to_float_normal())),
+          _ => {
+            // The coarser cell missed a crossing that the finer ones saw.
+            debug!("No surface in {:?} for the edge at {:?}", bounds, p);
+            continue 'edges
+          },
+        }
+      }
+      if cells.len() > 1 && cells[0].0 == cells[cells.len() - 1].0 {
+        cells.pop();
+      }
+      if cells.len() < 3 {
+        continue
+      }
+
+      // `quadrants` go counterclockwise around the axis, so this faces along it.
+      if !facing_up {
+        cells.reverse();
+      }
+      for t in 1 .. cells.len() - 1 {
+        add_polygon(
+          [cells[0].1, cells[t].1, cells[t + 1].1],
+          [cells[0].2, cells[t].2, cells[t + 1].2],
+          material,
+        );
+      }
+    }}}
+  }
+}
+
+fn corner_material(voxel: &voxel::T) -> voxel::Material {
+  match *voxel {
+    voxel::Surface(ref voxel) => voxel.corner,
+    voxel::Volume(material) => material,
+  }
+}
+
+/// Mesh the faces between water and air in the cells from `low` up to `high`, in samples.
+///
+/// Dual contouring only finds surfaces of opaque materials, and water isn't one, so the top of a
+/// lake would otherwise be invisible. Water is flat, so its cells' faces are good enough.
+fn water_polygons<AddPolygon>(
+  voxels: &voxel::tree::T,
+  lg_sample_size: i16,
+  low: Point3<i32>,
+  high: Point3<i32>,
+  add_polygon: &mut AddPolygon,
+) where
+  AddPolygon: FnMut([Point3<f32>; 3], [Vector3<f32>; 3], voxel::Material),
+{
+  let size = (1 << lg_sample_size) as f32;
+  let material_at = |p: [i32; 3]| {
+    voxels.get(&voxel::bounds::new(p[0], p[1], p[2], lg_sample_size)).map(corner_material)
+  };
+
+  for x in low.x .. high.x {
+  for y in low.y .. high.y {
+  for z in low.z .. high.z {
+    let p = [x, y, z];
+    if material_at(p) != Some(voxel::Material::Water) {
+      continue
+    }
+
+    for axis in 0 .. 3 {
+    for &side in [-1, 1].iter() {
+      let mut neighbour = p;
+      neighbour[axis] += side;
+      // Faces are only meshed by the water side, so nothing is meshed twice.
+      if material_at(neighbour) != Some(voxel::Material::Empty) {
+        continue
+      }
+
+      let (u, v) = perpendicular(axis);
+      let mut corner = [p[0] as f32 * size, p[1] as f32 * size, p[2] as f32 * size];
+      if side > 0 {
+        corner[axis] += size;
+      }
+      let vertex = |du: f32, dv: f32| {
+        let mut c = corner;
+        c[u] += du;
+        c[v] += dv;
+        Point3::new(c[0], c[1], c[2])
+      };
+      let mut normal = [0.0, 0.0, 0.0];
+      normal[axis] = side as f32;
+      let normal = Vector3::new(normal[0], normal[1], normal[2]);
+
+      // Counterclockwise from the air side.
+      let mut quad = [vertex(0.0, 0.0), vertex(size, 0.0), vertex(size, size), vertex(0.0, size)];
+      if side < 0 {
+        quad.reverse();
+      }
+      add_polygon([quad[0], quad[1], quad[2]], [normal, normal, normal], voxel::Material::Water);
+      add_polygon([quad[0], quad[2], quad[3]], [normal, normal, normal], voxel::Material::Water);
+    }}
+  }}}
+}
+
+/// Mesh a block. Triangles that are identical to ones in `prev`, an earlier mesh of the same
+/// block, keep their IDs and grass, and only new triangles and grass are given new IDs. Pass
+/// `&empty()` for a block that hasn't been meshed before.
+///
+/// The whole block is extracted either way; reusing `prev` only saves IDs, and lets `changes`
+/// find the triangles that a remesh didn't touch.
+pub fn generate(
+  voxels: &voxel::tree::T,
+  resolutions: &Resolutions,
   block_position: &block_position::T,
   lod: lod::T,
+  neighbour_lods: &NeighbourLods,
+  prev: &T,
   id_allocator: &Mutex<id_allocator::T<entity_id::T>>,
-  rng: &mut Rng,
+  grass_rules: &GrassRules,
 ) -> T
 {
   stopwatch::time("terrain_mesh::generate", || {
+    let mut reusable = HashMap::new();
+    for i in 0 .. prev.ids.len() {
+      let v = &prev.vertex_coordinates[i];
+      reusable.entry(triangle_key(&[v.v1, v.v2, v.v3], prev.materials[i])).or_insert_with(Vec::new).push(i);
+    }
+    let mut prev_grass = HashMap::new();
+    for (i, polygon) in prev.grass_polygons.iter().enumerate() {
+      prev_grass.entry(*polygon).or_insert_with(Vec::new).push(i);
+    }
+
     let mut block = empty();
     {
       let block2 = Arc::make_mut(&mut block);
 
-      let lg_edge_samples = LG_EDGE_SAMPLES[lod.0 as usize];
-      let lg_sample_size = LG_SAMPLE_SIZE[lod.0 as usize];
+      let lg_edge_samples = resolutions.lg_edge_samples(lod);
+      let lg_sample_size = resolutions.lg_sample_size(lod);
+      let lods = LodsAround { lod: lod, neighbour_lods: neighbour_lods };
+
+      let mut add_polygon = |vertices: [Point3<f32>; 3], normals: [Vector3<f32>; 3], material: voxel::Material| {
+        let reused =
+          reusable.get_mut(&triangle_key(&vertices, material as i32))
+          .and_then(|matches| matches.pop());
+        let id =
+          match reused {
+            Some(i) => prev.ids[i],
+            None => id_allocator::allocate(id_allocator),
+          };
+
+        block2.vertex_coordinates.push(tri(vertices[0], vertices[1], vertices[2]));
+        block2.normals.push(tri(normals[0], normals[1], normals[2]));
+        block2.materials.push(material as i32);
+        block2.ids.push(id);
+        block2.bounds.push((id, make_bounds(&vertices[0], &vertices[1], &vertices[2])));
+
+        if reused.is_some() {
+          for &i in prev_grass.get(&id).into_iter().flat_map(|grass| grass.iter()) {
+            block2.grass.push(prev.grass[i].clone());
+            block2.grass_ids.push(prev.grass_ids[i]);
+            block2.grass_polygons.push(id);
+          }
+        } else if lod <= lod::T(1) {
+          for grass in place_grass(&vertices, material, grass_rules) {
+            let grass_id = id_allocator::allocate(id_allocator);
+            block2.grass.push(grass);
+            block2.grass_ids.push(grass_id);
+            block2.grass_polygons.push(id);
+          }
+        }
+      };
 
       let low = *block_position.as_pnt();
       let high = low.add_v(&Vector3::new(1, 1, 1));
@@ -298,6 +750,13 @@ pub fn generate<Rng: rand::Rng>(
           for x in range_inclusive(low_x, high_x) {
           for y in range_inclusive(low_y, high_y) {
           for z in range_inclusive(low_z, high_z) {
+            let p = [x << lg_sample_size, y << lg_sample_size, z << lg_sample_size];
+            let quadrants = lods.quadrants(block_position, p, axis_of(direction));
+            if quadrants.iter().any(|&(_, quadrant_lod)| quadrant_lod != lod) {
+              // This edge borders another LOD; see `transition_polygons`.
+              continue
+            }
+
             trace!("edge: {:?} {:?}", direction, Point3::new(x, y, z));
             let edge =
               dual_contouring::edge::T {
@@ -311,22 +770,9 @@ pub fn generate<Rng: rand::Rng>(
                 &mut voxel_storage::T { voxels: voxels },
                 &edge,
                 &mut |polygon: dual_contouring::polygon::T<voxel::Material>| {
-                  let id = id_allocator::allocate(id_allocator);
-
-                  block2.vertex_coordinates.push(tri(polygon.vertices[0], polygon.vertices[1], polygon.vertices[2]));
-                  block2.normals.push(tri(polygon.normals[0], polygon.normals[1], polygon.normals[2]));
-                  block2.materials.push(polygon.material as i32);
-                  block2.ids.push(id);
                   let v = &polygon.vertices;
-                  block2.bounds.push((id, make_bounds(&v[0], &v[1], &v[2])));
-
-                  if polygon.material == voxel::Material::Terrain && lod <= lod::T(1) {
-                    for grass in place_grass(&polygon, rng) {
-                      let id = id_allocator::allocate(id_allocator);
-                      block2.grass.push(grass);
-                      block2.grass_ids.push(id);
-                    }
-                  }
+                  let n = &polygon.normals;
+                  add_polygon([v[0], v[1], v[2]], [n[0], n[1], n[2]], polygon.material);
                 }
               );
           }}}
@@ -351,6 +797,12 @@ pub fn generate<Rng: rand::Rng>(
           low.z, high.z - 1,
         );
       }
+
+      if neighbour_lods.iter().any(|&neighbour_lod| neighbour_lod != lod) {
+        transition_polygons(voxels, resolutions, block_position, &lods, &mut add_polygon);
+      }
+
+      water_polygons(voxels, lg_sample_size, low, high, &mut add_polygon);
     }
     block
   })
@@ -382,6 +834,8 @@ pub struct T2 {
 
   pub grass: Vec<Grass>,
   pub grass_ids: Vec<entity_id::T>,
+  /// The ID of the triangle each tuft of grass grows on.
+  pub grass_polygons: Vec<entity_id::T>,
 }
 
 pub type T = Arc<T2>;
@@ -398,5 +852,248 @@ pub fn empty() -> T {
 
     grass: Vec::new(),
     grass_ids: Vec::new(),
+    grass_polygons: Vec::new(),
   })
 }
+
+/// What changed between two meshes of the same block.
+pub struct Changes {
+  /// The triangles and grass that are new, with their IDs.
+  pub added: T,
+  /// IDs of triangles that are gone.
+  pub removed_ids: Vec<entity_id::T>,
+  /// IDs of grass that's gone.
+  pub removed_grass_ids: Vec<entity_id::T>,
+}
+
+/// Identifies a triangle by its exact contents. Meshing the same voxels gives bitwise identical
+/// triangles, so this finds the triangles a remesh didn't touch.
+fn triangle_key(v: &[Point3<f32>; 3], material: i32) -> [u32; 10] {
+  let bits = |x: f32| -> u32 { unsafe { mem::transmute(x) } };
+  [
+    bits(v[0].x), bits(v[0].y), bits(v[0].z),
+    bits(v[1].x), bits(v[1].y), bits(v[1].z),
+    bits(v[2].x), bits(v[2].y), bits(v[2].z),
+    material as u32,
+  ]
+}
+
+/// What changed from `prev` to `next`, a mesh of the same block generated from it, judging by
+/// their triangles' IDs.
+pub fn changes(prev: &T, next: &T) -> Changes {
+  let prev_ids: HashSet<entity_id::T> = prev.ids.iter().cloned().collect();
+  let next_ids: HashSet<entity_id::T> = next.ids.iter().cloned().collect();
+
+  let mut added = empty();
+  {
+    let added = Arc::make_mut(&mut added);
+    for i in 0 .. next.ids.len() {
+      if prev_ids.contains(&next.ids[i]) {
+        continue
+      }
+      added.vertex_coordinates.push(next.vertex_coordinates[i]);
+      added.normals.push(next.normals[i]);
+      added.ids.push(next.ids[i]);
+      added.materials.push(next.materials[i]);
+      added.bounds.push(next.bounds[i]);
+    }
+    for i in 0 .. next.grass_ids.len() {
+      if prev_ids.contains(&next.grass_polygons[i]) {
+        continue
+      }
+      added.grass.push(next.grass[i].clone());
+      added.grass_ids.push(next.grass_ids[i]);
+      added.grass_polygons.push(next.grass_polygons[i]);
+    }
+  }
+
+  Changes {
+    added: added,
+    removed_ids: prev.ids.iter().filter(|id| !next_ids.contains(*id)).cloned().collect(),
+    removed_grass_ids:
+      prev.grass_ids.iter().zip(prev.grass_polygons.iter())
+      .filter(|&(_, polygon)| !next_ids.contains(polygon))
+      .map(|(id, _)| *id)
+      .collect(),
+  }
+}
+
+/// Solid below the plane y = 3.3 + 0.1x + 0.15z.
+#[cfg(test)]
+struct TiltedPlane;
+
+#[cfg(test)]
+impl voxel::field::T for TiltedPlane {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    3.3 + 0.1 * p.x + 0.15 * p.z - p.y
+  }
+
+  fn normal(&mut self, _: &Point3<f32>) -> Vector3<f32> {
+    Vector3::new(-0.1, 1.0, -0.15).normalize()
+  }
+}
+
+#[cfg(test)]
+impl voxel::mosaic::T<voxel::Material> for TiltedPlane {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if voxel::field::T::density(self, p) >= 0.0 {
+      Some(voxel::Material::Terrain)
+    } else {
+      Some(voxel::Material::Empty)
+    }
+  }
+}
+
+/// How many times each directed edge is used by the triangles in `meshes`.
+#[cfg(test)]
+fn directed_edges(meshes: &[T]) -> ::std::collections::HashMap<([i64; 3], [i64; 3]), u32> {
+  let key = |p: &Point3<f32>| [(p.x * 1024.0).round() as i64, (p.y * 1024.0).round() as i64, (p.z * 1024.0).round() as i64];
+  let mut edges = ::std::collections::HashMap::new();
+  for mesh in meshes {
+    for t in &mesh.vertex_coordinates {
+      for &(a, b) in &[(t.v1, t.v2), (t.v2, t.v3), (t.v3, t.v1)] {
+        *edges.entry((key(&a), key(&b))).or_insert(0) += 1;
+      }
+    }
+  }
+  edges
+}
+
+#[test]
+fn lod_seams_are_watertight() {
+  let resolutions: Resolutions = Default::default();
+  let fine = block_position::new(0, 0, 0);
+  let coarse = block_position::new(1, 0, 0);
+  let fine_lod = lod::T(0);
+  let coarse_lod = lod::T(1);
+  // `fine` is the first of `coarse`'s lower neighbours.
+  let mut coarse_neighbours = uniform_lods(coarse_lod);
+  coarse_neighbours[0] = fine_lod;
+
+  let mut voxels = voxel::tree::new();
+  let mut needed = voxels_for_mesh(&resolutions, &fine, fine_lod, &uniform_lods(fine_lod));
+  needed.extend(voxels_for_mesh(&resolutions, &coarse, coarse_lod, &coarse_neighbours));
+  for bounds in &needed {
+    let voxel = voxel::unwrap(voxel::of_field(&mut TiltedPlane, bounds));
+    voxels.get_mut_or_create(bounds).force_branches().data = Some(voxel);
+  }
+
+  let mesh = |block_position: &block_position::T, lod, neighbour_lods: &NeighbourLods| {
+    let id_allocator = Mutex::new(id_allocator::new());
+    generate(&voxels, &resolutions, block_position, lod, neighbour_lods, &empty(), &id_allocator, &Default::default())
+  };
+
+  // Edges that only one triangle uses, away from where the two blocks' meshes end.
+  let open_edges = |meshes: &[T]| {
+    let edges = directed_edges(meshes);
+    let inside = |p: &[i64; 3]| 0 < p[0] && p[0] < 14 * 1024 && 0 < p[2] && p[2] < 6 * 1024;
+    edges.keys()
+      .filter(|&&(ref a, ref b)| inside(a) && inside(b))
+      .filter(|&&(a, b)| edges.get(&(b, a)) != Some(&1) || edges[&(a, b)] != 1)
+      .count()
+  };
+
+  let fine_mesh = mesh(&fine, fine_lod, &uniform_lods(fine_lod));
+  assert!(!fine_mesh.ids.is_empty());
+  let seamless = open_edges(&[fine_mesh.clone(), mesh(&coarse, coarse_lod, &coarse_neighbours)]);
+  assert_eq!(seamless, 0);
+
+  // Meshing the coarse block as if its neighbour were at its own LOD leaves cracks.
+  let cracked = open_edges(&[fine_mesh, mesh(&coarse, coarse_lod, &uniform_lods(coarse_lod))]);
+  assert!(cracked > 0);
+}
+
+/// `TiltedPlane`, with a small dent dug into it around (4.5, 4.4, 4.5).
+#[cfg(test)]
+struct Dented;
+
+#[cfg(test)]
+impl voxel::field::T for Dented {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let distance = p.sub_p(&Point3::new(4.5, 4.4, 4.5)).length();
+    voxel::field::T::density(&mut TiltedPlane, p) - f32::max(0.0, 0.8 - distance)
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    voxel::field::T::normal(&mut TiltedPlane, p)
+  }
+}
+
+#[cfg(test)]
+impl voxel::mosaic::T<voxel::Material> for Dented {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if voxel::field::T::density(self, p) >= 0.0 {
+      Some(voxel::Material::Terrain)
+    } else {
+      Some(voxel::Material::Empty)
+    }
+  }
+}
+
+#[test]
+fn remeshing_reuses_unchanged_triangles() {
+  let resolutions: Resolutions = Default::default();
+  let block = block_position::new(0, 0, 0);
+  let lod = lod::T(0);
+  let id_allocator = Mutex::new(id_allocator::new());
+
+  let mesh = |dented: bool, prev: &T| {
+    let mut voxels = voxel::tree::new();
+    for bounds in voxels_for_block(&resolutions, &block, lod) {
+      let voxel =
+        if dented {
+          voxel::unwrap(voxel::of_field(&mut Dented, &bounds))
+        } else {
+          voxel::unwrap(voxel::of_field(&mut TiltedPlane, &bounds))
+        };
+      voxels.get_mut_or_create(&bounds).force_branches().data = Some(voxel);
+    }
+    generate(&voxels, &resolutions, &block, lod, &uniform_lods(lod), prev, &id_allocator, &Default::default())
+  };
+
+  let flat = mesh(false, &empty());
+  let before = id_allocator::allocate(&id_allocator);
+  let same = mesh(false, &flat);
+  // Nothing new needed an ID.
+  assert!(id_allocator::allocate(&id_allocator) == before + 1);
+  assert_eq!(same.ids, flat.ids);
+  assert_eq!(same.grass_ids, flat.grass_ids);
+  let diff = changes(&flat, &same);
+  assert!(diff.added.ids.is_empty());
+  assert!(diff.removed_ids.is_empty());
+  assert!(diff.removed_grass_ids.is_empty());
+
+  let dented = mesh(true, &flat);
+  let diff = changes(&flat, &dented);
+  assert!(!diff.added.ids.is_empty());
+  assert!(!diff.removed_ids.is_empty());
+  // Most of the block is untouched.
+  assert!(diff.added.ids.len() * 2 < dented.ids.len());
+  for id in &diff.removed_ids {
+    assert!(flat.ids.contains(id));
+    assert!(!dented.ids.contains(id));
+  }
+  for id in &diff.added.ids {
+    assert!(dented.ids.contains(id));
+    assert!(!flat.ids.contains(id));
+  }
+}
+
+#[test]
+fn grass_is_seeded_by_position_and_world() {
+  let flat = [Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 1.0), Point3::new(1.0, 0.0, 0.0)];
+  let cliff = [Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0), Point3::new(0.0, 0.0, 1.0)];
+  let rules = GrassRules { density: 3.0, seed: 7 };
+  let tex_ids = |grass: Vec<Grass>| grass.iter().map(|g| g.tex_ids).collect::<Vec<_>>();
+
+  let grass = place_grass(&flat, voxel::Material::Terrain, &rules);
+  assert_eq!(grass.len(), 3);
+  assert_eq!(tex_ids(place_grass(&flat, voxel::Material::Terrain, &rules)), tex_ids(grass.clone()));
+  let other_world = place_grass(&flat, voxel::Material::Terrain, &GrassRules { seed: 8, .. rules });
+  assert!(tex_ids(other_world) != tex_ids(grass));
+
+  assert!(place_grass(&flat, voxel::Material::Stone, &rules).is_empty());
+  assert!(place_grass(&flat, voxel::Material::Dirt, &rules).is_empty());
+  assert!(place_grass(&cliff, voxel::Material::Terrain, &rules).is_empty());
+  assert!(place_grass(&flat, voxel::Material::Terrain, &GrassRules { density: 0.0, .. rules }).is_empty());
+}
@@ -99,6 +99,17 @@ impl Font {
     Font { p: p }
   }
 
+  /// The width and height in pixels of `txt` when it's rendered.
+  pub fn size_of(&self, txt: &str) -> (u32, u32) {
+    let c_str = CString::new(txt.as_bytes()).unwrap();
+    let mut w = 0;
+    let mut h = 0;
+    unsafe {
+      assert_eq!(ffi::TTF_SizeUTF8(self.p, c_str.as_ptr() as *const i8, &mut w, &mut h), 0);
+    }
+    (w as u32, h as u32)
+  }
+
   /// Color is rgba
   pub fn render<'a, 'b:'a>(
     &self,
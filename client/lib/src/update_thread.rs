@@ -1,4 +1,3 @@
-use cgmath::{Aabb3, Point3};
 use std::sync::Mutex;
 use stopwatch;
 use time;
@@ -14,13 +13,15 @@ use client;
 use lod;
 use load_terrain;
 use load_terrain::lod_index;
-use record_book;
 use server_update::apply_server_update;
 use terrain_mesh;
 use view_update::ClientToView;
 
 const MAX_OUTSTANDING_TERRAIN_REQUESTS: u32 = 1;
 
+/// How often to ping the server to measure latency.
+const PING_INTERVAL_NS: u64 = 1_000_000_000;
+
 pub fn update_thread<RecvServer, RecvVoxelUpdates, UpdateView0, UpdateView1, UpdateAudio, UpdateServer, EnqueueBlockUpdates>(
   quit: &Mutex<bool>,
   client: &client::T,
@@ -47,7 +48,15 @@ pub fn update_thread<RecvServer, RecvVoxelUpdates, UpdateView0, UpdateView1, Upd
     } else {
       stopwatch::time("update_iteration", || {
         stopwatch::time("process_server_updates", || {
-          process_server_updates(client, recv_server, update_view0, update_audio, update_server, enqueue_block_updates);
+          process_server_updates(client, recv_server, update_view0, update_audio, enqueue_block_updates);
+        });
+
+        if client.metrics.start_ping(time::precise_time_ns(), PING_INTERVAL_NS) {
+          update_server(protocol::ClientToServer::Ping(client.id));
+        }
+
+        stopwatch::time("update_load_distance", || {
+          update_load_distance(client, update_view1);
         });
 
         stopwatch::time("update_surroundings", || {
@@ -55,13 +64,86 @@ pub fn update_thread<RecvServer, RecvVoxelUpdates, UpdateView0, UpdateView1, Upd
         });
 
         stopwatch::time("process_voxel_updates", || {
-          process_voxel_updates(client, recv_voxel_updates, update_view1);
+          process_voxel_updates(client, recv_voxel_updates);
+        });
+
+        stopwatch::time("process_meshed_blocks", || {
+          process_meshed_blocks(client, update_view1);
         });
+
+        let should_evict = client.voxel_cache.lock().unwrap().should_evict(time::precise_time_ns());
+        if should_evict {
+          stopwatch::time("evict_voxels", || {
+            load_terrain::evict_voxels(client);
+          });
+        }
       })
     }
   }
 }
 
+fn unload_block<UpdateView>(
+  client: &client::T,
+  update_view: &mut UpdateView,
+  block_position: &block_position::T,
+) where
+  UpdateView: FnMut(ClientToView),
+{
+  // The block removal code is duplicated in load_terrain.
+
+  client.mesh_pool.cancel(block_position);
+  client.seam_lods.lock().unwrap().remove(block_position);
+  client.loaded_blocks
+  .lock().unwrap()
+    .remove(block_position)
+    // If it wasn't loaded, don't unload anything.
+    .map(|(block, _)| {
+      for id in &block.grass_ids {
+        update_view(ClientToView::RemoveGrass(*id));
+      }
+      for id in &block.ids {
+        update_view(ClientToView::RemoveTerrain(*id));
+      }
+    });
+}
+
+/// Switch to a requested load distance, if there is one. Blocks that are now out of range are
+/// unloaded, and the new `SurroundingsLoader` starts over, loading anything whose LOD changed.
+#[inline(never)]
+fn update_load_distance<UpdateView>(
+  client: &client::T,
+  update_view: &mut UpdateView,
+) where
+  UpdateView: FnMut(ClientToView),
+{
+  let load_distance =
+    match client.requested_load_distance.lock().unwrap().take() {
+      None => return,
+      Some(load_distance) => load_distance,
+    };
+  info!("Changing load distance to {}", load_distance);
+  *client.max_load_distance.lock().unwrap() = load_distance;
+  *client.surroundings_loader.lock().unwrap() = client::surroundings_loader(&client.settings, load_distance);
+
+  let load_position = {
+    let load_position = *client.load_position.lock().unwrap();
+    load_position.unwrap_or_else(|| *client.player_position.lock().unwrap())
+  };
+  let load_position = block_position::of_world_position(&load_position);
+  let in_range = |block_position: &block_position::T| {
+    surroundings_loader::distance_between(load_position.as_pnt(), block_position.as_pnt()) <= load_distance
+  };
+  client.mesh_pool.retain(&in_range);
+  let out_of_range: Vec<block_position::T> =
+    client.loaded_blocks.lock().unwrap().keys()
+    .filter(|block_position| !in_range(*block_position))
+    .cloned()
+    .collect();
+  for block_position in &out_of_range {
+    unload_block(client, update_view, block_position);
+  }
+}
+
 #[inline(never)]
 fn update_surroundings<UpdateView, UpdateServer>(
   client: &client::T,
@@ -106,7 +188,7 @@ fn update_surroundings<UpdateView, UpdateServer>(
       LoadType::Load => {
         stopwatch::time("update_thread.load_block", || {
           info!("Loading distance {}", distance);
-          let new_lod = lod_index(distance);
+          let new_lod = lod_index(&client.settings.lod_thresholds, distance);
           let lod_change =
             client.loaded_blocks
             .lock().unwrap()
@@ -115,20 +197,20 @@ fn update_surroundings<UpdateView, UpdateServer>(
           if lod_change == Some(false) {
             debug!("Not re-loading {:?} at {:?}", block_position, new_lod);
           } else {
-            load_or_request_chunk(client, update_server, update_view, block_position, new_lod);
+            load_or_request_chunk(client, update_server, block_position, new_lod);
           }
         })
       },
       LoadType::Update => {
         stopwatch::time("update_thread.update_block", || {
-          let new_lod = lod_index(distance);
+          let new_lod = lod_index(&client.settings.lod_thresholds, distance);
           let lod_change =
             client.loaded_blocks
             .lock().unwrap()
             .get(&block_position)
             .map(|&(_, lod)| new_lod < lod);
           if lod_change == Some(true) {
-            load_or_request_chunk(client, update_server, update_view, block_position, new_lod);
+            load_or_request_chunk(client, update_server, block_position, new_lod);
           } else {
             trace!("Not updating {:?} at {:?}", block_position, new_lod);
           }
@@ -136,20 +218,7 @@ fn update_surroundings<UpdateView, UpdateServer>(
       },
       LoadType::Unload => {
         stopwatch::time("update_thread.unload", || {
-          // The block removal code is duplicated in load_terrain.
-
-          client.loaded_blocks
-          .lock().unwrap()
-            .remove(&block_position)
-            // If it wasn't loaded, don't unload anything.
-            .map(|(block, _)| {
-              for id in &block.grass_ids {
-                update_view(ClientToView::RemoveGrass(*id));
-              }
-              for id in &block.ids {
-                update_view(ClientToView::RemoveTerrain(*id));
-              }
-            });
+          unload_block(client, update_view, &block_position);
         })
       },
     };
@@ -164,98 +233,80 @@ fn update_surroundings<UpdateView, UpdateServer>(
   }
 }
 
-fn load_or_request_chunk<UpdateServer, UpdateView>(
+fn load_or_request_chunk<UpdateServer>(
   client: &client::T,
   update_server: &mut UpdateServer,
-  update_view: &mut UpdateView,
   block_position: block_position::T,
   lod: lod::T,
 ) where
   UpdateServer: FnMut(protocol::ClientToServer),
-  UpdateView: FnMut(ClientToView),
 {
-  if load_terrain::all_voxels_loaded(&client.block_voxels_loaded.lock().unwrap(), block_position, lod) {
+  let resolutions = &client.settings.resolutions;
+  if load_terrain::all_voxels_loaded(resolutions, &client.block_voxels_loaded.lock().unwrap(), block_position, lod) {
     load_terrain::load_block(
       client,
-      update_view,
       &block_position,
       lod,
     );
+    return
+  }
+
+  let saved = client.disk_cache.lock().unwrap().load(resolutions, &block_position, lod);
+  if let Some(voxels) = saved {
+    debug!("Loading {:?} at {:?} from disk", block_position, lod);
+    load_voxels(client, voxels);
   } else {
-    let voxel_size = 1 << terrain_mesh::LG_SAMPLE_SIZE[lod.0 as usize];
     update_server(
       protocol::ClientToServer::RequestVoxels(
         time::precise_time_ns(),
         client.id,
-        terrain_mesh::voxels_in(
-          &Aabb3::new(
-            Point3::new(
-              (block_position.as_pnt().x << terrain_mesh::LG_WIDTH) - voxel_size,
-              (block_position.as_pnt().y << terrain_mesh::LG_WIDTH) - voxel_size,
-              (block_position.as_pnt().z << terrain_mesh::LG_WIDTH) - voxel_size,
-            ),
-            Point3::new(
-              ((block_position.as_pnt().x + 1) << terrain_mesh::LG_WIDTH) + voxel_size,
-              ((block_position.as_pnt().y + 1) << terrain_mesh::LG_WIDTH) + voxel_size,
-              ((block_position.as_pnt().z + 1) << terrain_mesh::LG_WIDTH) + voxel_size,
-            ),
-          ),
-          terrain_mesh::LG_SAMPLE_SIZE[lod.0 as usize],
-        ),
+        terrain_mesh::voxels_for_block(resolutions, &block_position, lod),
       )
     );
     *client.outstanding_terrain_requests.lock().unwrap() += 1;
   }
 }
 
+/// Store some voxels and load any blocks they complete.
+fn load_voxels(client: &client::T, voxels: Vec<(voxel::bounds::T, voxel::T)>) {
+  let mut update_blocks = block_position::with_lod::set::new();
+  for (bounds, voxel) in voxels {
+    trace!("Got voxel at {:?}", bounds);
+    load_terrain::load_voxel(
+      client,
+      voxel,
+      &bounds,
+      |block, lod| { update_blocks.insert((block, lod)); },
+    );
+  }
+
+  for (block, lod) in update_blocks.into_iter() {
+    load_terrain::load_block(
+      client,
+      &block,
+      lod
+    )
+  }
+}
+
 #[inline(never)]
-fn process_voxel_updates<RecvVoxelUpdates, UpdateView>(
+fn process_voxel_updates<RecvVoxelUpdates>(
   client: &client::T,
   recv_voxel_updates: &mut RecvVoxelUpdates,
-  update_view: &mut UpdateView,
 ) where
   RecvVoxelUpdates: FnMut() -> Option<(Option<u64>, Vec<(voxel::bounds::T, voxel::T)>, protocol::VoxelReason)>,
-  UpdateView: FnMut(ClientToView),
 {
   let start = time::precise_time_ns();
   while let Some((request_time, voxel_updates, reason)) = recv_voxel_updates() {
-    let mut update_blocks = block_position::with_lod::set::new();
-    let response_time = time::precise_time_ns();
-    for (bounds, voxel) in voxel_updates {
-      trace!("Got voxel at {:?}", bounds);
-      load_terrain::load_voxel(
-        client,
-        voxel,
-        &bounds,
-        |block, lod| { update_blocks.insert((block, lod)); },
-      );
-    }
-
-    let processed_time = time::precise_time_ns();
-
-    for (block, lod) in update_blocks.into_iter() {
-      load_terrain::load_block(
-        client,
-        update_view,
-        &block,
-        lod
-      )
+    if let protocol::VoxelReason::Updated = reason {
+      client.disk_cache.lock().unwrap().apply_next_versions();
     }
 
+    load_voxels(client, voxel_updates);
     let block_loaded = time::precise_time_ns();
 
-    match request_time {
-      None => {},
-      Some(request_time) => {
-        record_book::thread_local::push_block_load(
-          record_book::BlockLoad {
-            requested_at: request_time,
-            responded_at: response_time,
-            processed_at: processed_time,
-            loaded_at: block_loaded,
-          }
-        );
-      },
+    if let Some(request_time) = request_time {
+      client.metrics.record_block_load(request_time, block_loaded);
     }
 
     match reason {
@@ -272,19 +323,35 @@ fn process_voxel_updates<RecvVoxelUpdates, UpdateView>(
   }
 }
 
+/// Load the blocks that the mesh pool has finished, nearest first.
+#[inline(never)]
+fn process_meshed_blocks<UpdateView>(
+  client: &client::T,
+  update_view: &mut UpdateView,
+) where
+  UpdateView: FnMut(ClientToView),
+{
+  let start = time::precise_time_ns();
+  while let Some((block_position, lod, neighbour_lods, mesh)) = client.mesh_pool.pop() {
+    load_terrain::finish_block(client, update_view, &block_position, lod, &neighbour_lods, mesh);
+
+    if time::precise_time_ns() - start >= 1_000_000 {
+      break
+    }
+  }
+}
+
 #[inline(never)]
-fn process_server_updates<RecvServer, UpdateView, UpdateAudio, UpdateServer, EnqueueBlockUpdates>(
+fn process_server_updates<RecvServer, UpdateView, UpdateAudio, EnqueueBlockUpdates>(
   client: &client::T,
   recv_server: &mut RecvServer,
   update_view: &mut UpdateView,
   update_audio: &mut UpdateAudio,
-  update_server: &mut UpdateServer,
   enqueue_block_updates: &mut EnqueueBlockUpdates,
 ) where
   RecvServer: FnMut() -> Option<protocol::ServerToClient>,
   UpdateView: FnMut(ClientToView),
   UpdateAudio: FnMut(audio_thread::Message),
-  UpdateServer: FnMut(protocol::ClientToServer),
   EnqueueBlockUpdates: FnMut(Option<u64>, Vec<(voxel::bounds::T, voxel::T)>, protocol::VoxelReason),
 {
   let start = time::precise_time_ns();
@@ -294,7 +361,6 @@ fn process_server_updates<RecvServer, UpdateView, UpdateAudio, UpdateServer, Enq
       client,
       update_view,
       update_audio,
-      update_server,
       enqueue_block_updates,
       up,
     );
@@ -13,6 +13,8 @@ use yaglw::texture::{TextureUnit};
 
 use camera::Camera;
 use common::id_allocator;
+use debug_hud;
+use gamepad;
 use light;
 use grass_buffers;
 use mob_buffers::MobBuffers;
@@ -46,6 +48,7 @@ pub struct T<'a> {
   pub player_buffers: PlayerBuffers<'a>,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  pub debug_hud: debug_hud::T<'a>,
 
   pub sun: light::Sun,
   pub camera: Camera,
@@ -55,6 +58,8 @@ pub struct T<'a> {
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
+
+  pub gamepad: gamepad::T,
 }
 
 fn load_grass_texture<'a, 'b:'a>(
@@ -90,6 +95,7 @@ fn load_grass_texture<'a, 'b:'a>(
 pub fn new<'a>(
   mut gl: GLContext,
   window_size: cgmath::Vector2<i32>,
+  terrain_polygon_budget: usize,
 ) -> T<'a> {
   let mut texture_unit_alloc = id_allocator::new();
 
@@ -97,7 +103,7 @@ pub fn new<'a>(
   let far = 2048.0;
   let mut shaders = Shaders::new(&mut gl, window_size, near, far);
 
-  let terrain_buffers = TerrainBuffers::new(&mut gl);
+  let terrain_buffers = TerrainBuffers::new(&mut gl, terrain_polygon_budget);
   terrain_buffers.bind_glsl_uniforms(
     &mut gl,
     &mut texture_unit_alloc,
@@ -160,6 +166,8 @@ pub fn new<'a>(
   let grass_buffers = grass_buffers::new(&mut gl, &shaders.grass_billboard.shader);
   let grass_texture = load_grass_texture(&mut gl).unwrap();
 
+  let debug_hud = debug_hud::new(&mut gl, &shaders.texture_shader);
+
   let empty_gl_array = yaglw::vertex_buffer::ArrayHandle::new(&gl);
 
   T {
@@ -172,6 +180,7 @@ pub fn new<'a>(
     mob_buffers: mob_buffers,
     player_buffers: player_buffers,
     hud_triangles: hud_triangles,
+    debug_hud: debug_hud,
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
@@ -197,5 +206,7 @@ pub fn new<'a>(
 
     show_hud: true,
     input_mode: InputMode::Camera,
+
+    gamepad: gamepad::new(),
   }
 }
@@ -15,8 +15,9 @@ use common::protocol;
 
 use client;
 use hud::make_hud;
-use process_event::process_event;
+use process_event::{process_event, update_gamepad};
 use render::render;
+use terrain_buffers;
 use view;
 use view_update::{ClientToView, apply_client_to_view};
 
@@ -44,6 +45,15 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
   let sdl = sdl2::init().unwrap();
   let _event = sdl.event().unwrap();
   let video = sdl.video().unwrap();
+  // Connected gamepads show up as ControllerDeviceAdded events.
+  let game_controller =
+    match sdl.game_controller() {
+      Ok(game_controller) => Some(game_controller),
+      Err(err) => {
+        warn!("Couldn't start the game controller subsystem; continuing without gamepads: {}", err);
+        None
+      },
+    };
   let gl_attr = video.gl_attr();
 
   gl_attr.set_context_profile(video::GLProfile::Core);
@@ -82,7 +92,8 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
     Vector2::new(w as i32, h as i32)
   };
 
-  let mut view = view::new(gl, window_size);
+  let terrain_polygon_budget = terrain_buffers::polygon_budget(client.settings.terrain_byte_budget);
+  let mut view = view::new(gl, window_size, terrain_polygon_budget);
 
   sdl.mouse().set_relative_mouse_mode(true);
 
@@ -99,13 +110,15 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
   }
 
   let mut last_update = time::precise_time_ns();
+  let mut last_render = last_update;
 
   loop {
     let view_iteration =
       stopwatch::time("view_iteration", || {
         let now = time::precise_time_ns();
-        if now - last_update >= render_interval {
-          warn!("{:?}ms since last view update", (now - last_update) / 1000000);
+        let since_last_update = now - last_update;
+        if since_last_update >= render_interval {
+          warn!("{:?}ms since last view update", since_last_update / 1000000);
         }
         last_update = now;
 
@@ -117,6 +130,11 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
             Event::AppTerminating{..} => {
               return ViewIteration::Quit
             }
+            Event::ControllerDeviceAdded{which, ..} => {
+              if let Some(ref game_controller) = game_controller {
+                view.gamepad.open(game_controller, which as u32);
+              }
+            }
             event => {
               process_event(
                 update_server,
@@ -128,6 +146,8 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
           }
         }
 
+        update_gamepad(update_server, &mut view, &client, since_last_update);
+
         if window.window_flags() & (sdl2_sys::video::SDL_WindowFlags::SDL_WINDOW_MOUSE_FOCUS as u32) != 0 {
           sdl.mouse().warp_mouse_in_window(&window, window_size.x / 2, window_size.y / 2);
         }
@@ -157,6 +177,10 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
             // swap buffers
             window.gl_swap_window();
           });
+          let now = time::precise_time_ns();
+          client.metrics.record_frame(now - last_render);
+          last_render = now;
+          view.debug_hud.frame(&mut view.gl, window_size, client, now);
         }
 
         ViewIteration::Continue
@@ -59,10 +59,11 @@ pub fn apply_client_to_view(view: &mut view::T, up: ClientToView) {
         },
       }
     },
-    ClientToView::AddBlock(_, block, _) => {
+    ClientToView::AddBlock(block_position, block, _) => {
       stopwatch::time("add_block", || {
         view.terrain_buffers.push(
           &mut view.gl,
+          &block_position,
           block.vertex_coordinates.as_ref(),
           block.normals.as_ref(),
           block.ids.as_ref(),
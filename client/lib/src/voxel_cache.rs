@@ -0,0 +1,211 @@
+//! Bookkeeping for the voxels the client has cached from the server, so that they can be evicted
+//! once they're out of range, or when there are too many of them.
+
+use std::mem;
+
+use common::interval_timer::IntervalTimer;
+use common::surroundings_loader;
+use common::voxel;
+
+use block_position;
+
+/// How many blocks past the load distance voxels are kept, so that moving back and forth across
+/// the edge of the loaded area doesn't keep evicting and re-requesting the same voxels.
+pub const HYSTERESIS: i32 = 4;
+
+/// Roughly how many bytes of voxels to keep before evicting ones that are still in range.
+pub const MEMORY_BUDGET: usize = 512 << 20;
+
+/// Blocks at most this far away are never evicted to stay under budget,
+/// since they're the ones likely to be edited.
+pub const PROTECTED_DISTANCE: i32 = 16;
+
+/// How often to look for voxels to evict.
+const EVICTION_INTERVAL: u64 = 1_000_000_000;
+
+/// Rough cost of a cached voxel, including its tree node and our bookkeeping.
+fn bytes_per_voxel() -> usize {
+  mem::size_of::<voxel::T>() + mem::size_of::<voxel::bounds::T>() + 64
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(missing_docs)]
+pub struct Stats {
+  pub voxels: usize,
+  pub blocks: usize,
+  pub evicted_voxels: u64,
+  pub evicted_blocks: u64,
+  pub compactions: u32,
+}
+
+impl Stats {
+  /// Approximately how much memory the cached voxels take up.
+  pub fn bytes(&self) -> usize {
+    self.voxels * bytes_per_voxel()
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The bounds of every cached voxel, by the block that contains it.
+  by_block: block_position::map::T<Vec<voxel::bounds::T>>,
+  /// Voxels evicted since the voxel tree was last rebuilt. Their tree nodes are still allocated.
+  pub evicted_since_compaction: usize,
+  stats: Stats,
+  timer: IntervalTimer,
+}
+
+#[allow(missing_docs)]
+pub fn new(now: u64) -> T {
+  T {
+    by_block: block_position::map::new(),
+    evicted_since_compaction: 0,
+    stats: Default::default(),
+    timer: IntervalTimer::new(EVICTION_INTERVAL, now + EVICTION_INTERVAL),
+  }
+}
+
+impl T {
+  /// Record that a new voxel has been cached.
+  pub fn insert(&mut self, bounds: &voxel::bounds::T) {
+    self.by_block
+      .entry(block_position::containing_voxel(bounds))
+      .or_insert_with(Vec::new)
+      .push(*bounds);
+    self.stats.voxels += 1;
+  }
+
+  #[allow(missing_docs)]
+  pub fn stats(&self) -> Stats {
+    Stats {
+      blocks: self.by_block.len(),
+      .. self.stats
+    }
+  }
+
+  /// Whether it's time to look for voxels to evict again.
+  pub fn should_evict(&mut self, now: u64) -> bool {
+    self.timer.update(now) > 0
+  }
+
+  /// The blocks whose voxels should be evicted: everything more than `HYSTERESIS` past
+  /// `max_distance` from `center`, and then the farthest blocks until we're under `budget` bytes.
+  /// Blocks that are `in_use`, e.g. by loaded meshes, are only evicted once they're out of range,
+  /// since nothing would request their voxels again.
+  pub fn blocks_to_evict<InUse>(
+    &self,
+    center: &block_position::T,
+    max_distance: i32,
+    budget: usize,
+    mut in_use: InUse,
+  ) -> Vec<block_position::T> where
+    InUse: FnMut(&block_position::T) -> bool,
+  {
+    let mut blocks: Vec<_> =
+      self.by_block.iter()
+      .map(|(block, voxels)| {
+        (surroundings_loader::distance_between(center.as_pnt(), block.as_pnt()), *block, voxels.len())
+      })
+      .collect();
+    blocks.sort_by(|x, y| y.0.cmp(&x.0));
+
+    let mut remaining = self.stats.voxels * bytes_per_voxel();
+    let mut evict = Vec::new();
+    for (distance, block, voxel_count) in blocks {
+      let out_of_range = distance > max_distance + HYSTERESIS;
+      let over_budget = remaining > budget && distance > PROTECTED_DISTANCE;
+      if !out_of_range && !over_budget {
+        break
+      }
+      if !out_of_range && in_use(&block) {
+        continue
+      }
+      evict.push(block);
+      remaining -= voxel_count * bytes_per_voxel();
+    }
+    evict
+  }
+
+  /// Forget a block's voxels, returning their bounds.
+  pub fn remove_block(&mut self, block: &block_position::T) -> Vec<voxel::bounds::T> {
+    let voxels = self.by_block.remove(block).unwrap_or_else(Vec::new);
+    self.stats.voxels -= voxels.len();
+    self.stats.evicted_voxels += voxels.len() as u64;
+    self.stats.evicted_blocks += 1;
+    self.evicted_since_compaction += voxels.len();
+    voxels
+  }
+
+  /// Whether enough has been evicted that the voxel tree should be rebuilt to free its nodes.
+  pub fn should_compact(&self) -> bool {
+    self.evicted_since_compaction > self.stats.voxels
+  }
+
+  /// The bounds of every cached voxel, for rebuilding the voxel tree.
+  pub fn compact(&mut self) -> Vec<voxel::bounds::T> {
+    self.evicted_since_compaction = 0;
+    self.stats.compactions += 1;
+    self.by_block.values().flat_map(|voxels| voxels.iter().cloned()).collect()
+  }
+}
+
+#[cfg(test)]
+fn block_voxels(block: &block_position::T) -> Vec<voxel::bounds::T> {
+  let p = block.as_pnt();
+  let lg_size = 1;
+  let width = 1 << (::terrain_mesh::LG_WIDTH - lg_size);
+  vec!(
+    voxel::bounds::new(p.x * width, p.y * width, p.z * width, lg_size),
+    voxel::bounds::new(p.x * width + 1, p.y * width, p.z * width, lg_size),
+  )
+}
+
+#[test]
+fn evicts_out_of_range_then_farthest() {
+  let mut cache = new(0);
+  for x in 0 .. 40 {
+    for bounds in block_voxels(&block_position::new(x, 0, 0)) {
+      cache.insert(&bounds);
+    }
+  }
+
+  let center = block_position::new(0, 0, 0);
+  let mut evicted = cache.blocks_to_evict(&center, 30, MEMORY_BUDGET, |_| false);
+  evicted.sort_by(|x, y| x.as_pnt().x.cmp(&y.as_pnt().x));
+  let expected: Vec<_> = (30 + HYSTERESIS + 1 .. 40).map(|x| block_position::new(x, 0, 0)).collect();
+  assert_eq!(evicted, expected);
+
+  // With room for 20 blocks, the farthest ones go, but never the protected ones.
+  let evicted = cache.blocks_to_evict(&center, 100, 20 * 2 * bytes_per_voxel(), |_| false);
+  assert_eq!(evicted.len(), 20);
+  assert!(evicted.iter().all(|b| b.as_pnt().x >= 20));
+  let evicted = cache.blocks_to_evict(&center, 100, 0, |_| false);
+  assert_eq!(evicted.len(), 40 - PROTECTED_DISTANCE as usize - 1);
+
+  let removed = cache.remove_block(&block_position::new(39, 0, 0));
+  assert_eq!(removed.len(), 2);
+  assert_eq!(cache.stats().voxels, 78);
+  assert_eq!(cache.stats().blocks, 39);
+  assert!(!cache.should_compact());
+}
+
+#[test]
+fn blocks_in_use_are_not_evicted_for_budget() {
+  let mut cache = new(0);
+  for x in 0 .. 40 {
+    for bounds in block_voxels(&block_position::new(x, 0, 0)) {
+      cache.insert(&bounds);
+    }
+  }
+
+  // Block 30 is in range and loaded, but everything is over budget.
+  let center = block_position::new(0, 0, 0);
+  let in_use = |b: &block_position::T| b.as_pnt().x == 30;
+  let evicted = cache.blocks_to_evict(&center, 100, 0, in_use);
+  assert_eq!(evicted.len(), 40 - PROTECTED_DISTANCE as usize - 2);
+  assert!(!evicted.contains(&block_position::new(30, 0, 0)));
+
+  // Once it's out of range, it goes like any other.
+  let evicted = cache.blocks_to_evict(&center, 20, MEMORY_BUDGET, in_use);
+  assert!(evicted.contains(&block_position::new(30, 0, 0)));
+}
@@ -37,8 +37,11 @@ pub mod fnv_map;
 pub mod fnv_set;
 pub mod id_allocator;
 pub mod interval_timer;
+pub mod prometheus;
 pub mod protocol;
 pub mod range_abs;
+pub mod samples;
 pub mod socket;
 pub mod surroundings_loader;
+pub mod tree_species;
 pub mod voxel;
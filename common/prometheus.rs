@@ -0,0 +1,155 @@
+//! Serve metrics over HTTP in the Prometheus text format.
+
+use std;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A page of metrics in the Prometheus text format.
+pub struct Text {
+  text: String,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> Text {
+  Text {
+    text: String::new(),
+  }
+}
+
+impl Text {
+  /// Start a metric, with its type ("counter", "gauge" or "summary") and help text.
+  pub fn metric(&mut self, name: &str, kind: &str, help: &str) {
+    self.text.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind));
+  }
+
+  /// Add a sample to the current metric, e.g. `sample("x_count", &[], 3.0)` or
+  /// `sample("x", &[("quantile", "0.5")], 1.0)`.
+  pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+    self.text.push_str(name);
+    if !labels.is_empty() {
+      let labels: Vec<String> =
+        labels.iter()
+        .map(|&(label, value)| format!("{}=\"{}\"", label, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+      self.text.push_str(&format!("{{{}}}", labels.join(",")));
+    }
+    self.text.push_str(&format!(" {}\n", value));
+  }
+
+  #[allow(missing_docs)]
+  pub fn into_string(self) -> String {
+    self.text
+  }
+}
+
+/// How often `serve` checks whether it should quit.
+const QUIT_POLL_MS: u64 = 100;
+
+fn respond(mut stream: TcpStream, body: &str) -> io::Result<()> {
+  try!(stream.set_nonblocking(false));
+  try!(stream.set_read_timeout(Some(Duration::from_secs(1))));
+
+  // Read (enough of) the request line; we only care about the path.
+  let mut request = [0; 1024];
+  let len = try!(stream.read(&mut request));
+  let request = String::from_utf8_lossy(&request[.. len]);
+  let path = request.split_whitespace().nth(1).unwrap_or("");
+
+  let response =
+    if path == "/" || path == "/metrics" {
+      format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body,
+      )
+    } else {
+      String::from("HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+    };
+  stream.write_all(response.as_bytes())
+}
+
+/// Answer HTTP requests for `/metrics` at `address` with whatever `render` returns,
+/// until `quit` is set.
+pub fn serve<Render>(address: &str, quit: &Mutex<bool>, render: Render) -> io::Result<()> where
+  Render: FnMut() -> String,
+{
+  let listener = try!(TcpListener::bind(address));
+  serve_on(listener, quit, render)
+}
+
+/// Like `serve`, but on an already-bound `listener`.
+pub fn serve_on<Render>(listener: TcpListener, quit: &Mutex<bool>, mut render: Render) -> io::Result<()> where
+  Render: FnMut() -> String,
+{
+  try!(listener.set_nonblocking(true));
+  info!("Serving metrics at http://{}/metrics", try!(listener.local_addr()));
+
+  while !*quit.lock().unwrap() {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        if let Err(err) = respond(stream, &render()) {
+          warn!("Couldn't serve metrics: {}", err);
+        }
+      },
+      Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+        std::thread::sleep(Duration::from_millis(QUIT_POLL_MS));
+      },
+      Err(err) => warn!("Couldn't accept a metrics connection: {}", err),
+    }
+  }
+  Ok(())
+}
+
+#[test]
+fn formats_metrics() {
+  let mut text = new();
+  text.metric("requests", "counter", "Requests served.");
+  text.sample("requests", &[], 3.0);
+  text.metric("latency_ms", "summary", "Request latency.");
+  text.sample("latency_ms", &[("quantile", "0.5")], 1.5);
+  text.sample("latency_ms_count", &[], 3.0);
+  text.metric("queue_depth", "gauge", "Queued things.");
+  text.sample("queue_depth", &[("queue", "a\"b"), ("thread", "x")], 0.0);
+  assert_eq!(
+    text.into_string(),
+    "# HELP requests Requests served.\n# TYPE requests counter\nrequests 3\n\
+     # HELP latency_ms Request latency.\n# TYPE latency_ms summary\n\
+     latency_ms{quantile=\"0.5\"} 1.5\nlatency_ms_count 3\n\
+     # HELP queue_depth Queued things.\n# TYPE queue_depth gauge\n\
+     queue_depth{queue=\"a\\\"b\",thread=\"x\"} 0\n"
+  );
+}
+
+#[test]
+fn serves_metrics_over_http() {
+  use std::sync::Arc;
+
+  let quit = Arc::new(Mutex::new(false));
+  // Let the OS pick a free port so concurrent test runs don't collide.
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let server = {
+    let quit = quit.clone();
+    std::thread::spawn(move || serve_on(listener, &quit, || String::from("up 1\n")).unwrap())
+  };
+
+  let get = |path: &str| {
+    // The listener is already bound, so this connects even before the server thread accepts.
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(format!("GET {} HTTP/1.0\r\n\r\n", path).as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+  };
+
+  let response = get("/metrics");
+  assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+  assert!(response.ends_with("\r\n\r\nup 1\n"));
+  assert!(get("/other").starts_with("HTTP/1.0 404"));
+
+  *quit.lock().unwrap() = true;
+  server.join().unwrap();
+}
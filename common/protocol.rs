@@ -1,10 +1,14 @@
 //! Defines the messages passed between client and server.
 
 use cgmath::{Aabb3, Vector2, Vector3, Point3};
+use fnv;
 use std::default::Default;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::Add;
 
 use entity_id;
+use tree_species;
 use voxel;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, RustcEncodable, RustcDecodable)]
@@ -26,6 +30,46 @@ impl Add<u32> for ClientId {
   }
 }
 
+impl fmt::Display for ClientId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let ClientId(i) = *self;
+    write!(f, "{}", i)
+  }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, RustcEncodable, RustcDecodable)]
+/// Identifies the world a server is running, so clients can tell which saved voxels apply to it.
+pub struct WorldId(pub u64);
+
+impl WorldId {
+  /// The world generated from a seed and a fingerprint of everything else its terrain depends on.
+  pub fn new(terrain_seed: u32, source: &[u8]) -> WorldId {
+    let mut hasher = fnv::FnvHasher::default();
+    terrain_seed.hash(&mut hasher);
+    source.hash(&mut hasher);
+    WorldId(hasher.finish())
+  }
+}
+
+/// lg of the width, in world units, of the regions that voxel versions are tracked for.
+pub const LG_VERSION_REGION_WIDTH: i16 = 3;
+
+/// The version of a region that has never been edited.
+/// Generation is deterministic, so these regions are the same every time a world is run.
+pub const UNEDITED_VERSION: u64 = 0;
+
+/// The region whose version covers a voxel.
+pub fn version_region(bounds: &voxel::bounds::T) -> Point3<i32> {
+  let f = |x: i32| {
+    if bounds.lg_size < 0 {
+      (x >> -bounds.lg_size) >> LG_VERSION_REGION_WIDTH
+    } else {
+      (x << bounds.lg_size) >> LG_VERSION_REGION_WIDTH
+    }
+  };
+  Point3::new(f(bounds.x), f(bounds.y), f(bounds.z))
+}
+
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 /// Messages the client sends to the server.
 pub enum ClientToServer {
@@ -45,10 +89,16 @@ pub enum ClientToServer {
   StopJump(entity_id::T),
   /// Ask the server to send a block of terrain.
   RequestVoxels(u64, ClientId, Vec<voxel::bounds::T>),
-  /// Brush-remove where the player's looking.
-  Add(entity_id::T),
+  /// Brush-add a tree of some species where the player's looking.
+  Add(entity_id::T, tree_species::T),
   /// Brush-add at where the player's looking.
   Remove(entity_id::T),
+  /// Save the voxels in some bounds as a named prefab.
+  SavePrefab(ClientId, String, Aabb3<i32>),
+  /// Ask for the names of the saved prefabs.
+  ListPrefabs(ClientId),
+  /// Stamp a named prefab where the player's looking, rotated some radians about the y axis.
+  PastePrefab(entity_id::T, String, f32),
 }
 
 /// Why a block is being sent to a client.
@@ -73,6 +123,8 @@ pub enum Collision {
 pub enum ServerToClient {
   /// Provide the client a unique id to tag its messages.
   LeaseId(ClientId),
+  /// The world the server is running, and the versions of all its edited regions.
+  World(WorldId, Vec<(Point3<i32>, u64)>),
   /// Ping
   Ping,
 
@@ -88,6 +140,10 @@ pub enum ServerToClient {
 
   /// Provide a block of terrain to a client.
   Voxels(Option<u64>, Vec<(voxel::bounds::T, voxel::T)>, VoxelReason),
+  /// New versions of regions whose voxels changed. Sent before the changed voxels.
+  VoxelVersions(Vec<(Point3<i32>, u64)>),
   /// A collision happened.
   Collision(Collision),
+  /// The names of the saved prefabs.
+  Prefabs(Vec<String>),
 }
@@ -0,0 +1,94 @@
+//! Keep the most recent samples of some duration, and summarize them.
+
+use std::collections::VecDeque;
+
+use prometheus;
+
+/// How many of the most recent samples are kept for percentiles.
+pub const MAX_SAMPLES: usize = 10_000;
+
+/// The distribution of some recent durations.
+#[derive(Debug, Clone, Copy, PartialEq, RustcEncodable)]
+pub struct Summary {
+  /// How many samples this summarizes.
+  pub count: usize,
+  /// The average, in milliseconds.
+  pub mean_ms: f64,
+  /// The 50th percentile, in milliseconds.
+  pub median_ms: f64,
+  /// The 95th percentile, in milliseconds.
+  pub p95_ms: f64,
+  /// The slowest, in milliseconds.
+  pub max_ms: f64,
+}
+
+/// Summarize some durations in nanoseconds.
+pub fn summarize<Ns: Iterator<Item=u64>>(ns: Ns) -> Option<Summary> {
+  let mut ns: Vec<u64> = ns.collect();
+  if ns.is_empty() {
+    return None
+  }
+  ns.sort();
+  let ms = |ns: u64| ns as f64 / 1_000_000.0;
+  let percentile = |p: usize| ms(ns[(ns.len() - 1) * p / 100]);
+  Some(Summary {
+    count: ns.len(),
+    mean_ms: ms(ns.iter().fold(0, |sum, x| sum + x)) / ns.len() as f64,
+    median_ms: percentile(50),
+    p95_ms: percentile(95),
+    max_ms: ms(ns[ns.len() - 1]),
+  })
+}
+
+/// The most recent `MAX_SAMPLES` durations, in nanoseconds.
+pub struct T {
+  ns: VecDeque<u64>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T { ns: VecDeque::new() }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn push(&mut self, ns: u64) {
+    if self.ns.len() >= MAX_SAMPLES {
+      self.ns.pop_front();
+    }
+    self.ns.push_back(ns);
+  }
+
+  #[allow(missing_docs)]
+  pub fn summary(&self) -> Option<Summary> {
+    summarize(self.ns.iter().cloned())
+  }
+}
+
+/// Format a summary in the Prometheus text format, as a metric called `name`.
+pub fn to_prometheus(text: &mut prometheus::Text, name: &str, help: &str, summary: &Summary) {
+  text.metric(name, "summary", help);
+  text.sample(name, &[("quantile", "0.5")], summary.median_ms);
+  text.sample(name, &[("quantile", "0.95")], summary.p95_ms);
+  text.sample(name, &[("quantile", "1")], summary.max_ms);
+  text.sample(&format!("{}_sum", name), &[], summary.mean_ms * summary.count as f64);
+  text.sample(&format!("{}_count", name), &[], summary.count as f64);
+}
+
+#[test]
+fn summarizes_durations() {
+  assert!(summarize(Vec::new().into_iter()).is_none());
+
+  let summary = summarize((1 .. 101).rev().map(|i| i * 1_000_000)).unwrap();
+  assert_eq!(summary.count, 100);
+  assert_eq!(summary.mean_ms, 50.5);
+  assert_eq!(summary.median_ms, 50.0);
+  assert_eq!(summary.p95_ms, 95.0);
+  assert_eq!(summary.max_ms, 100.0);
+
+  let mut samples = new();
+  for i in 0 .. MAX_SAMPLES as u64 + 1 {
+    samples.push(i);
+  }
+  assert_eq!(samples.summary().map(|s| (s.count, s.max_ms)), Some((MAX_SAMPLES, MAX_SAMPLES as f64 / 1_000_000.0)));
+}
@@ -0,0 +1,36 @@
+//! The kinds of trees that can be generated.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, RustcEncodable, RustcDecodable)]
+/// Tree species, each with its own overall shape.
+pub enum T {
+  /// Thick trunk with a big round canopy.
+  Oak,
+  /// Stacked cones of leaves around a straight trunk.
+  Conifer,
+  /// Tall, thin trunk with a small canopy.
+  Birch,
+  /// A low clump of leaves with no trunk.
+  Bush,
+  /// A trunk with bare branches.
+  Dead,
+  /// Recursively-forking branches with leaves at the tips.
+  Branching,
+}
+
+/// Every species, in the order that `next` cycles through them.
+pub const ALL: [T; 6] = [
+  T::Oak,
+  T::Conifer,
+  T::Birch,
+  T::Bush,
+  T::Dead,
+  T::Branching,
+];
+
+impl T {
+  /// The species after this one, wrapping around at the end of `ALL`.
+  pub fn next(self) -> T {
+    let i = ALL.iter().position(|&s| s == self).unwrap();
+    ALL[(i + 1) % ALL.len()]
+  }
+}
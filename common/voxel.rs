@@ -21,6 +21,10 @@ pub enum Material {
   Leaves = 3,
   Stone = 4,
   Marble = 5,
+  Water = 6,
+  Sand = 7,
+  Snow = 8,
+  Dirt = 9,
 }
 
 #[allow(missing_docs)]
@@ -51,8 +55,78 @@ pub mod mosaic {
   pub use voxel_data::mosaic::*;
 }
 
+/// Every material, in order of ID.
+pub const MATERIALS: [Material; 10] = [
+  Material::Empty,
+  Material::Terrain,
+  Material::Bark,
+  Material::Leaves,
+  Material::Stone,
+  Material::Marble,
+  Material::Water,
+  Material::Sand,
+  Material::Snow,
+  Material::Dirt,
+];
+
+impl Material {
+  /// The material with a given ID, i.e. `material as i32`.
+  pub fn of_id(id: i32) -> Option<Material> {
+    MATERIALS.iter().find(|&&material| material as i32 == id).cloned()
+  }
+
+  /// A human-readable name, for tools and exported files.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Material::Empty => "Empty",
+      Material::Terrain => "Terrain",
+      Material::Bark => "Bark",
+      Material::Leaves => "Leaves",
+      Material::Stone => "Stone",
+      Material::Marble => "Marble",
+      Material::Water => "Water",
+      Material::Sand => "Sand",
+      Material::Snow => "Snow",
+      Material::Dirt => "Dirt",
+    }
+  }
+
+  /// A flat RGB color to show this material as in previews.
+  pub fn color(&self) -> [u8; 3] {
+    match *self {
+      Material::Empty => [0, 0, 0],
+      Material::Terrain => [0, 160, 0],
+      Material::Bark => [96, 64, 32],
+      Material::Leaves => [0, 96, 32],
+      Material::Stone => [128, 128, 128],
+      Material::Marble => [224, 224, 240],
+      Material::Water => [32, 64, 224],
+      Material::Sand => [224, 208, 128],
+      Material::Snow => [255, 255, 255],
+      Material::Dirt => [128, 80, 32],
+    }
+  }
+
+  /// Whether this material should be collided with.
+  pub fn is_solid(&self) -> bool {
+    match *self {
+      Material::Empty | Material::Water => false,
+      _ => true,
+    }
+  }
+}
+
 impl isosurface_extraction::dual_contouring::material::T for Material {
   fn is_opaque(&self) -> bool {
-    *self != Material::Empty
+    self.is_solid()
+  }
+}
+
+#[test]
+fn materials_are_listed_by_id() {
+  for (id, material) in MATERIALS.iter().enumerate() {
+    assert_eq!(*material as usize, id);
+    assert_eq!(Material::of_id(id as i32), Some(*material));
   }
+  assert_eq!(Material::of_id(MATERIALS.len() as i32), None);
 }
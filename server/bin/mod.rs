@@ -23,10 +23,23 @@ use std::sync::Mutex;
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
-  args.next().unwrap();
-  let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
-  assert!(args.next().is_none());
+  let mut args = env::args().skip(1).peekable();
+  let has_listen_url =
+    match args.peek() {
+      Some(arg) => !arg.starts_with("--"),
+      None => false,
+    };
+  let listen_url =
+    if has_listen_url {
+      args.next().unwrap()
+    } else {
+      String::from("ipc:///tmp/server.ipc")
+    };
+  let config =
+    match server_lib::config::parse(args) {
+      Ok(config) => config,
+      Err(err) => panic!("{}", err),
+    };
 
   info!("Listening on {}.", listen_url);
 
@@ -43,7 +56,7 @@ fn main() {
       })
     };
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+  server_lib::run(listen_url.borrow(), &config, &quit_signal);
 }
 
 fn wait_for_quit() {
@@ -1,6 +1,4 @@
 use cgmath::{Point, Point3, Vector, Vector3, Aabb3};
-use rand;
-use rand::distributions::IndependentSample;
 use std::convert::AsRef;
 use std::f32::consts::PI;
 use std::ops::DerefMut;
@@ -14,6 +12,7 @@ use common::socket::SendSocket;
 use common::voxel;
 
 use player;
+use prefab;
 use server;
 use server::Client;
 use terrain;
@@ -40,13 +39,28 @@ fn cast(
     &ray,
     &mut |bounds, voxel| {
       match voxel {
-        &voxel::Volume(voxel::Material::Empty) => None,
+        &voxel::Volume(material) if !material.is_solid() => None,
         _ => Some(bounds),
       }
     }
   )
 }
 
+fn send_prefabs(server: &server::T, client_id: protocol::ClientId) {
+  let names =
+    match prefab::list() {
+      Ok(names) => names,
+      Err(err) => {
+        warn!("Couldn't list prefabs: {}", err);
+        Vec::new()
+      },
+    };
+  server.clients.lock().unwrap()
+    .get_mut(&client_id)
+    .unwrap()
+    .send(protocol::ServerToClient::Prefabs(names));
+}
+
 pub fn apply_client_update<UpdateGaia>(
   server: &server::T,
   update_gaia: &mut UpdateGaia,
@@ -62,10 +76,16 @@ pub fn apply_client_update<UpdateGaia>(
         let mut client =
           Client {
             socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+            bytes_sent: 0,
           };
 
         let client_id = id_allocator::allocate(&server.client_allocator);
         client.send(protocol::ServerToClient::LeaseId(client_id));
+        // Hold the versions until the client is in `clients`, so no version changes between the
+        // snapshot and the client hearing about later ones.
+        let voxel_versions = server.voxel_versions.lock().unwrap();
+        let versions = voxel_versions.iter().map(|(&r, &v)| (r, v)).collect();
+        client.send(protocol::ServerToClient::World(server.world_id, versions));
 
         server.clients.lock().unwrap().insert(client_id, client);
       },
@@ -134,48 +154,30 @@ pub fn apply_client_update<UpdateGaia>(
       protocol::ClientToServer::RequestVoxels(request_time, client_id, bounds) => {
         update_gaia(update_gaia::Message::Load(request_time, bounds, LoadReason::ForClient(client_id)));
       },
-      protocol::ClientToServer::Add(player_id) => {
+      protocol::ClientToServer::Add(player_id, species) => {
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
           let mut rng = server.rng.lock().unwrap();
           let rng = rng.deref_mut();
 
-          let trunk_radius =
-            rand::distributions::normal::Normal::new(2.0, 0.5)
-            .ind_sample(rng);
-          let trunk_radius =
-            f64::max(1.0, f64::min(3.0, trunk_radius));
-
-          let trunk_height =
-            rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
-            .ind_sample(rng);
-          let trunk_height =
-            f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
-
-          let leaf_radius =
-            rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
-            .ind_sample(rng);
-          let leaf_radius =
-            f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
-
           let (low, high) = bounds.corners();
           let mut bottom = low.add_v(&high.to_vec()).div_s(2.0);
           bottom.y = low.y;
 
-          let trunk_height = trunk_height as f32;
-          let trunk_radius = trunk_radius as f32;
-          let leaf_radius = leaf_radius as f32;
+          let tree = terrain::tree::new(rng, species);
+          let height = tree.height;
+          let radius = tree.radius;
 
           let tree =
             voxel_data::mosaic::translation::T {
               translation: bottom.to_vec(),
-              mosaic: terrain::tree::new(rng, trunk_height, trunk_radius, leaf_radius),
+              mosaic: tree,
             };
 
           let center =
-            bottom.add_v(&Vector3::new(0.0, trunk_height / 2.0, 0.0));
-          let r = trunk_height / 2.0 + leaf_radius + 20.0;
+            bottom.add_v(&Vector3::new(0.0, height / 2.0, 0.0));
+          let r = f32::max(height / 2.0, radius) + 2.0;
           let brush =
             voxel_data::brush::T {
               bounds:
@@ -234,6 +236,41 @@ pub fn apply_client_update<UpdateGaia>(
           update_gaia(update_gaia::Message::Brush(brush));
         });
       },
+      protocol::ClientToServer::SavePrefab(client_id, name, bounds) => {
+        let prefab = {
+          let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+          prefab::capture(&voxels, &bounds)
+        };
+        match prefab.and_then(|prefab| prefab::save(&name, &prefab)) {
+          Ok(()) => info!("Saved prefab {:?}", name),
+          Err(err) => warn!("Couldn't save prefab {:?}: {}", name, err),
+        }
+        send_prefabs(server, client_id);
+      },
+      protocol::ClientToServer::ListPrefabs(client_id) => {
+        send_prefabs(server, client_id);
+      },
+      protocol::ClientToServer::PastePrefab(player_id, name, rotation) => {
+        let prefab =
+          match prefab::load(&name) {
+            Ok(prefab) => prefab,
+            Err(err) => {
+              warn!("Couldn't load prefab {:?}: {}", name, err);
+              return
+            },
+          };
+
+        let bounds = cast(server, player_id);
+
+        bounds.map(|bounds| {
+          // Stand the prefab on top of whatever we're looking at.
+          let (low, high) = bounds.corners();
+          let mut bottom = low.add_v(&high.to_vec()).div_s(2.0);
+          bottom.y = high.y;
+
+          update_gaia(update_gaia::Message::Brush(prefab.brush(&bottom, rotation)));
+        });
+      },
     };
   })
 }
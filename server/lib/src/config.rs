@@ -0,0 +1,154 @@
+//! Server configuration, settable from the command line.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use terrain;
+use terrain::biome::heightmap;
+
+#[allow(missing_docs)]
+pub struct T {
+  pub terrain_seed: u32,
+  pub terrain: terrain::Source,
+  /// Serve metrics in the Prometheus text format here, e.g. "127.0.0.1:9101".
+  pub metrics_address: Option<String>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    terrain_seed: 0,
+    terrain: terrain::Source::Demo,
+    metrics_address: None,
+  }
+}
+
+/// How to use the flags understood by `parse`.
+pub const USAGE: &'static str = "\
+  --seed N                 Seed for terrain generation.
+  --heightmap PATH         Generate terrain from a grayscale PNG heightmap.
+  --raw16 WIDTHxHEIGHT     The heightmap is raw little-endian 16-bit samples instead.
+  --material-map PATH      RGB PNG painting surface materials onto the heightmap.
+  --clamp                  Extend the heightmap's edges instead of tiling it.
+  --horizontal-scale N     World units per heightmap pixel.
+  --min-height N           Height of the darkest heightmap samples.
+  --max-height N           Height of the brightest heightmap samples.
+  --detail N               Amplitude of the noise added to the heightmap.
+  --metrics-address ADDR   Serve Prometheus metrics over HTTP at ADDR, e.g. 127.0.0.1:9101.";
+
+fn value<Args, X>(args: &mut Args, flag: &str) -> Result<X, String> where
+  Args: Iterator<Item=String>,
+  X: FromStr,
+{
+  let arg = try!(args.next().ok_or_else(|| format!("{} needs a value", flag)));
+  X::from_str(&arg).map_err(|_| format!("Invalid value for {}: {:?}", flag, arg))
+}
+
+fn dimensions(s: &str) -> Option<(u32, u32)> {
+  let mut parts = s.split('x');
+  let width = parts.next().and_then(|w| u32::from_str(w).ok());
+  let height = parts.next().and_then(|h| u32::from_str(h).ok());
+  match (width, height, parts.next()) {
+    (Some(width), Some(height), None) => Some((width, height)),
+    _ => None,
+  }
+}
+
+/// Parse flags (see `USAGE`) on top of the defaults.
+pub fn parse<Args>(mut args: Args) -> Result<T, String> where
+  Args: Iterator<Item=String>,
+{
+  let mut config = new();
+
+  // Heightmap settings can come before --heightmap, so hold on to them until the end.
+  let mut heightmap_path = None;
+  let mut raw16 = None;
+  let mut material_map = None;
+  let mut clamp = false;
+  let mut horizontal_scale = None;
+  let mut min_height = None;
+  let mut max_height = None;
+  let mut detail = None;
+
+  while let Some(flag) = args.next() {
+    match flag.as_ref() {
+      "--seed" => config.terrain_seed = try!(value(&mut args, &flag)),
+      "--heightmap" => heightmap_path = Some(PathBuf::from(try!(value::<_, String>(&mut args, &flag)))),
+      "--raw16" => {
+        let s: String = try!(value(&mut args, &flag));
+        raw16 = Some(try!(dimensions(&s).ok_or_else(|| format!("Expected WIDTHxHEIGHT, got {:?}", s))));
+      },
+      "--material-map" => material_map = Some(PathBuf::from(try!(value::<_, String>(&mut args, &flag)))),
+      "--clamp" => clamp = true,
+      "--horizontal-scale" => horizontal_scale = Some(try!(value(&mut args, &flag))),
+      "--min-height" => min_height = Some(try!(value(&mut args, &flag))),
+      "--max-height" => max_height = Some(try!(value(&mut args, &flag))),
+      "--detail" => detail = Some(try!(value(&mut args, &flag))),
+      "--metrics-address" => config.metrics_address = Some(try!(value(&mut args, &flag))),
+      _ => return Err(format!("Unrecognized argument {:?}. Options:\n{}", flag, USAGE)),
+    }
+  }
+
+  match heightmap_path {
+    None => {
+      let has_heightmap_settings =
+        raw16.is_some() || material_map.is_some() || clamp || horizontal_scale.is_some() ||
+        min_height.is_some() || max_height.is_some() || detail.is_some();
+      if has_heightmap_settings {
+        return Err(String::from("Heightmap settings were given without --heightmap"))
+      }
+    },
+    Some(path) => {
+      let mut settings = heightmap::Config::new(path);
+      if let Some((width, height)) = raw16 {
+        settings.format = heightmap::Format::Raw16 { width: width, height: height };
+      }
+      settings.material_map = material_map;
+      if clamp {
+        settings.edges = heightmap::Edges::Clamp;
+      }
+      settings.horizontal_scale = horizontal_scale.unwrap_or(settings.horizontal_scale);
+      settings.min_height = min_height.unwrap_or(settings.min_height);
+      settings.max_height = max_height.unwrap_or(settings.max_height);
+      settings.detail = detail.unwrap_or(settings.detail);
+      if !(settings.horizontal_scale > 0.0) {
+        return Err(format!("--horizontal-scale must be positive, got {}", settings.horizontal_scale))
+      }
+      if settings.min_height > settings.max_height {
+        return Err(format!(
+          "--min-height {} is above --max-height {}",
+          settings.min_height, settings.max_height,
+        ))
+      }
+      config.terrain = terrain::Source::Heightmap(settings);
+    },
+  }
+
+  Ok(config)
+}
+
+#[test]
+fn parses_heightmap_settings() {
+  let args = ["--raw16", "64x32", "--heightmap", "map.raw", "--clamp", "--seed", "3"];
+  let config = parse(args.iter().map(|s| String::from(*s))).unwrap();
+  assert_eq!(config.terrain_seed, 3);
+  match config.terrain {
+    terrain::Source::Heightmap(settings) => {
+      assert_eq!(settings.heightmap, PathBuf::from("map.raw"));
+      assert_eq!(settings.format, heightmap::Format::Raw16 { width: 64, height: 32 });
+      assert_eq!(settings.edges, heightmap::Edges::Clamp);
+    },
+    terrain::Source::Demo => panic!("Expected a heightmap"),
+  }
+
+  assert!(parse(vec!(String::from("--clamp")).into_iter()).is_err());
+}
+
+#[test]
+fn rejects_bad_heightmap_scales() {
+  let parse_strs = |args: &[&str]| parse(args.iter().map(|s| String::from(*s)));
+  assert!(parse_strs(&["--heightmap", "map.png", "--horizontal-scale", "0"]).is_err());
+  assert!(parse_strs(&["--heightmap", "map.png", "--horizontal-scale", "-2"]).is_err());
+  assert!(parse_strs(&["--heightmap", "map.png", "--min-height", "10", "--max-height", "5"]).is_err());
+  assert!(parse_strs(&["--heightmap", "map.png", "--min-height", "5", "--max-height", "10"]).is_ok());
+}
@@ -4,6 +4,7 @@ use common::entity_id;
 use common::id_allocator;
 use common::surroundings_loader::SurroundingsLoader;
 
+use lod;
 use mob;
 use server;
 
@@ -111,7 +112,8 @@ fn add_mob(
       behavior: behavior,
       entity_id: entity_id,
       owner_id: id_allocator::allocate(&server.owner_allocator),
-      surroundings_loader: SurroundingsLoader::new(8, Vec::new()),
+      surroundings_loader: SurroundingsLoader::new(mob::SURROUNDINGS_RADIUS, Vec::new()),
+      physics_lod: lod::COARSEST,
     };
 
   server.physics.lock().unwrap().insert_misc(entity_id, &bounds);
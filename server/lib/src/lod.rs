@@ -11,12 +11,79 @@ pub use self::T::*;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Level of detail a block can be loaded at.
 pub enum T {
-  /// Variable detail as an index into various T arrays.
+  /// Collide with the block's own voxel.
   Full,
+  /// Collide with an approximation of the block from the voxel of this lg_size that contains it.
+  /// Bigger voxels are coarser, but cheaper to generate.
+  Coarse(i16),
   /// No detail: an invisible solid block that can be loaded synchronously.
   Placeholder,
 }
 
+/// The coarsest physics LOD used for anything that isn't a placeholder.
+pub const COARSEST: T = T::Coarse(3);
+
+/// Distances from the nearest player within which each physics LOD is used, from finest to coarsest.
+/// Past the last one, `COARSEST` is used.
+const THRESHOLDS: [(i32, T); 3] = [
+  (32, T::Full),
+  (64, T::Coarse(1)),
+  (128, T::Coarse(2)),
+];
+
+/// How far past a threshold something has to be before it switches to a coarser LOD,
+/// so that things hovering around a threshold don't keep reloading their surroundings.
+const HYSTERESIS: i32 = 8;
+
+impl T {
+  /// The lg_size of the voxels this LOD's collision comes from.
+  /// Placeholders don't come from voxels at all.
+  pub fn lg_size(&self) -> Option<i16> {
+    match *self {
+      T::Full => Some(0),
+      T::Coarse(lg_size) => Some(lg_size),
+      T::Placeholder => None,
+    }
+  }
+
+  // Higher is more detailed.
+  fn detail(&self) -> i32 {
+    match *self {
+      T::Full => 0,
+      T::Coarse(lg_size) => -(lg_size as i32),
+      T::Placeholder => i32::min_value(),
+    }
+  }
+}
+
+/// Choose a physics LOD for something at a distance from the nearest player.
+/// With no players (a `None` distance), that's `COARSEST`.
+pub fn of_player_distance(distance: Option<i32>) -> T {
+  let distance =
+    match distance {
+      None => return COARSEST,
+      Some(d) => d,
+    };
+  THRESHOLDS.iter()
+    .find(|&&(threshold, _)| distance < threshold)
+    .map(|&(_, lod)| lod)
+    .unwrap_or(COARSEST)
+}
+
+/// Like `of_player_distance`, but only get coarser than `current` once well past the threshold.
+pub fn update_for_player_distance(current: T, distance: Option<i32>) -> T {
+  let lod = of_player_distance(distance);
+  if lod >= current {
+    return lod
+  }
+  let lod = of_player_distance(distance.map(|d| d - HYSTERESIS));
+  if lod < current {
+    lod
+  } else {
+    current
+  }
+}
+
 impl PartialOrd for T {
   fn partial_cmp(&self, other: &T) -> Option<Ordering> {
     Some(self.cmp(other))
@@ -25,12 +92,7 @@ impl PartialOrd for T {
 
 impl Ord for T {
   fn cmp(&self, other: &T) -> Ordering {
-    match (*self, *other) {
-      (T::Placeholder, T::Placeholder) => Ordering::Equal,
-      (T::Placeholder, T::Full) => Ordering::Less,
-      (T::Full, T::Placeholder) => Ordering::Greater,
-      (T::Full, T::Full) => Ordering::Equal,
-    }
+    self.detail().cmp(&other.detail())
   }
 }
 
@@ -60,6 +122,11 @@ impl Map {
     })
   }
 
+  /// The finest T any owner wants at `position`, if it has any owners.
+  pub fn lod(&self, position: &voxel::bounds::T) -> Option<T> {
+    self.loaded.get(position).map(|bls| bls.loaded_lod)
+  }
+
   // TODO: Can probably get rid of the LODChange returns; we only assert with em.
 
   /// Acquire/update an owner's handle in `position`.
@@ -125,6 +192,20 @@ impl Map {
     }
   }
 
+  /// How many blocks are loaded at each T, finest first.
+  pub fn loaded_counts(&self) -> Vec<(T, usize)> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for block_load_state in self.loaded.values() {
+      let lod = block_load_state.loaded_lod;
+      match counts.iter().position(|&(l, _)| l == lod) {
+        None => counts.push((lod, 1)),
+        Some(i) => counts[i].1 += 1,
+      }
+    }
+    counts.sort_by(|&(x, _), &(y, _)| y.cmp(&x));
+    counts
+  }
+
   /// Release an owner's handle on `position`.
   /// Returns (owner's previous T, T change if the location's T changes).
   pub fn remove(
@@ -225,3 +306,48 @@ struct BlockLoadState {
   pub owner_lods: Vec<(OwnerId, T)>,
   pub loaded_lod: T,
 }
+
+#[test]
+fn finer_lods_are_greater() {
+  assert!(Placeholder < Coarse(3));
+  assert!(Coarse(3) < Coarse(1));
+  assert!(Coarse(1) < Full);
+  assert!(Full == Full);
+}
+
+#[test]
+fn nearer_players_mean_finer_lods() {
+  assert_eq!(of_player_distance(Some(0)), Full);
+  assert_eq!(of_player_distance(Some(40)), Coarse(1));
+  assert_eq!(of_player_distance(Some(100)), Coarse(2));
+  assert_eq!(of_player_distance(Some(1000)), COARSEST);
+  assert_eq!(of_player_distance(None), COARSEST);
+}
+
+#[test]
+fn lods_get_coarser_past_the_hysteresis() {
+  assert_eq!(update_for_player_distance(Coarse(2), Some(0)), Full);
+  assert_eq!(update_for_player_distance(Full, Some(32)), Full);
+  assert_eq!(update_for_player_distance(Full, Some(40)), Coarse(1));
+  assert_eq!(update_for_player_distance(Full, None), COARSEST);
+}
+
+#[test]
+fn counts_blocks_by_loaded_lod() {
+  let block = |x| voxel::bounds::new(x, 0, 0, 3);
+  let mut map = Map::new();
+  map.insert(block(0), Coarse(2), OwnerId(0));
+  map.insert(block(0), Full, OwnerId(1));
+  map.insert(block(1), Coarse(2), OwnerId(0));
+  map.insert(block(2), Placeholder, OwnerId(0));
+  map.insert(block(3), Coarse(2), OwnerId(1));
+  assert_eq!(map.loaded_counts(), vec!((Full, 1), (Coarse(2), 2), (Placeholder, 1)));
+
+  assert_eq!(map.lod(&block(0)), Some(Full));
+
+  map.remove(block(0), OwnerId(1));
+  assert_eq!(map.loaded_counts(), vec!((Coarse(2), 3), (Placeholder, 1)));
+  assert_eq!(map.lod(&block(0)), Some(Coarse(2)));
+  map.remove(block(0), OwnerId(0));
+  assert_eq!(map.lod(&block(0)), None);
+}
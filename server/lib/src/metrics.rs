@@ -0,0 +1,184 @@
+//! Server metrics: how long world updates take, who's connected, how much terrain is loaded,
+//! and how much is being sent. They're logged periodically as JSON and can be served live in the
+//! Prometheus text format.
+
+use rustc_serialize::json;
+use std::sync::Mutex;
+
+use common::prometheus;
+use common::samples;
+use common::samples::Summary;
+
+use lod;
+use octree;
+use server;
+
+#[allow(missing_docs)]
+pub struct T {
+  ticks: Mutex<samples::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    ticks: Mutex::new(samples::new()),
+  }
+}
+
+impl T {
+  /// Record how long a world update took.
+  pub fn record_tick(&self, ns: u64) {
+    self.ticks.lock().unwrap().push(ns);
+  }
+}
+
+/// The number of terrain blocks loaded at some `lod::T`.
+#[derive(Debug, Clone, PartialEq, RustcEncodable)]
+#[allow(missing_docs)]
+pub struct LodBlocks {
+  pub lod: String,
+  pub blocks: usize,
+}
+
+fn lod_label(lod: lod::T) -> String {
+  match lod {
+    lod::Full => String::from("full"),
+    lod::Coarse(lg_size) => format!("coarse{}", lg_size),
+    lod::Placeholder => String::from("placeholder"),
+  }
+}
+
+/// How much has been sent to a client.
+#[derive(Debug, Clone, PartialEq, RustcEncodable)]
+#[allow(missing_docs)]
+pub struct ClientBytes {
+  pub client: String,
+  pub bytes: u64,
+}
+
+/// The metrics at some point in time.
+#[derive(Debug, Clone, RustcEncodable)]
+#[allow(missing_docs)]
+pub struct Snapshot {
+  /// How long world updates take.
+  pub tick_ms: Option<Summary>,
+  pub clients: usize,
+  pub players: usize,
+  pub mobs: usize,
+  /// Terrain loads and edits waiting to be handled.
+  pub gaia_updates: usize,
+  /// Finest first.
+  pub terrain_blocks: Vec<LodBlocks>,
+  pub terrain_octree: octree::Size,
+  pub misc_octree: octree::Size,
+  pub bytes_sent: Vec<ClientBytes>,
+}
+
+/// Take a snapshot of the server's metrics. `gaia_updates` is the length of the gaia queue,
+/// which the server doesn't own.
+pub fn snapshot(server: &server::T, gaia_updates: usize) -> Snapshot {
+  let mut bytes_sent: Vec<_> =
+    server.clients.lock().unwrap().iter()
+    .map(|(&id, client)| (id, client.bytes_sent))
+    .collect();
+  bytes_sent.sort();
+
+  let (terrain_octree, misc_octree) = {
+    let physics = server.physics.lock().unwrap();
+    (physics.terrain_octree.size(), physics.misc_octree.size())
+  };
+
+  Snapshot {
+    tick_ms: server.metrics.ticks.lock().unwrap().summary(),
+    clients: bytes_sent.len(),
+    players: server.players.lock().unwrap().len(),
+    mobs: server.mobs.lock().unwrap().len(),
+    gaia_updates: gaia_updates,
+    terrain_blocks:
+      server.terrain_loader.lod_map.lock().unwrap().loaded_counts().into_iter()
+      .map(|(lod, blocks)| LodBlocks { lod: lod_label(lod), blocks: blocks })
+      .collect(),
+    terrain_octree: terrain_octree,
+    misc_octree: misc_octree,
+    bytes_sent:
+      bytes_sent.into_iter()
+      .map(|(id, bytes)| ClientBytes { client: id.to_string(), bytes: bytes })
+      .collect(),
+  }
+}
+
+impl Snapshot {
+  /// The metrics as one line of JSON.
+  pub fn to_json(&self) -> String {
+    json::encode(self).unwrap()
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_prometheus(&self) -> String {
+    let mut text = prometheus::new();
+    if let Some(ref tick_ms) = self.tick_ms {
+      samples::to_prometheus(&mut text, "playform_server_tick_ms", "Milliseconds spent updating the world.", tick_ms);
+    }
+
+    let counts = [
+      ("playform_server_clients", "Connected clients.", self.clients),
+      ("playform_server_players", "Players in the world.", self.players),
+      ("playform_server_mobs", "Mobs in the world.", self.mobs),
+      ("playform_server_gaia_updates", "Terrain updates waiting to be handled.", self.gaia_updates),
+    ];
+    for &(name, help, count) in &counts {
+      text.metric(name, "gauge", help);
+      text.sample(name, &[], count as f64);
+    }
+
+    text.metric("playform_server_terrain_blocks", "gauge", "Terrain blocks loaded, by physics LOD.");
+    for lod_blocks in &self.terrain_blocks {
+      text.sample("playform_server_terrain_blocks", &[("lod", &lod_blocks.lod)], lod_blocks.blocks as f64);
+    }
+
+    text.metric("playform_server_octree_nodes", "gauge", "Nodes in the physics octrees.");
+    text.sample("playform_server_octree_nodes", &[("octree", "terrain")], self.terrain_octree.nodes as f64);
+    text.sample("playform_server_octree_nodes", &[("octree", "misc")], self.misc_octree.nodes as f64);
+    text.metric("playform_server_octree_entries", "gauge", "Entries in the physics octrees' leaves.");
+    text.sample("playform_server_octree_entries", &[("octree", "terrain")], self.terrain_octree.entries as f64);
+    text.sample("playform_server_octree_entries", &[("octree", "misc")], self.misc_octree.entries as f64);
+
+    text.metric("playform_server_bytes_sent", "counter", "Bytes sent to each client.");
+    for client_bytes in &self.bytes_sent {
+      text.sample("playform_server_bytes_sent", &[("client", &client_bytes.client)], client_bytes.bytes as f64);
+    }
+    text.into_string()
+  }
+}
+
+#[test]
+fn exports_snapshots() {
+  let snapshot =
+    Snapshot {
+      tick_ms: samples::summarize(vec!(2_000_000).into_iter()),
+      clients: 1,
+      players: 1,
+      mobs: 3,
+      gaia_updates: 5,
+      terrain_blocks: vec!(
+        LodBlocks { lod: lod_label(lod::Full), blocks: 4 },
+        LodBlocks { lod: lod_label(lod::Coarse(2)), blocks: 9 },
+      ),
+      terrain_octree: octree::Size { nodes: 7, entries: 13 },
+      misc_octree: octree::Size { nodes: 1, entries: 4 },
+      bytes_sent: vec!(ClientBytes { client: String::from("0"), bytes: 1024 }),
+    };
+
+  let json = snapshot.to_json();
+  assert!(!json.contains('\n'));
+  let json = json::Json::from_str(&json).unwrap();
+  assert_eq!(json.find_path(&["tick_ms", "max_ms"]).and_then(|x| x.as_f64()), Some(2.0));
+  assert_eq!(json.find_path(&["misc_octree", "entries"]).and_then(|x| x.as_u64()), Some(4));
+
+  let prometheus = snapshot.to_prometheus();
+  assert!(prometheus.contains("\nplayform_server_tick_ms_count 1\n"));
+  assert!(prometheus.contains("\nplayform_server_mobs 3\n"));
+  assert!(prometheus.contains("\nplayform_server_terrain_blocks{lod=\"coarse2\"} 9\n"));
+  assert!(prometheus.contains("\nplayform_server_octree_entries{octree=\"terrain\"} 13\n"));
+  assert!(prometheus.contains("\nplayform_server_bytes_sent{client=\"0\"} 1024\n"));
+}
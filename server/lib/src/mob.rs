@@ -6,6 +6,9 @@ use common::surroundings_loader::SurroundingsLoader;
 use lod;
 use server;
 
+/// How far around themselves mobs load terrain to collide with.
+pub const SURROUNDINGS_RADIUS: i32 = 8;
+
 pub type Behavior = fn(&server::T, &mut Mob);
 
 pub struct Mob {
@@ -16,4 +19,6 @@ pub struct Mob {
   pub entity_id: entity_id::T,
   pub owner_id: lod::OwnerId,
   pub surroundings_loader: SurroundingsLoader,
+  /// The LOD of the terrain this mob collides with, which depends on how close players are.
+  pub physics_lod: lod::T,
 }
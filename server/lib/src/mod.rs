@@ -41,6 +41,7 @@ mod client_recv_thread;
 mod in_progress_terrain;
 mod init_mobs;
 mod lod;
+mod metrics;
 mod mob;
 mod octree;
 mod physics;
@@ -51,5 +52,9 @@ mod sun;
 mod terrain_loader;
 mod update_gaia;
 mod update_world;
+mod water;
+
+pub mod config;
+pub mod prefab;
 
 pub use run::run;
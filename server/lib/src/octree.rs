@@ -82,6 +82,15 @@ enum OctreeContents<V> {
   Branch(Branches<V>),
 }
 
+/// How big an octree has grown.
+#[derive(Debug, Clone, Copy, PartialEq, Default, RustcEncodable)]
+pub struct Size {
+  /// Branches and leaves.
+  pub nodes: usize,
+  /// Entries in leaves. Anything that straddles a split is counted once per leaf it's in.
+  pub entries: usize,
+}
+
 // TODO: allow inserting things with a "mobile" flag; don't subdivide those objects.
 pub struct Octree<V> {
   parent: *mut Octree<V>,
@@ -290,4 +299,18 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
     self.remove(bounds, v);
     self.insert_from(new_bounds, v)
   }
+
+  pub fn size(&self) -> Size {
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => Size { nodes: 1, entries: vs.len() },
+      OctreeContents::Branch(ref b) => {
+        let low = b.low_tree.size();
+        let high = b.high_tree.size();
+        Size {
+          nodes: 1 + low.nodes + high.nodes,
+          entries: low.entries + high.entries,
+        }
+      },
+    }
+  }
 }
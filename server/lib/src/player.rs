@@ -14,10 +14,14 @@ use lod;
 use physics::Physics;
 use server;
 use update_gaia;
-use update_world::load_placeholders;
+use update_world::load_at_lod;
 
 const MAX_JUMP_FUEL: u32 = 4;
 const MAX_STEP_HEIGHT: f32 = 1.0;
+// Upward acceleration while submerged. This is stronger than gravity, so players float.
+const WATER_BUOYANCY: f32 = 0.15;
+// Multiplier applied to speed every update while submerged.
+const WATER_DRAG: f32 = 0.8;
 
 #[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 pub enum Collision {
@@ -183,6 +187,7 @@ impl T {
               &server.physics,
               &pos,
               owner,
+              request_block,
             );
           },
         }
@@ -191,8 +196,9 @@ impl T {
       let owner = self.solid_owner;
       for (pos, load_type) in self.solid_boundary.updates(&player_position) {
         let block_position = voxel::bounds::new(pos.x, pos.y, pos.z, 0);
-        load_placeholders(
+        load_at_lod(
           owner,
+          lod::Placeholder,
           server,
           request_block,
           &block_position,
@@ -236,6 +242,13 @@ impl T {
         .mul_v(&self.walk_accel);
     self.speed.add_self_v(&walk_v);
     self.speed.add_self_v(&self.accel);
+
+    let in_water =
+      server.terrain_loader.terrain.material_at(&self.position) == Some(voxel::Material::Water);
+    if in_water {
+      self.speed.add_self_v(&Vector3::new(0.0, WATER_BUOYANCY, 0.0));
+      self.speed.mul_self_s(WATER_DRAG);
+    }
     // friction
     self.speed.mul_self_v(&Vector3::new(0.7, 0.99, 0.7 as f32));
 
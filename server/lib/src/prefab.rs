@@ -0,0 +1,314 @@
+//! Prefabs are regions of voxels saved to disk, which can be stamped back into the world
+//! elsewhere as a brush.
+
+use bincode;
+use bincode::SizeLimit;
+use cgmath::{Aabb3, Point, Point3, Vector, Vector3, EuclideanVector};
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use common::voxel;
+
+/// Where prefabs are saved, relative to the working directory.
+const DIRECTORY: &'static str = "prefabs";
+const EXTENSION: &'static str = "prefab";
+
+/// The most voxels a single prefab can capture.
+const MAX_VOLUME: i64 = 1 << 21;
+
+/// A box of materials, sampled at the low corner of each lg_size 0 voxel.
+#[derive(Debug, Clone, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct T {
+  /// The number of voxels along each axis.
+  pub size: Vector3<i32>,
+  /// Indexed by `(x * size.y + y) * size.z + z`.
+  pub materials: Vec<voxel::Material>,
+}
+
+fn invalid_input(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn invalid_data<E: ::std::fmt::Debug>(err: E) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))
+}
+
+fn path(name: &str) -> io::Result<PathBuf> {
+  let is_valid =
+    !name.is_empty() &&
+    name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+  if !is_valid {
+    return Err(invalid_input(format!("Invalid prefab name {:?}", name)))
+  }
+  let mut path = PathBuf::from(DIRECTORY);
+  path.push(name);
+  path.set_extension(EXTENSION);
+  Ok(path)
+}
+
+/// Copy the finest voxels in `bounds` out of `voxels`. Voxels that aren't loaded are captured as empty.
+pub fn capture(voxels: &voxel::tree::T, bounds: &Aabb3<i32>) -> io::Result<T> {
+  let size = bounds.max.sub_p(&bounds.min);
+  if size.x <= 0 || size.y <= 0 || size.z <= 0 {
+    return Err(invalid_input(format!("Empty prefab bounds {:?}", bounds)))
+  }
+  let volume = size.x as i64 * size.y as i64 * size.z as i64;
+  if volume > MAX_VOLUME {
+    return Err(invalid_input(format!("Prefab volume {} exceeds {}", volume, MAX_VOLUME)))
+  }
+
+  let mut materials = Vec::with_capacity(volume as usize);
+  for x in bounds.min.x .. bounds.max.x {
+  for y in bounds.min.y .. bounds.max.y {
+  for z in bounds.min.z .. bounds.max.z {
+    let material =
+      match voxels.get(&voxel::bounds::new(x, y, z, 0)) {
+        Some(&voxel::Volume(material)) => material,
+        Some(&voxel::Surface(ref surface)) => surface.corner,
+        None => voxel::Material::Empty,
+      };
+    materials.push(material);
+  }}}
+
+  Ok(T {
+    size: size,
+    materials: materials,
+  })
+}
+
+/// Write a prefab to disk, replacing any existing prefab with the same name.
+pub fn save(name: &str, prefab: &T) -> io::Result<()> {
+  let path = try!(path(name));
+  try!(fs::create_dir_all(DIRECTORY));
+  let bytes = try!(bincode::rustc_serialize::encode(prefab, SizeLimit::Infinite).map_err(invalid_data));
+  let mut file = try!(fs::File::create(path));
+  file.write_all(bytes.as_ref())
+}
+
+#[allow(missing_docs)]
+pub fn load(name: &str) -> io::Result<T> {
+  let path = try!(path(name));
+  let mut bytes = Vec::new();
+  let mut file = try!(fs::File::open(path));
+  try!(file.read_to_end(&mut bytes));
+  let prefab: T = try!(bincode::rustc_serialize::decode(bytes.as_ref()).map_err(invalid_data));
+  if prefab.materials.len() as i64 != prefab.size.x as i64 * prefab.size.y as i64 * prefab.size.z as i64 {
+    return Err(invalid_data(format!("Prefab {:?} has the wrong number of voxels", name)))
+  }
+  Ok(prefab)
+}
+
+/// The names of all the saved prefabs, sorted.
+pub fn list() -> io::Result<Vec<String>> {
+  let entries =
+    match fs::read_dir(DIRECTORY) {
+      Ok(entries) => entries,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err),
+    };
+
+  let mut names = Vec::new();
+  for entry in entries {
+    let path = try!(entry).path();
+    if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+      continue
+    }
+    if let Some(name) = path.file_stem().and_then(|s| s.to_str()) {
+      names.push(name.to_owned());
+    }
+  }
+  names.sort();
+  Ok(names)
+}
+
+/// A prefab as a mosaic, with the center of its bottom face at the origin, rotated about the y axis.
+pub struct Placed {
+  prefab: T,
+  cos: f32,
+  sin: f32,
+}
+
+unsafe impl Send for Placed {}
+
+impl T {
+  fn index(&self, x: i32, y: i32, z: i32) -> Option<usize> {
+    if x < 0 || y < 0 || z < 0 || x >= self.size.x || y >= self.size.y || z >= self.size.z {
+      None
+    } else {
+      Some(((x * self.size.y + y) * self.size.z + z) as usize)
+    }
+  }
+
+  fn material(&self, x: i32, y: i32, z: i32) -> voxel::Material {
+    match self.index(x, y, z) {
+      None => voxel::Material::Empty,
+      Some(i) => self.materials[i],
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn place(self, rotation: f32) -> Placed {
+    Placed {
+      prefab: self,
+      cos: rotation.cos(),
+      sin: rotation.sin(),
+    }
+  }
+
+  /// A brush that stamps this prefab with the center of its bottom face at `bottom`,
+  /// rotated by `rotation` radians about the y axis.
+  pub fn brush(
+    self,
+    bottom: &Point3<f32>,
+    rotation: f32,
+  ) -> voxel::brush::T<Box<voxel::mosaic::T<voxel::Material> + Send>> {
+    let half_width = Vector3::new(self.size.x as f32, 0.0, self.size.z as f32).mul_s(0.5);
+    let r = half_width.length() + 1.0;
+    let height = self.size.y as f32 + 1.0;
+
+    let low = bottom.add_v(&Vector3::new(-r, -1.0, -r));
+    let high = bottom.add_v(&Vector3::new(r, height, r));
+    let placed =
+      voxel::mosaic::translation::T {
+        translation: bottom.to_vec(),
+        mosaic: self.place(rotation),
+      };
+
+    voxel::brush::T {
+      bounds:
+        Aabb3::new(
+          Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32),
+          Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32),
+        ),
+      mosaic: Box::new(placed) as Box<voxel::mosaic::T<voxel::Material> + Send>,
+      min_lg_size: 0,
+    }
+  }
+}
+
+impl Placed {
+  /// Transform a point into the prefab's voxel coordinates.
+  fn to_prefab(&self, p: &Point3<f32>) -> Point3<f32> {
+    // Rotate by -rotation to undo the placement.
+    let x = self.cos * p.x + self.sin * p.z;
+    let z = -self.sin * p.x + self.cos * p.z;
+    Point3::new(
+      x + self.prefab.size.x as f32 / 2.0,
+      p.y,
+      z + self.prefab.size.z as f32 / 2.0,
+    )
+  }
+
+  /// Run `f` on each voxel corner around `p` with its trilinear weight.
+  fn with_corners<F>(&self, p: &Point3<f32>, mut f: F) where
+    F: FnMut(voxel::Material, f32),
+  {
+    let p = self.to_prefab(p);
+    let low = Point3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    let t = p.sub_p(&low);
+    for &dx in &[0, 1] {
+    for &dy in &[0, 1] {
+    for &dz in &[0, 1] {
+      let weight =
+        (if dx == 0 { 1.0 - t.x } else { t.x }) *
+        (if dy == 0 { 1.0 - t.y } else { t.y }) *
+        (if dz == 0 { 1.0 - t.z } else { t.z });
+      let material = self.prefab.material(low.x as i32 + dx, low.y as i32 + dy, low.z as i32 + dz);
+      f(material, weight);
+    }}}
+  }
+}
+
+impl voxel::field::T for Placed {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let mut density = 0.0;
+    self.with_corners(p, |material, weight| {
+      // Water takes up space, but isn't solid; a captured lake shouldn't paste as a block.
+      let occupancy = if material.is_solid() { 1.0 } else { -1.0 };
+      density += occupancy * weight;
+    });
+    density
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let h = 0.5;
+    let mut d = |dx, dy, dz| voxel::field::T::density(self, &p.add_v(&Vector3::new(dx, dy, dz)));
+    let gradient =
+      Vector3::new(
+        d(h, 0.0, 0.0) - d(-h, 0.0, 0.0),
+        d(0.0, h, 0.0) - d(0.0, -h, 0.0),
+        d(0.0, 0.0, h) - d(0.0, 0.0, -h),
+      );
+    if gradient.length2() == 0.0 {
+      return Vector3::new(0.0, 1.0, 0.0)
+    }
+    // Density increases inwards.
+    -gradient.normalize()
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for Placed {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    // The heaviest solid corner, so edges don't get hollowed out by their non-solid neighbors.
+    // Failing that, the heaviest non-empty one, so water stays water.
+    let mut heaviest_solid = None;
+    let mut heaviest = None;
+    self.with_corners(p, |material, weight| {
+      if material == voxel::Material::Empty {
+        return
+      }
+      let heaviest = if material.is_solid() { &mut heaviest_solid } else { &mut heaviest };
+      let is_heavier =
+        match *heaviest {
+          None => true,
+          Some((_, max_weight)) => weight > max_weight,
+        };
+      if is_heavier {
+        *heaviest = Some((material, weight));
+      }
+    });
+    heaviest_solid.or(heaviest).map(|(material, _)| material)
+  }
+}
+
+#[test]
+fn capture_reads_the_finest_voxels() {
+  let mut voxels = voxel::tree::new();
+  let stone = voxel::bounds::new(1, 0, 2, 0);
+  voxels.get_mut_or_create(&stone).force_branches().data = Some(voxel::Volume(voxel::Material::Stone));
+
+  let prefab =
+    capture(&voxels, &Aabb3::new(Point3::new(0, 0, 0), Point3::new(2, 1, 3)))
+    .unwrap();
+
+  assert_eq!(prefab.size, Vector3::new(2, 1, 3));
+  assert_eq!(prefab.materials.len(), 6);
+  for x in 0 .. 2 {
+  for z in 0 .. 3 {
+    let expected =
+      if (x, z) == (1, 2) {
+        voxel::Material::Stone
+      } else {
+        voxel::Material::Empty
+      };
+    assert_eq!(prefab.material(x, 0, z), expected);
+  }}
+
+  let empty = Aabb3::new(Point3::new(0, 0, 0), Point3::new(0, 1, 1));
+  assert!(capture(&voxels, &empty).is_err());
+}
+
+#[test]
+fn water_is_pasted_as_water_not_solid() {
+  let prefab =
+    T {
+      size: Vector3::new(2, 1, 2),
+      materials: vec!(voxel::Material::Water; 4),
+    };
+  let mut placed = prefab.place(0.0);
+  let p = Point3::new(0.0, 0.0, 0.0);
+  assert!(voxel::field::T::density(&mut placed, &p) < 0.0);
+  assert_eq!(voxel::mosaic::T::material(&mut placed, &p), Some(voxel::Material::Water));
+}
@@ -8,30 +8,42 @@ use time;
 
 use common;
 use common::closure_series;
+use common::prometheus;
 use common::socket::ReceiveSocket;
 
 use client_recv_thread::apply_client_update;
+use config;
+use metrics;
 use server;
 use update_gaia;
 use update_gaia::update_gaia;
 use update_world::update_world;
 
+/// How often metrics are logged, in monitor thread ticks (seconds).
+const METRICS_LOG_INTERVAL: u32 = 10;
+
 #[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
+pub fn run(listen_url: &str, config: &config::T, quit_signal: &Mutex<bool>) {
   let gaia_updates = Mutex::new(std::collections::VecDeque::new());
 
   let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
   let listen_socket = Mutex::new(listen_socket);
 
-  let server = server::new();
+  let server = server::new(config);
   let server = &server;
 
   let mut threads = Vec::new();
 
   unsafe {
     threads.push(thread_scoped::scoped(|| {
+      let mut ticks = 0;
       while !*quit_signal.lock().unwrap() {
-        info!("Outstanding gaia updates: {}", gaia_updates.lock().unwrap().len());
+        let outstanding = gaia_updates.lock().unwrap().len();
+        info!("Outstanding gaia updates: {}", outstanding);
+        ticks += 1;
+        if ticks % METRICS_LOG_INTERVAL == 0 {
+          info!("Metrics: {}", metrics::snapshot(server, outstanding).to_json());
+        }
         std::thread::sleep(std::time::Duration::from_secs(1));
       }
 
@@ -39,6 +51,24 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     }))
   }
 
+  if let Some(ref address) = config.metrics_address {
+    unsafe {
+      let gaia_updates = &gaia_updates;
+      threads.push(thread_scoped::scoped(move || {
+        let render = || {
+          // Don't hold the queue's lock while taking the server's locks.
+          let outstanding = gaia_updates.lock().unwrap().len();
+          metrics::snapshot(server, outstanding).to_prometheus()
+        };
+        if let Err(err) = prometheus::serve(address, quit_signal, render) {
+          warn!("Couldn't serve metrics at {}: {}", address, err);
+        }
+
+        stopwatch::clone()
+      }))
+    }
+  }
+
   unsafe {
     let server = &server;
     let gaia_updates = &gaia_updates;
@@ -98,10 +128,12 @@ fn consider_world_update<'a, ToGaia>(
 {
   box move || {
     if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+      let start = time::precise_time_ns();
       update_world(
         server,
         &mut to_gaia,
       );
+      server.metrics.record_tick(time::precise_time_ns() - start);
       closure_series::Restart
     } else {
       closure_series::Continue
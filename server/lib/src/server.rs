@@ -9,20 +9,27 @@ use common::fnv_map;
 use common::id_allocator;
 use common::interval_timer::IntervalTimer;
 use common::socket::SendSocket;
+use common::voxel;
 
+use config;
 use init_mobs::init_mobs;
 use lod;
+use metrics;
 use mob;
 use physics::Physics;
 use player;
 use sun::Sun;
 use terrain_loader;
+use water;
 
 const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
+const WATER_TICK_NS: u64 = 200000000;
 
 pub struct Client {
   pub socket: SendSocket,
+  /// Bytes successfully sent to this client.
+  pub bytes_sent: u64,
 }
 
 impl Client {
@@ -31,7 +38,7 @@ impl Client {
     use bincode::rustc_serialize::encode;
     let msg = encode(&msg, SizeLimit::Infinite).unwrap();
     match self.socket.write(msg.as_ref()) {
-      Ok(()) => {},
+      Ok(()) => self.bytes_sent += msg.len() as u64,
       Err(err) => warn!("Error sending to client: {:?}", err),
     }
   }
@@ -52,12 +59,21 @@ pub struct T {
 
   pub clients: Mutex<fnv_map::T<protocol::ClientId, Client>>,
 
+  pub world_id: protocol::WorldId,
+  /// The version of every region that's been edited. Other regions are `protocol::UNEDITED_VERSION`.
+  pub voxel_versions: Mutex<fnv_map::T<Point3<i32>, u64>>,
+  /// Versions are handed out from here. It starts at the wall-clock time, so that edits made by
+  /// different runs of the server (which doesn't save them) don't share versions.
+  pub next_voxel_version: Mutex<u64>,
+
   pub sun: Mutex<Sun>,
+  pub water: Mutex<water::T>,
   pub update_timer: Mutex<IntervalTimer>,
+  pub metrics: metrics::T,
 }
 
 #[allow(missing_docs)]
-pub fn new() -> T {
+pub fn new(config: &config::T) -> T {
   let world_width: u32 = 1 << 11;
   let world_width = world_width as f32;
   let physics =
@@ -80,7 +96,7 @@ pub fn new() -> T {
     client_allocator: Mutex::new(id_allocator::new()),
 
     physics: Mutex::new(physics),
-    terrain_loader: terrain_loader::T::new(),
+    terrain_loader: terrain_loader::T::new(config),
     rng: {
       let seed = [0];
       let seed: &[usize] = &seed;
@@ -88,7 +104,23 @@ pub fn new() -> T {
     },
 
     clients: Mutex::new(fnv_map::new()),
+
+    world_id: {
+      let fingerprint =
+        match config.terrain.fingerprint() {
+          Ok(fingerprint) => fingerprint,
+          Err(err) => panic!("Couldn't read terrain: {}", err),
+        };
+      protocol::WorldId::new(config.terrain_seed, &fingerprint)
+    },
+    voxel_versions: Mutex::new(fnv_map::new()),
+    next_voxel_version: {
+      let now = time::get_time();
+      Mutex::new(now.sec as u64 * 1_000_000_000 + now.nsec as u64)
+    },
+
     sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+    water: Mutex::new(water::new(WATER_TICK_NS)),
 
     update_timer: {
       let now = time::precise_time_ns();
@@ -96,9 +128,35 @@ pub fn new() -> T {
       Mutex::new(
         IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
       )
-    }
+    },
+    metrics: metrics::new(),
   };
 
   init_mobs(&server);
   server
 }
+
+impl T {
+  /// Give new versions to the regions containing some changed voxels, and tell the clients.
+  /// This should be called before the changed voxels are sent.
+  pub fn update_voxel_versions(&self, updates: &[(voxel::bounds::T, voxel::T)]) {
+    let mut versions = Vec::new();
+    {
+      let mut voxel_versions = self.voxel_versions.lock().unwrap();
+      let mut next_voxel_version = self.next_voxel_version.lock().unwrap();
+      for &(ref bounds, _) in updates {
+        let region = protocol::version_region(bounds);
+        if versions.iter().any(|&(r, _)| r == region) {
+          continue
+        }
+        *next_voxel_version += 1;
+        voxel_versions.insert(region, *next_voxel_version);
+        versions.push((region, *next_voxel_version));
+      }
+    }
+
+    for (_, client) in self.clients.lock().unwrap().iter_mut() {
+      client.send(protocol::ServerToClient::VoxelVersions(versions.clone()));
+    }
+  }
+}
@@ -8,6 +8,7 @@ use common::fnv_map;
 use common::id_allocator;
 use common::voxel;
 
+use config;
 use in_progress_terrain;
 use lod;
 use physics::Physics;
@@ -24,13 +25,20 @@ pub struct T {
   pub terrain: terrain::T,
   pub in_progress_terrain: Mutex<in_progress_terrain::T>,
   pub lod_map: Mutex<lod::Map>,
-  pub loaded: Mutex<fnv_map::T<voxel::bounds::T, Vec<entity_id::T>>>,
+  /// The LOD each block's collision is actually loaded at, and its ids in the physics octree.
+  /// This can lag behind `lod_map` while new LODs are being generated.
+  pub loaded: Mutex<fnv_map::T<voxel::bounds::T, (lod::T, Vec<entity_id::T>)>>,
 }
 
 impl T {
-  pub fn new() -> T {
+  pub fn new(config: &config::T) -> T {
+    let terrain =
+      match terrain::T::new(config.terrain_seed, &config.terrain) {
+        Ok(terrain) => terrain,
+        Err(err) => panic!("Couldn't load terrain: {}", err),
+      };
     T {
-      terrain: terrain::T::new(terrain::Seed::new(0)),
+      terrain: terrain,
       in_progress_terrain: Mutex::new(in_progress_terrain::T::new()),
       lod_map: Mutex::new(lod::Map::new()),
       loaded: Mutex::new(fnv_map::new()),
@@ -92,11 +100,15 @@ impl T {
         assert!(change.desired == Some(lod::Placeholder));
         in_progress_terrain.insert(id_allocator, physics, block_position);
       },
-      lod::Full => {
+      lod::Full | lod::Coarse(_) => {
         let mut generate_block = || {
-          debug!("{:?} requested from gaia", block_position);
+          debug!("{:?} requested from gaia at {:?}", block_position, new_lod);
           load_block(
-            update_gaia::Message::Load(time::precise_time_ns(), vec!(*block_position), LoadReason::Local(owner))
+            update_gaia::Message::Load(
+              time::precise_time_ns(),
+              vec!(*block_position),
+              LoadReason::Local(owner, new_lod),
+            )
           );
         };
         generate_block();
@@ -104,58 +116,65 @@ impl T {
     };
   }
 
-  pub fn insert_block(
-    block: &LoadedTerrain,
+  /// Load generated collision for a block into physics, if it's still wanted. `generate` makes the
+  /// block's collision at another LOD, for when the LOD wanted changed while `block` was generated.
+  pub fn insert_block<Generate>(
+    block: LoadedTerrain,
+    generate: Generate,
     position: &voxel::bounds::T,
+    lod: lod::T,
     owner: lod::OwnerId,
     physics: &Mutex<Physics>,
     lod_map: &mut lod::Map,
     in_progress_terrain: &mut in_progress_terrain::T,
-    loaded: &mut fnv_map::T<voxel::bounds::T, Vec<entity_id::T>>,
-  ) {
-    let lod = lod::Full;
-    let (_, change) = lod_map.insert(*position, lod, owner);
-    // TODO: This should be an unwrap, but the preconditions of another TODO aren't
-    // satisfied in src/update_gaia.rs.
-    // (i.e. blocks sometimes get here when they're stale).
-    let change = match change {
-      None => return,
-      Some(change) => change,
-    };
-    assert!(change.desired == Some(lod));
-    change.loaded.map(|loaded_lod|
-      match loaded_lod {
-        lod::Placeholder => {
-          in_progress_terrain.remove(physics, position);
-        }
-        lod::Full => {
-          stopwatch::time("terrain_loader.load.unload", || {
-            let ids = loaded.get(position).unwrap();
-            let mut physics = physics.lock().unwrap();
-            for id in ids {
-              physics.remove_terrain(*id);
-            }
-          });
-        },
-      }
-    );
+    loaded: &mut fnv_map::T<voxel::bounds::T, (lod::T, Vec<entity_id::T>)>,
+  ) where Generate: FnOnce(lod::T) -> LoadedTerrain
+  {
+    // TODO: Check that the owner still wants this block at all; see src/update_gaia.rs.
+    lod_map.insert(*position, lod, owner);
+    // The owner now wants `lod`, so this is at least as fine as `lod`.
+    let desired = lod_map.lod(position).unwrap();
+
+    if loaded.get(position).map(|&(lod, _)| lod) == Some(desired) {
+      debug!("Dropping stale generation of {:?} at {:?}", position, lod);
+      return
+    }
+
+    let block =
+      if desired == lod {
+        block
+      } else {
+        // Another owner asked for finer collision while this was being generated.
+        debug!("{:?} was generated at {:?}, but {:?} is wanted now", position, lod, desired);
+        generate(desired)
+      };
+
+    in_progress_terrain.remove(physics, position);
 
     stopwatch::time("terrain_loader.load.physics", || {
       let mut physics = physics.lock().unwrap();
       for &(ref id, ref bounds) in &block.bounds {
         physics.insert_terrain(*id, bounds);
       }
+      let ids = block.bounds.iter().map(|&(id, _)| id).collect();
+      if let Some((_, stale_ids)) = loaded.insert(*position, (desired, ids)) {
+        for id in stale_ids {
+          physics.remove_terrain(id);
+        }
+      }
     });
   }
 
-  pub fn unload(
+  pub fn unload<LoadBlock>(
     &self,
     physics: &Mutex<Physics>,
     block_position: &voxel::bounds::T,
     owner: lod::OwnerId,
-  ) {
-    let (_, mlod_change) =
-      self.lod_map.lock().unwrap().remove(*block_position, owner);
+    load_block: &mut LoadBlock,
+  ) where LoadBlock: FnMut(update_gaia::Message)
+  {
+    let mut lod_map = self.lod_map.lock().unwrap();
+    let (_, mlod_change) = lod_map.remove(*block_position, owner);
 
     let lod_change;
     match mlod_change {
@@ -170,16 +189,33 @@ impl T {
         lod::Placeholder => {
           self.in_progress_terrain.lock().unwrap().remove(physics, block_position);
         }
-        lod::Full => {
+        lod::Full | lod::Coarse(_) => {
+          if let Some(desired) = lod_change.desired {
+            if desired != lod::Placeholder {
+              // The remaining owners want coarser collision. Keep what's loaded until that's
+              // generated, so there's never a hole; `insert_block` swaps it out.
+              let (_, lods) = lod_map.get(block_position, owner).unwrap();
+              let &(desired_owner, _) = lods.iter().find(|&&(_, lod)| lod == desired).unwrap();
+              debug!("{:?} requested from gaia at {:?}", block_position, desired);
+              load_block(
+                update_gaia::Message::Load(
+                  time::precise_time_ns(),
+                  vec!(*block_position),
+                  LoadReason::Local(desired_owner, desired),
+                )
+              );
+              return;
+            }
+          }
           stopwatch::time("terrain_loader.unload", || {
-            match self.loaded.lock().unwrap().get(block_position) {
+            match self.loaded.lock().unwrap().remove(block_position) {
               None => {
                 // Unloaded before the load request completed.
               },
-              Some(ids) => {
+              Some((_, ids)) => {
                 let mut physics = physics.lock().unwrap();
                 for id in ids {
-                  physics.remove_terrain(*id);
+                  physics.remove_terrain(id);
                 }
               },
             }
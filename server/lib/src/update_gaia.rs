@@ -1,6 +1,6 @@
 /// Creator of the earth.
 
-use cgmath::{Aabb3};
+use cgmath::{Aabb3, Point, Point3, Vector};
 use stopwatch;
 
 use common;
@@ -15,7 +15,7 @@ use voxel_data;
 
 #[derive(Debug, Clone, Copy)]
 pub enum LoadReason {
-  Local(lod::OwnerId),
+  Local(lod::OwnerId, lod::T),
   ForClient(protocol::ClientId),
 }
 
@@ -46,6 +46,15 @@ pub fn update_gaia(
           },
         );
 
+        {
+          let mut water = server.water.lock().unwrap();
+          for &(ref bounds, _) in &updates {
+            water.wake(bounds);
+          }
+        }
+
+        server.update_voxel_versions(&updates);
+
         let mut clients = server.clients.lock().unwrap();
         for (_, client) in clients.iter_mut() {
           client.send(
@@ -73,32 +82,19 @@ fn load(
   let mut lod_map = server.terrain_loader.lod_map.lock().unwrap();
   let mut in_progress_terrain = server.terrain_loader.in_progress_terrain.lock().unwrap();
   match load_reason {
-    LoadReason::Local(owner) => {
+    LoadReason::Local(owner, lod) => {
       for voxel_bounds in voxel_bounds.into_iter() {
-        server.terrain_loader.terrain.load(
+        let block = generate_physics(server, &voxel_bounds, lod);
+        terrain_loader::T::insert_block(
+          block,
+          |lod| generate_physics(server, &voxel_bounds, lod),
           &voxel_bounds,
-          |block| {
-            let bounds =
-              match block {
-                &voxel::Volume(voxel::Material::Empty) => Vec::new(),
-                _ => {
-                  let (low, high) = voxel_bounds.corners();
-                  let id = id_allocator::allocate(&server.id_allocator);
-                  vec!((id, Aabb3::new(low, high)))
-                },
-              };
-            // TODO: Check that this block isn't stale, i.e. should still be loaded.
-            // Maybe this should just ping the original thread, same as we ping the client.
-            terrain_loader::T::insert_block(
-              &terrain_loader::LoadedTerrain { bounds: bounds },
-              &voxel_bounds,
-              owner,
-              &server.physics,
-              &mut *lod_map,
-              &mut *in_progress_terrain,
-              &mut *server.terrain_loader.loaded.lock().unwrap(),
-            );
-          }
+          lod,
+          owner,
+          &server.physics,
+          &mut *lod_map,
+          &mut *in_progress_terrain,
+          &mut *server.terrain_loader.loaded.lock().unwrap(),
         );
       }
     },
@@ -125,3 +121,48 @@ fn load(
     },
   }
 }
+
+/// Generate the collision for a block at a physics LOD.
+fn generate_physics(
+  server: &server::T,
+  voxel_bounds: &voxel::bounds::T,
+  lod: lod::T,
+) -> terrain_loader::LoadedTerrain {
+  let lg_size = lod.lg_size().unwrap();
+  // Coarse LODs approximate this block from the bigger voxel containing it.
+  let source =
+    voxel::bounds::new(
+      voxel_bounds.x >> lg_size,
+      voxel_bounds.y >> lg_size,
+      voxel_bounds.z >> lg_size,
+      lg_size,
+    );
+  let mut bounds = Vec::new();
+  server.terrain_loader.terrain.load(
+    &source,
+    |voxel| {
+      if is_solid(voxel, &source, voxel_bounds) {
+        let (low, high) = voxel_bounds.corners();
+        let id = id_allocator::allocate(&server.id_allocator);
+        bounds.push((id, Aabb3::new(low, high)));
+      }
+    }
+  );
+  terrain_loader::LoadedTerrain { bounds: bounds }
+}
+
+/// Whether the block at `position` should be solid, going by the voxel at `source`, which contains it.
+fn is_solid(voxel: &voxel::T, source: &voxel::bounds::T, position: &voxel::bounds::T) -> bool {
+  match *voxel {
+    voxel::Volume(material) => material.is_solid(),
+    voxel::Surface(_) if source == position => true,
+    voxel::Surface(ref surface) => {
+      // Solid if the block's center is on the inside of the surface's plane.
+      let (low, high) = position.corners();
+      let center = low.add_v(&high.to_vec()).mul_s(0.5);
+      let vertex: Point3<f32> = surface.surface_vertex.to_world_vertex(source);
+      let normal = surface.normal.to_float_normal();
+      center.sub_p(&vertex).dot(&normal) <= 0.0
+    },
+  }
+}
@@ -3,7 +3,8 @@ use std::ops::Neg;
 use stopwatch;
 
 use common::protocol;
-use common::surroundings_loader::LoadType;
+use common::surroundings_loader;
+use common::surroundings_loader::{LoadType, SurroundingsLoader};
 use common::voxel;
 
 use lod;
@@ -50,6 +51,17 @@ pub fn update_world<RequestBlock>(
     });
 
     stopwatch::time("update_world.mobs", || {
+      let player_positions: Vec<Point3<i32>> =
+        server.players.lock().unwrap().values()
+        .map(|player| {
+          Point3::new(
+            player.position.x as i32,
+            player.position.y as i32,
+            player.position.z as i32,
+          )
+        })
+        .collect();
+
       for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
         let position =
           Point3::new(
@@ -58,10 +70,23 @@ pub fn update_world<RequestBlock>(
             mob.position.z as i32,
           );
 
+        // Mobs far from any player don't need fine collision.
+        let player_distance =
+          player_positions.iter()
+          .map(|p| surroundings_loader::distance_between(p, &position))
+          .min();
+        let physics_lod = lod::update_for_player_distance(mob.physics_lod, player_distance);
+        if physics_lod != mob.physics_lod {
+          // Start over, so that everything in range gets requested at the new LOD.
+          mob.physics_lod = physics_lod;
+          mob.surroundings_loader = SurroundingsLoader::new(mob::SURROUNDINGS_RADIUS, Vec::new());
+        }
+
         let owner_id = mob.owner_id;
         for (position, load_type) in mob.surroundings_loader.updates(&position) {
-          load_placeholders(
+          load_at_lod(
             owner_id,
+            physics_lod,
             server,
             request_block,
             &voxel::bounds::new(position.x, position.y, position.z, 0),
@@ -95,6 +120,29 @@ pub fn update_world<RequestBlock>(
         client.send(protocol::ServerToClient::UpdateSun(fraction));
       }
     });
+
+    stopwatch::time("update_world.water", || {
+      let updates = {
+        let mut voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+        server.water.lock().unwrap().update(&mut voxels)
+      };
+
+      if updates.is_empty() {
+        return
+      }
+
+      server.update_voxel_versions(&updates);
+
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+        client.send(
+          protocol::ServerToClient::Voxels(
+            None,
+            updates.clone(),
+            protocol::VoxelReason::Updated,
+          )
+        );
+      }
+    });
   });
 }
 
@@ -123,8 +171,9 @@ fn translate_mob(
   }
 }
 
-pub fn load_placeholders<RequestBlock>(
+pub fn load_at_lod<RequestBlock>(
   owner: lod::OwnerId,
+  lod: lod::T,
   server: &server::T,
   request_block: &mut RequestBlock,
   pos: &voxel::bounds::T,
@@ -138,7 +187,7 @@ pub fn load_placeholders<RequestBlock>(
         &server.id_allocator,
         &server.physics,
         &pos,
-        lod::Placeholder,
+        lod,
         owner,
         request_block,
       );
@@ -148,6 +197,7 @@ pub fn load_placeholders<RequestBlock>(
         &server.physics,
         &pos,
         owner,
+        request_block,
       );
     },
   }
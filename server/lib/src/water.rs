@@ -0,0 +1,211 @@
+//! Cellular simulation of water flowing into adjacent empty voxels.
+//!
+//! Water is only ever moved, never created or destroyed: every flow empties the voxel the water
+//! came from. A water voxel falls if it can. Otherwise it moves sideways into an empty neighbour
+//! when that's somewhere it can fall from next, or when there's water above pushing it. This means
+//! every sideways move is soon followed by a fall, so water always settles. It ends up level to
+//! within a voxel.
+//!
+//! Only edits wake water up (see `wake`). Generated water starts out static: generation fills every
+//! non-solid voxel below `terrain::biome::SEA_LEVEL` with water and none above it, so it's already
+//! at rest, and stays that way until something is dug or built next to it.
+
+use time;
+
+use common::fnv_set;
+use common::interval_timer::IntervalTimer;
+use common::voxel;
+
+/// Upper bound on the number of voxels of water that can move in one tick.
+const MAX_FLOWS_PER_TICK: usize = 1 << 10;
+
+pub struct T {
+  /// Voxels that might be water with somewhere to flow.
+  active: fnv_set::T<voxel::bounds::T>,
+  timer: IntervalTimer,
+}
+
+pub fn new(tick_ns: u64) -> T {
+  T {
+    active: fnv_set::new(),
+    timer: IntervalTimer::new(tick_ns, time::precise_time_ns()),
+  }
+}
+
+fn offset(bounds: &voxel::bounds::T, dx: i32, dy: i32, dz: i32) -> voxel::bounds::T {
+  voxel::bounds::new(bounds.x + dx, bounds.y + dy, bounds.z + dz, bounds.lg_size)
+}
+
+fn is_water(voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> bool {
+  match voxels.get(bounds) {
+    Some(&voxel::Volume(voxel::Material::Water)) => true,
+    _ => false,
+  }
+}
+
+// Unloaded voxels aren't empty; we never flow into terrain that hasn't been generated yet.
+fn is_empty(voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> bool {
+  match voxels.get(bounds) {
+    Some(&voxel::Volume(voxel::Material::Empty)) => true,
+    _ => false,
+  }
+}
+
+/// Where the water in `bounds` would move to, if anywhere.
+fn flow_target(voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> Option<voxel::bounds::T> {
+  let below = offset(bounds, 0, -1, 0);
+  if is_empty(voxels, &below) {
+    return Some(below)
+  }
+
+  let pushed = is_water(voxels, &offset(bounds, 0, 1, 0));
+  let sides = [
+    offset(bounds, -1, 0, 0),
+    offset(bounds, 1, 0, 0),
+    offset(bounds, 0, 0, -1),
+    offset(bounds, 0, 0, 1),
+  ];
+  sides.iter()
+    .find(|side| is_empty(voxels, side) && (pushed || is_empty(voxels, &offset(side, 0, -1, 0))))
+    .map(|side| *side)
+}
+
+impl T {
+  /// Mark a changed voxel and its neighbors as possibly able to flow.
+  pub fn wake(&mut self, bounds: &voxel::bounds::T) {
+    // The simulation only runs on the finest voxels.
+    if bounds.lg_size != 0 {
+      return
+    }
+
+    self.active.insert(*bounds);
+    self.active.insert(offset(bounds, 0, 1, 0));
+    self.active.insert(offset(bounds, -1, 0, 0));
+    self.active.insert(offset(bounds, 1, 0, 0));
+    self.active.insert(offset(bounds, 0, 0, -1));
+    self.active.insert(offset(bounds, 0, 0, 1));
+  }
+
+  /// Run the simulation if a tick has passed. Returns the voxels that changed.
+  pub fn update(&mut self, voxels: &mut voxel::tree::T) -> Vec<(voxel::bounds::T, voxel::T)> {
+    if self.timer.update(time::precise_time_ns()) == 0 {
+      return Vec::new()
+    }
+    self.tick(voxels)
+  }
+
+  fn tick(&mut self, voxels: &mut voxel::tree::T) -> Vec<(voxel::bounds::T, voxel::T)> {
+    let mut updates = Vec::new();
+    let mut flows = 0;
+    // Water that's already moved this tick.
+    let mut moved = fnv_set::new();
+
+    // Bottom up, so a column of water falls together instead of one voxel per tick.
+    let mut active: Vec<voxel::bounds::T> = self.active.drain().collect();
+    active.sort_by(|a, b| (a.y, a.x, a.z).cmp(&(b.y, b.x, b.z)));
+    for bounds in active {
+      if flows >= MAX_FLOWS_PER_TICK {
+        // Try again next tick.
+        self.active.insert(bounds);
+        continue
+      }
+
+      if moved.contains(&bounds) || !is_water(voxels, &bounds) {
+        continue
+      }
+
+      let target =
+        match flow_target(voxels, &bounds) {
+          None => continue,
+          Some(target) => target,
+        };
+
+      let water = voxel::Volume(voxel::Material::Water);
+      let empty = voxel::Volume(voxel::Material::Empty);
+      voxels.get_mut_or_create(&target).force_branches().data = Some(water);
+      voxels.get_mut_or_create(&bounds).force_branches().data = Some(empty);
+      updates.push((bounds, empty));
+      updates.push((target, water));
+      moved.insert(target);
+      flows += 1;
+
+      // The water may keep going, and whatever was above or beside it may follow.
+      self.wake(&bounds);
+      self.wake(&target);
+    }
+
+    updates
+  }
+}
+
+#[cfg(test)]
+fn set(voxels: &mut voxel::tree::T, x: i32, y: i32, z: i32, material: voxel::Material) {
+  voxels.get_mut_or_create(&voxel::bounds::new(x, y, z, 0)).force_branches().data = Some(voxel::Volume(material));
+}
+
+#[cfg(test)]
+fn water_in(voxels: &voxel::tree::T) -> Vec<(i32, i32, i32)> {
+  let mut water = Vec::new();
+  for x in -2 .. 3 {
+  for y in 0 .. 4 {
+  for z in -2 .. 3 {
+    if is_water(voxels, &voxel::bounds::new(x, y, z, 0)) {
+      water.push((x, y, z));
+    }
+  }}}
+  water
+}
+
+/// A 5x4x5 box of air with a stone floor at y = 0.
+#[cfg(test)]
+fn basin() -> voxel::tree::T {
+  let mut voxels = voxel::tree::new();
+  for x in -2 .. 3 {
+  for y in 0 .. 4 {
+  for z in -2 .. 3 {
+    set(&mut voxels, x, y, z, if y == 0 { voxel::Material::Stone } else { voxel::Material::Empty });
+  }}}
+  voxels
+}
+
+#[test]
+fn water_falls_without_being_copied() {
+  let mut voxels = basin();
+  set(&mut voxels, 0, 3, 0, voxel::Material::Water);
+  let mut water = new(1);
+  water.wake(&voxel::bounds::new(0, 3, 0, 0));
+
+  let updates = water.tick(&mut voxels);
+  assert_eq!(updates.len(), 2);
+  assert_eq!(water_in(&voxels), vec!((0, 2, 0)));
+}
+
+#[test]
+fn resting_water_stays_put() {
+  let mut voxels = basin();
+  set(&mut voxels, 0, 1, 0, voxel::Material::Water);
+  let mut water = new(1);
+  water.wake(&voxel::bounds::new(0, 1, 0, 0));
+
+  assert!(water.tick(&mut voxels).is_empty());
+  assert_eq!(water_in(&voxels), vec!((0, 1, 0)));
+}
+
+#[test]
+fn columns_spread_out_and_keep_their_volume() {
+  let mut voxels = basin();
+  let mut water = new(1);
+  for y in 1 .. 4 {
+    set(&mut voxels, 0, y, 0, voxel::Material::Water);
+    water.wake(&voxel::bounds::new(0, y, 0, 0));
+  }
+
+  // The water at the bottom is pushed out from under the rest, which falls.
+  for _ in 0 .. 8 {
+    water.tick(&mut voxels);
+  }
+  let settled = water_in(&voxels);
+  assert_eq!(settled.len(), 3);
+  assert!(settled.iter().all(|&(_, y, _)| y == 1));
+  assert!(water.tick(&mut voxels).is_empty());
+}
@@ -5,6 +5,12 @@ use noise::{Seed, perlin3};
 
 use common::voxel;
 
+use biome;
+use tree;
+
+/// Trees that grow in this biome, with relative weights. Nothing grows in caves.
+pub static TREES: &'static [(tree::Species, u32)] = &[];
+
 #[allow(missing_docs)]
 pub struct T {
   pub seed: Seed,
@@ -61,7 +67,7 @@ impl voxel::mosaic::T<voxel::Material> for T {
       if voxel::field::T::density(self, p) >= 0.0 {
         voxel::Material::Stone
       } else {
-        voxel::Material::Empty
+        biome::fill(p)
       }
     )
   }
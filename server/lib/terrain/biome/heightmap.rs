@@ -0,0 +1,323 @@
+//! Terrain authored as a heightmap image, optionally with a painted map of surface materials.
+//! Noise is added on top so the heightmap's pixels don't show.
+
+use cgmath::{Point3, Vector3, EuclideanVector};
+use image;
+use noise::{Seed, Brownian3, perlin3};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+
+use common::tree_species;
+use common::voxel;
+
+use biome;
+use layers;
+use tree;
+
+/// Trees that grow in this biome, with relative weights.
+pub static TREES: &'static [(tree::Species, u32)] = &[
+  (tree_species::T::Oak, 1),
+  (tree_species::T::Birch, 2),
+  (tree_species::T::Conifer, 2),
+  (tree_species::T::Bush, 3),
+];
+
+/// The colors understood in material maps, and the surface materials they paint.
+/// Other colors are treated as the nearest color here.
+pub static PALETTE: &'static [([u8; 3], Option<voxel::Material>)] = &[
+  // Unpainted: pick the material from the slope and altitude, like other biomes.
+  ([0, 0, 0], None),
+  ([0, 255, 0], Some(voxel::Material::Terrain)),
+  ([128, 64, 0], Some(voxel::Material::Dirt)),
+  ([128, 128, 128], Some(voxel::Material::Stone)),
+  ([255, 255, 0], Some(voxel::Material::Sand)),
+  ([255, 255, 255], Some(voxel::Material::Snow)),
+  ([255, 0, 255], Some(voxel::Material::Marble)),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(missing_docs)]
+pub enum Format {
+  /// Grayscale PNG. Color images are converted to grayscale.
+  Png,
+  /// Headerless little-endian 16-bit samples, row by row.
+  Raw16 { width: u32, height: u32 },
+}
+
+/// What the heightmap looks like beyond its edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edges {
+  /// Repeat the heightmap.
+  Tile,
+  /// Extend the border pixels forever.
+  Clamp,
+}
+
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub struct Config {
+  pub heightmap: PathBuf,
+  pub format: Format,
+  /// An RGB PNG the same size as the heightmap, painted with colors from `PALETTE`.
+  pub material_map: Option<PathBuf>,
+  pub edges: Edges,
+  /// World units per heightmap pixel.
+  pub horizontal_scale: f32,
+  /// The height of the darkest possible sample.
+  pub min_height: f32,
+  /// The height of the brightest possible sample.
+  pub max_height: f32,
+  /// The amplitude of the noise added to the heightmap.
+  pub detail: f32,
+}
+
+impl Config {
+  /// The default settings for a PNG heightmap at a given path.
+  pub fn new(heightmap: PathBuf) -> Config {
+    Config {
+      heightmap: heightmap,
+      format: Format::Png,
+      material_map: None,
+      edges: Edges::Tile,
+      horizontal_scale: 1.0,
+      min_height: -32.0,
+      max_height: 64.0,
+      detail: 2.0,
+    }
+  }
+}
+
+/// A 2D grid of samples, indexed by world x and z.
+struct Grid<X> {
+  width: u32,
+  height: u32,
+  samples: Vec<X>,
+}
+
+impl<X> Grid<X> {
+  fn get(&self, edges: Edges, x: i64, z: i64) -> &X {
+    let wrap = |i: i64, n: u32| {
+      let n = n as i64;
+      match edges {
+        Edges::Tile => ((i % n) + n) % n,
+        Edges::Clamp => if i < 0 { 0 } else if i >= n { n - 1 } else { i },
+      }
+    };
+    let x = wrap(x, self.width);
+    let z = wrap(z, self.height);
+    &self.samples[(z * self.width as i64 + x) as usize]
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// World-space heights.
+  heights: Grid<f32>,
+  materials: Option<Grid<Option<voxel::Material>>>,
+  edges: Edges,
+  horizontal_scale: f32,
+  detail_amplitude: f32,
+  detail: Brownian3<f64, fn (&Seed, &[f64; 3]) -> f64>,
+  seed: Seed,
+}
+
+fn invalid_data(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn open_image(path: &PathBuf) -> io::Result<image::DynamicImage> {
+  image::open(path)
+    .map_err(|err| invalid_data(format!("Couldn't read {:?}: {}", path, err)))
+}
+
+/// Samples from a heightmap file, scaled to [0, 1].
+fn load_heightmap(config: &Config) -> io::Result<Grid<f32>> {
+  match config.format {
+    Format::Png => {
+      let image = try!(open_image(&config.heightmap)).to_luma();
+      let (width, height) = image.dimensions();
+      let mut samples = Vec::with_capacity((width * height) as usize);
+      for z in 0 .. height {
+      for x in 0 .. width {
+        samples.push(image.get_pixel(x, z).data[0] as f32 / 255.0);
+      }}
+      Ok(Grid { width: width, height: height, samples: samples })
+    },
+    Format::Raw16 { width, height } => {
+      let expected =
+        try!(
+          (width as usize).checked_mul(height as usize)
+          .and_then(|samples| samples.checked_mul(2))
+          .ok_or_else(|| invalid_data(format!("A {}x{} 16-bit heightmap is too big", width, height)))
+        );
+      let mut bytes = Vec::new();
+      try!(try!(File::open(&config.heightmap)).read_to_end(&mut bytes));
+      if bytes.len() != expected {
+        return Err(invalid_data(format!(
+          "{:?} is {} bytes, but a {}x{} 16-bit heightmap should be {}",
+          config.heightmap, bytes.len(), width, height, expected,
+        )))
+      }
+      let samples =
+        bytes.chunks(2)
+        .map(|b| (b[0] as u16 | (b[1] as u16) << 8) as f32 / 65535.0)
+        .collect();
+      Ok(Grid { width: width, height: height, samples: samples })
+    },
+  }
+}
+
+fn nearest_palette_material(color: &[u8; 3]) -> Option<voxel::Material> {
+  let distance = |other: &[u8; 3]| {
+    (0 .. 3)
+    .map(|i| {
+      let d = color[i] as i32 - other[i] as i32;
+      d * d
+    })
+    .fold(0, |x, y| x + y)
+  };
+  let mut nearest = &PALETTE[0];
+  for entry in PALETTE {
+    if distance(&entry.0) < distance(&nearest.0) {
+      nearest = entry;
+    }
+  }
+  nearest.1
+}
+
+fn load_material_map(path: &PathBuf, width: u32, height: u32) -> io::Result<Grid<Option<voxel::Material>>> {
+  let image = try!(open_image(path)).to_rgb();
+  if image.dimensions() != (width, height) {
+    return Err(invalid_data(format!(
+      "Material map {:?} is {:?}, but the heightmap is {:?}",
+      path, image.dimensions(), (width, height),
+    )))
+  }
+  let mut samples = Vec::with_capacity((width * height) as usize);
+  for z in 0 .. height {
+  for x in 0 .. width {
+    samples.push(nearest_palette_material(&image.get_pixel(x, z).data));
+  }}
+  Ok(Grid { width: width, height: height, samples: samples })
+}
+
+#[allow(missing_docs)]
+pub fn load(seed: Seed, config: &Config) -> io::Result<T> {
+  let mut heights = try!(load_heightmap(config));
+  if heights.width == 0 || heights.height == 0 {
+    return Err(invalid_data(format!("{:?} is empty", config.heightmap)))
+  }
+  for h in &mut heights.samples {
+    *h = config.min_height + *h * (config.max_height - config.min_height);
+  }
+
+  let materials =
+    match config.material_map {
+      None => None,
+      Some(ref path) => Some(try!(load_material_map(path, heights.width, heights.height))),
+    };
+
+  let perlin3: fn(&Seed, &[f64; 3]) -> f64 = perlin3;
+  Ok(T {
+    heights: heights,
+    materials: materials,
+    edges: config.edges,
+    horizontal_scale: config.horizontal_scale,
+    detail_amplitude: config.detail,
+    detail:
+      Brownian3::new(perlin3, 3)
+      .frequency(1.0 / 8.0)
+      .persistence(2.0)
+      .lacunarity(1.0 / 2.0)
+    ,
+    seed: seed,
+  })
+}
+
+impl T {
+  /// Bilinearly-interpolated height of the heightmap.
+  fn height(&self, x: f32, z: f32) -> f32 {
+    let x = x / self.horizontal_scale;
+    let z = z / self.horizontal_scale;
+    let x0 = x.floor();
+    let z0 = z.floor();
+    let tx = x - x0;
+    let tz = z - z0;
+    let x0 = x0 as i64;
+    let z0 = z0 as i64;
+    let h = |dx, dz| *self.heights.get(self.edges, x0 + dx, z0 + dz);
+    let low = h(0, 0) * (1.0 - tx) + h(1, 0) * tx;
+    let high = h(0, 1) * (1.0 - tx) + h(1, 1) * tx;
+    low * (1.0 - tz) + high * tz
+  }
+
+  /// The painted material nearest a point, if any.
+  fn painted_material(&self, x: f32, z: f32) -> Option<voxel::Material> {
+    self.materials.as_ref().and_then(|materials| {
+      let x = (x / self.horizontal_scale).round() as i64;
+      let z = (z / self.horizontal_scale).round() as i64;
+      *materials.get(self.edges, x, z)
+    })
+  }
+}
+
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let height = self.height(p.x, p.z);
+    let detail = self.detail.apply(&self.seed, &[p.x as f64, p.y as f64, p.z as f64]);
+    height - p.y + detail as f32 * self.detail_amplitude
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    // Use density differential in each dimension as an approximation of the normal.
+
+    let delta = 0.01;
+
+    macro_rules! differential(($d:ident) => {{
+      let high: f32 = {
+        let mut p = *p;
+        p.$d += delta;
+        voxel::field::T::density(self, &p)
+      };
+      let low: f32 = {
+        let mut p = *p;
+        p.$d -= delta;
+        voxel::field::T::density(self, &p)
+      };
+      high - low
+    }});
+
+    let v = Vector3::new(differential!(x), differential!(y), differential!(z));
+    // Negate because we're leaving the volume when density is decreasing.
+    let v = -v;
+    v.normalize()
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let d = voxel::field::T::density(self, p);
+    if d < 0.0 {
+      return Some(biome::fill(p))
+    }
+
+    match self.painted_material(p.x, p.z) {
+      Some(material) if d <= layers::SOIL_DEPTH => Some(material),
+      _ => {
+        let normal = voxel::field::T::normal(self, p);
+        Some(layers::soil(d, &normal, p.y))
+      },
+    }
+  }
+}
+
+#[test]
+fn edges_tile_or_clamp() {
+  let grid = Grid { width: 2, height: 1, samples: vec!(1, 2) };
+  assert_eq!(*grid.get(Edges::Tile, -1, 0), 2);
+  assert_eq!(*grid.get(Edges::Tile, 2, 3), 1);
+  assert_eq!(*grid.get(Edges::Clamp, -5, 0), 1);
+  assert_eq!(*grid.get(Edges::Clamp, 5, -5), 2);
+}
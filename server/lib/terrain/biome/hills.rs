@@ -3,8 +3,20 @@
 use cgmath::{Point3, Vector3, EuclideanVector};
 use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
 
+use common::tree_species;
 use common::voxel;
 
+use biome;
+use layers;
+use tree;
+
+/// Trees that grow in this biome, with relative weights.
+pub static TREES: &'static [(tree::Species, u32)] = &[
+  (tree_species::T::Branching, 3),
+  (tree_species::T::Birch, 2),
+  (tree_species::T::Bush, 4),
+];
+
 #[allow(missing_docs)]
 pub struct T {
   height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
@@ -73,11 +85,13 @@ impl voxel::field::T for T {
 
 impl voxel::mosaic::T<voxel::Material> for T {
   fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let d = voxel::field::T::density(self, p);
     Some(
-      if voxel::field::T::density(self, p) >= 0.0 {
-        voxel::Material::Terrain
+      if d < 0.0 {
+        biome::fill(p)
       } else {
-        voxel::Material::Empty
+        let normal = voxel::field::T::normal(self, p);
+        layers::soil(d, &normal, p.y)
       }
     )
   }
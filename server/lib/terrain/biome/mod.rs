@@ -1,6 +1,26 @@
 //! Voxel mosaic implementations for different biomes.
+//! Each biome lists the tree species that grow in it, with relative weights, as `TREES`; the forest
+//! planted over a biome picks from that list. An empty list, like the caves', means no trees.
+
+use cgmath::Point3;
+
+use common::voxel;
 
 pub mod caves;
 pub mod demo;
+pub mod heightmap;
 pub mod hills;
 pub mod mountains;
+
+/// Non-solid space below this height is filled with water. Since all of it is, generated water
+/// is already at rest, and the server's water simulation doesn't need to look at it.
+pub const SEA_LEVEL: f32 = -8.0;
+
+/// The material of non-solid space at a point.
+pub fn fill(p: &Point3<f32>) -> voxel::Material {
+  if p.y < SEA_LEVEL {
+    voxel::Material::Water
+  } else {
+    voxel::Material::Empty
+  }
+}
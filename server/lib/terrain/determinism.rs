@@ -0,0 +1,237 @@
+//! Clients and the server both rely on `T::load` giving the same voxels for the same seed, no
+//! matter when, in what order, or from which threads blocks get loaded.
+//!
+//! The golden hashes live in `golden_hashes.txt`. After an intentional change to generation,
+//! rerun the tests with `PLAYFORM_BLESS=1` to record new ones. Missing entries are failures
+//! unless blessing, so a fresh checkout can't silently record whatever it generates.
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem;
+use std::sync::Arc;
+use std::thread;
+
+use common::voxel;
+
+use super::{Source, T};
+
+const GOLDEN_PATH: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden_hashes.txt");
+const GOLDEN_HEADER: &'static str = "\
+# Hashes of terrain::T::load output for fixed seeds and regions; see determinism.rs.
+# Regenerate with PLAYFORM_BLESS=1 after intentional changes to generation.
+";
+
+/// FNV-1a, which unlike std's hashers is guaranteed to stay the same.
+struct Fnv(u64);
+
+impl Fnv {
+  fn new() -> Fnv {
+    Fnv(0xcbf29ce484222325)
+  }
+
+  fn write_u32(&mut self, x: u32) {
+    for i in 0 .. 4 {
+      self.0 ^= ((x >> (8 * i)) & 0xff) as u64;
+      self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+  }
+
+  fn write_i32(&mut self, x: i32) {
+    self.write_u32(x as u32)
+  }
+
+  fn write_f32(&mut self, x: f32) {
+    self.write_u32(unsafe { mem::transmute(x) })
+  }
+}
+
+fn hash_voxel(bounds: &voxel::bounds::T, voxel: &voxel::T) -> u64 {
+  let mut h = Fnv::new();
+  h.write_i32(bounds.x);
+  h.write_i32(bounds.y);
+  h.write_i32(bounds.z);
+  h.write_i32(bounds.lg_size as i32);
+  match *voxel {
+    voxel::Volume(material) => {
+      h.write_u32(0);
+      h.write_u32(material as u32);
+    },
+    voxel::Surface(ref surface) => {
+      h.write_u32(1);
+      h.write_u32(surface.corner as u32);
+      let vertex = surface.surface_vertex.to_world_vertex(bounds);
+      h.write_f32(vertex.x);
+      h.write_f32(vertex.y);
+      h.write_f32(vertex.z);
+      let normal = surface.normal.to_float_normal();
+      h.write_f32(normal.x);
+      h.write_f32(normal.y);
+      h.write_f32(normal.z);
+    },
+  }
+  h.0
+}
+
+/// A box of voxels of a single size, in voxel coordinates.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+  low: (i32, i32, i32),
+  high: (i32, i32, i32),
+  lg_size: i16,
+}
+
+impl Region {
+  fn voxels(&self) -> Vec<voxel::bounds::T> {
+    let mut voxels = Vec::new();
+    for x in self.low.0 .. self.high.0 {
+    for y in self.low.1 .. self.high.1 {
+    for z in self.low.2 .. self.high.2 {
+      voxels.push(voxel::bounds::new(x, y, z, self.lg_size));
+    }}}
+    voxels
+  }
+}
+
+/// Regions straddling the surface of the demo terrain, at a couple of resolutions.
+const REGIONS: [Region; 2] = [
+  Region { low: (-8, -16, -8), high: (8, 16, 8), lg_size: 0 },
+  Region { low: (-8, -8, -8), high: (8, 8, 8), lg_size: 2 },
+];
+
+fn new_terrain(seed: u32) -> T {
+  T::new(seed, &Source::Demo).unwrap()
+}
+
+/// Load voxels in the order given, returning each voxel's hash, sorted by position.
+fn load_hashes(terrain: &T, voxels: &[voxel::bounds::T]) -> BTreeMap<(i32, i32, i32, i16), u64> {
+  let mut hashes = BTreeMap::new();
+  for bounds in voxels {
+    terrain.load(bounds, |voxel| {
+      hashes.insert((bounds.x, bounds.y, bounds.z, bounds.lg_size), hash_voxel(bounds, voxel));
+    });
+  }
+  hashes
+}
+
+/// Combine the hashes of a whole region into one.
+fn region_hash(hashes: &BTreeMap<(i32, i32, i32, i16), u64>) -> u64 {
+  let mut h = Fnv::new();
+  for hash in hashes.values() {
+    h.write_u32(*hash as u32);
+    h.write_u32((*hash >> 32) as u32);
+  }
+  h.0
+}
+
+fn read_golden() -> BTreeMap<String, String> {
+  let mut contents = String::new();
+  if let Ok(mut file) = File::open(GOLDEN_PATH) {
+    file.read_to_string(&mut contents).unwrap();
+  }
+  contents.lines()
+    .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+    .map(|line| {
+      let mut words = line.split_whitespace();
+      let name = words.next().unwrap().to_owned();
+      let hash = words.next().unwrap_or_else(|| panic!("Malformed golden hash line {:?}", line)).to_owned();
+      (name, hash)
+    })
+    .collect()
+}
+
+fn write_golden(golden: &BTreeMap<String, String>) {
+  let mut file = File::create(GOLDEN_PATH).unwrap();
+  file.write_all(GOLDEN_HEADER.as_bytes()).unwrap();
+  for (name, hash) in golden {
+    writeln!(file, "{} {}", name, hash).unwrap();
+  }
+}
+
+#[test]
+fn golden_hashes() {
+  let bless = env::var_os("PLAYFORM_BLESS").is_some();
+  let mut golden = read_golden();
+  let mut changed = false;
+  let mut missing = Vec::new();
+
+  for &seed in &[0, 1, 0xdeadbeef] {
+    let terrain = new_terrain(seed);
+    for region in &REGIONS {
+      let name = format!("seed{}-lg{}", seed, region.lg_size);
+      let hash = format!("{:016x}", region_hash(&load_hashes(&terrain, &region.voxels())));
+      if bless {
+        println!("Recording golden hash {} {}", name, hash);
+        golden.insert(name, hash);
+        changed = true;
+      } else if !golden.contains_key(&name) {
+        missing.push(format!("{} {}", name, hash));
+      } else {
+        assert!(
+          golden[&name] == hash,
+          "Terrain for {} changed: expected hash {}, got {}", name, golden[&name], hash,
+        );
+      }
+    }
+  }
+
+  if changed {
+    write_golden(&golden);
+  }
+  assert!(
+    missing.is_empty(),
+    "No golden hashes recorded for:\n{}\nRerun with PLAYFORM_BLESS=1 to record them.", missing.join("\n"),
+  );
+}
+
+#[test]
+fn load_order_does_not_matter() {
+  let seed = 7;
+  let mut voxels: Vec<_> = REGIONS.iter().flat_map(|r| r.voxels().into_iter()).collect();
+  let expected = load_hashes(&new_terrain(seed), &voxels);
+
+  voxels.reverse();
+  assert!(load_hashes(&new_terrain(seed), &voxels) == expected);
+
+  let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+  rng.shuffle(&mut voxels);
+  assert!(load_hashes(&new_terrain(seed), &voxels) == expected);
+}
+
+#[test]
+fn concurrent_loads_match_sequential_loads() {
+  const THREADS: usize = 4;
+
+  let seed = 7;
+  let voxels: Vec<_> = REGIONS.iter().flat_map(|r| r.voxels().into_iter()).collect();
+  let expected = load_hashes(&new_terrain(seed), &voxels);
+
+  let terrain = Arc::new(new_terrain(seed));
+  let voxels = Arc::new(voxels);
+  let threads: Vec<_> =
+    (0 .. THREADS)
+    .map(|i| {
+      let terrain = terrain.clone();
+      let voxels = voxels.clone();
+      thread::spawn(move || {
+        // Each thread takes every THREADS'th voxel, alternating directions, so that threads
+        // are racing on neighboring voxels and shared cache entries.
+        let mine: Vec<_> =
+          voxels.iter()
+          .enumerate()
+          .filter(|&(j, _)| j % THREADS == i)
+          .map(|(_, bounds)| *bounds)
+          .collect();
+        let mine: Vec<_> = if i % 2 == 0 { mine } else { mine.into_iter().rev().collect() };
+        load_hashes(&terrain, &mine);
+      })
+    })
+    .collect();
+  for thread in threads {
+    thread.join().unwrap();
+  }
+
+  assert!(load_hashes(&terrain, &voxels) == expected);
+}
@@ -0,0 +1,336 @@
+//! Deterministically scatter trees over grassy parts of a base mosaic.
+//!
+//! The world is divided into square columns, each wide enough for the biggest tree that can grow
+//! in it, and each column gets at most one tree, entirely inside it. Everything about a column's
+//! tree is derived from the seed and the column's coordinates, so a tree that straddles block
+//! boundaries comes out the same no matter which of its blocks is generated first, and trees
+//! can be forgotten and regenerated freely.
+
+use cgmath::{Point, Point3, Vector3};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::cmp;
+
+use common::fnv_map;
+use common::voxel;
+
+use tree;
+
+/// Narrowest the columns that each hold at most one tree can be.
+const MIN_CELL_WIDTH: i32 = 16;
+/// The most columns' trees to keep generated at once.
+const MAX_CACHED_COLUMNS: usize = 1 << 10;
+/// Chance that a given column has a tree in it.
+const TREE_PROBABILITY: f32 = 0.25;
+
+/// Range of heights to search for the ground in.
+const MIN_GROUND_HEIGHT: f32 = -64.0;
+const MAX_GROUND_HEIGHT: f32 = 64.0;
+
+struct Tree {
+  /// The bottom-center of the trunk.
+  bottom: Point3<f32>,
+  mosaic: tree::T,
+}
+
+struct Column {
+  tree: Option<Tree>,
+  /// When this column was last looked at, for evicting the least recently used.
+  last_used: u64,
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  seed: u32,
+  /// The species that can grow here, with relative weights.
+  species: &'static [(tree::Species, u32)],
+  /// Width of the columns that each hold at most one tree.
+  cell_width: i32,
+  base: Box<voxel::mosaic::T<voxel::Material> + Send>,
+  /// Lazily-generated trees, keyed by column.
+  columns: fnv_map::T<(i32, i32), Column>,
+  /// Counts column lookups, to timestamp them.
+  lookups: u64,
+}
+
+#[allow(missing_docs)]
+pub fn new(
+  seed: u32,
+  species: &'static [(tree::Species, u32)],
+  base: Box<voxel::mosaic::T<voxel::Material> + Send>,
+) -> T {
+  let max_tree_radius =
+    species.iter()
+    .fold(0.0, |r, &(species, _)| f32::max(r, tree::max_radius(species)));
+  T {
+    seed: seed,
+    species: species,
+    cell_width: cmp::max(MIN_CELL_WIDTH, (2.0 * max_tree_radius).ceil() as i32),
+    base: base,
+    columns: fnv_map::new(),
+    lookups: 0,
+  }
+}
+
+// Integer finalizer from MurmurHash3, to decorrelate the seeds of neighboring columns.
+fn mix(x: u32) -> u32 {
+  let x = (x ^ (x >> 16)).wrapping_mul(0x85ebca6b);
+  let x = (x ^ (x >> 13)).wrapping_mul(0xc2b2ae35);
+  x ^ (x >> 16)
+}
+
+fn column_rng(seed: u32, column: (i32, i32)) -> XorShiftRng {
+  let a = mix(seed);
+  let b = mix(a ^ column.0 as u32);
+  let c = mix(b ^ column.1 as u32);
+  // XorShift can't be seeded with all zeros.
+  let d = mix(c) | 1;
+  SeedableRng::from_seed([a, b, c, d])
+}
+
+/// Pick a species at random, in proportion to the weights.
+fn choose_species<R: Rng>(rng: &mut R, species: &[(tree::Species, u32)]) -> tree::Species {
+  let total_weight = species.iter().fold(0, |total, &(_, w)| total + w);
+  let mut choice = rng.gen_range(0, total_weight);
+  for &(s, w) in species {
+    if choice < w {
+      return s
+    }
+    choice -= w;
+  }
+  unreachable!()
+}
+
+impl T {
+  /// Find the top of the solid ground in a column, if it's within the search range.
+  fn ground(&mut self, x: f32, z: f32) -> Option<Point3<f32>> {
+    let mut y = MAX_GROUND_HEIGHT;
+    while y >= MIN_GROUND_HEIGHT {
+      let p = Point3::new(x, y, z);
+      if voxel::field::T::density(&mut self.base, &p) >= 0.0 {
+        return Some(p)
+      }
+      y -= 1.0;
+    }
+    None
+  }
+
+  fn column_of(&self, x: f32) -> i32 {
+    (x / self.cell_width as f32).floor() as i32
+  }
+
+  fn generate_tree(&mut self, column: (i32, i32)) -> Option<Tree> {
+    let mut rng = column_rng(self.seed, column);
+
+    if self.species.iter().all(|&(_, w)| w == 0) || rng.next_f32() >= TREE_PROBABILITY {
+      return None
+    }
+
+    let species = choose_species(&mut rng, self.species);
+
+    // Keep the whole tree inside its column, so trees in neighboring columns can't overlap.
+    let inset = tree::max_radius(species);
+    let cell_width = self.cell_width;
+    let offset = |rng: &mut XorShiftRng, column: i32| {
+      let low = (column * cell_width) as f32 + inset;
+      let high = ((column + 1) * cell_width) as f32 - inset;
+      if low < high { rng.gen_range(low, high) } else { low }
+    };
+    let x = offset(&mut rng, column.0);
+    let z = offset(&mut rng, column.1);
+    let ground =
+      match self.ground(x, z) {
+        None => return None,
+        Some(ground) => ground,
+      };
+
+    // Only grow on grass.
+    if voxel::mosaic::T::material(&mut self.base, &ground) != Some(voxel::Material::Terrain) {
+      return None
+    }
+
+    Some(Tree {
+      // Sink the trunk a little so it doesn't float on slopes.
+      bottom: ground.add_v(&Vector3::new(0.0, -1.0, 0.0)),
+      mosaic: tree::new(&mut rng, species),
+    })
+  }
+
+  /// The tree in a column, if it has one, generating it if it isn't cached.
+  fn tree(&mut self, column: (i32, i32)) -> Option<&mut Tree> {
+    self.lookups += 1;
+    if !self.columns.contains_key(&column) {
+      if self.columns.len() >= MAX_CACHED_COLUMNS {
+        let least_recently_used =
+          self.columns.iter()
+          .min_by_key(|&(_, c)| c.last_used)
+          .map(|(&column, _)| column)
+          .unwrap();
+        self.columns.remove(&least_recently_used);
+      }
+      let tree = self.generate_tree(column);
+      self.columns.insert(column, Column { tree: tree, last_used: 0 });
+    }
+    let lookups = self.lookups;
+    let column = self.columns.get_mut(&column).unwrap();
+    column.last_used = lookups;
+    column.tree.as_mut()
+  }
+
+  /// Run `f` on the tree that might contain `p`, with `p` translated into the tree's space.
+  fn with_trees_near<F>(&mut self, p: &Point3<f32>, mut f: F) where
+    F: FnMut(&mut tree::T, &Point3<f32>),
+  {
+    // Trees don't reach outside their columns.
+    let column = (self.column_of(p.x), self.column_of(p.z));
+    if let Some(tree) = self.tree(column) {
+      let local = Point3::from_vec(&p.sub_p(&tree.bottom));
+      if local.y < -1.0 || local.y > tree.mosaic.height + 1.0 {
+        return
+      }
+      f(&mut tree.mosaic, &local);
+    }
+  }
+
+  /// The density of the densest tree at `p`, along with the tree's normal and material there.
+  fn densest_tree(&mut self, p: &Point3<f32>) -> Option<(f32, Vector3<f32>, Option<voxel::Material>)> {
+    let mut densest = None;
+    self.with_trees_near(p, |tree, local| {
+      let d = voxel::field::T::density(tree, local);
+      let is_denser =
+        match densest {
+          None => true,
+          Some((max_d, _, _)) => d > max_d,
+        };
+      if is_denser {
+        let normal = voxel::field::T::normal(tree, local);
+        let material = voxel::mosaic::T::material(tree, local);
+        densest = Some((d, normal, material));
+      }
+    });
+    densest
+  }
+}
+
+impl voxel::field::T for T {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    let d = voxel::field::T::density(&mut self.base, p);
+    match self.densest_tree(p) {
+      Some((tree_d, _, _)) if tree_d > d => tree_d,
+      _ => d,
+    }
+  }
+
+  fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+    let d = voxel::field::T::density(&mut self.base, p);
+    match self.densest_tree(p) {
+      Some((tree_d, normal, _)) if tree_d > d => normal,
+      _ => voxel::field::T::normal(&mut self.base, p),
+    }
+  }
+}
+
+impl voxel::mosaic::T<voxel::Material> for T {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    match self.densest_tree(p) {
+      Some((tree_d, _, material)) if tree_d >= 0.0 => material,
+      _ => voxel::mosaic::T::material(&mut self.base, p),
+    }
+  }
+}
+
+/// Flat grass at y = 0.
+#[cfg(test)]
+struct Flat;
+
+#[cfg(test)]
+impl voxel::field::T for Flat {
+  fn density(&mut self, p: &Point3<f32>) -> f32 {
+    -p.y
+  }
+
+  fn normal(&mut self, _: &Point3<f32>) -> Vector3<f32> {
+    Vector3::new(0.0, 1.0, 0.0)
+  }
+}
+
+#[cfg(test)]
+impl voxel::mosaic::T<voxel::Material> for Flat {
+  fn material(&mut self, p: &Point3<f32>) -> Option<voxel::Material> {
+    if p.y <= 0.0 {
+      Some(voxel::Material::Terrain)
+    } else {
+      None
+    }
+  }
+}
+
+#[cfg(test)]
+static TEST_TREES: &'static [(tree::Species, u32)] = &[
+  (tree::Species::Oak, 1),
+  (tree::Species::Bush, 3),
+];
+
+#[cfg(test)]
+fn tree_bottoms(forest: &mut T, columns: &[(i32, i32)]) -> Vec<Option<Point3<f32>>> {
+  columns.iter().map(|&column| forest.tree(column).map(|tree| tree.bottom)).collect()
+}
+
+#[test]
+fn trees_are_placed_deterministically() {
+  let columns: Vec<(i32, i32)> = (-4 .. 4).flat_map(|x| (-4 .. 4).map(move |z| (x, z))).collect();
+  let bottoms = tree_bottoms(&mut new(3, TEST_TREES, Box::new(Flat)), &columns);
+  assert!(bottoms.iter().any(|bottom| bottom.is_some()));
+
+  // Looking columns up in another order doesn't matter.
+  let mut forest = new(3, TEST_TREES, Box::new(Flat));
+  let reversed: Vec<(i32, i32)> = columns.iter().rev().cloned().collect();
+  let mut reversed_bottoms = tree_bottoms(&mut forest, &reversed);
+  reversed_bottoms.reverse();
+  assert_eq!(reversed_bottoms, bottoms);
+
+  // Nor does forgetting them.
+  let others: Vec<(i32, i32)> = (0 .. MAX_CACHED_COLUMNS as i32).map(|x| (1000 + x, 0)).collect();
+  tree_bottoms(&mut forest, &others);
+  assert_eq!(forest.columns.len(), MAX_CACHED_COLUMNS);
+  assert_eq!(tree_bottoms(&mut forest, &columns), bottoms);
+
+  assert!(tree_bottoms(&mut new(4, TEST_TREES, Box::new(Flat)), &columns) != bottoms);
+}
+
+#[test]
+fn species_follow_their_weights() {
+  let species = [
+    (tree::Species::Conifer, 3),
+    (tree::Species::Bush, 1),
+    (tree::Species::Dead, 0),
+  ];
+  let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+  let mut conifers = 0;
+  for _ in 0 .. 4000 {
+    match choose_species(&mut rng, &species) {
+      tree::Species::Conifer => conifers += 1,
+      tree::Species::Bush => {},
+      species => panic!("{:?} has no weight", species),
+    }
+  }
+  assert!(conifers > 2800 && conifers < 3200, "{} of 4000 trees were conifers", conifers);
+}
+
+#[test]
+fn trees_stay_inside_their_columns() {
+  let mut forest = new(5, TEST_TREES, Box::new(Flat));
+  let width = forest.cell_width as f32;
+  assert!(width >= 2.0 * tree::max_radius(tree::Species::Oak));
+
+  let mut trees = 0;
+  for x in -8 .. 8 {
+  for z in -8 .. 8 {
+    if let Some(tree) = forest.tree((x, z)) {
+      trees += 1;
+      let r = tree.mosaic.radius;
+      assert!(tree.bottom.x - r >= x as f32 * width && tree.bottom.x + r <= (x + 1) as f32 * width);
+      assert!(tree.bottom.z - r >= z as f32 * width && tree.bottom.z + r <= (z + 1) as f32 * width);
+    }
+  }}
+  assert!(trees > 0);
+}
@@ -0,0 +1,50 @@
+//! Pick materials for solid terrain based on where it is relative to the surface, so that
+//! e.g. topsoil sits on top of stone, beaches form near the water and peaks get snow.
+
+use cgmath::Vector3;
+
+use common::voxel;
+
+use biome;
+
+/// Below this depth, everything is the biome's underlying rock.
+pub const SOIL_DEPTH: f32 = 4.0;
+/// Depth of the grassy top layer of soil.
+const TOPSOIL_DEPTH: f32 = 1.0;
+/// Terrain below this height above sea level is sand.
+const BEACH_HEIGHT: f32 = 2.0;
+/// Altitude above which flat-enough surfaces are covered in snow.
+const SNOW_LINE: f32 = 32.0;
+/// Surfaces with normals less vertical than this are too steep to hold soil or snow.
+const MIN_FLATNESS: f32 = 0.6;
+
+fn is_flat(normal: &Vector3<f32>) -> bool {
+  normal.y >= MIN_FLATNESS
+}
+
+/// The material of a solid point in soil-covered terrain, given its depth below the surface
+/// (approximated by density), the normal of the nearby surface, and its altitude.
+pub fn soil(depth: f32, normal: &Vector3<f32>, altitude: f32) -> voxel::Material {
+  if depth > SOIL_DEPTH {
+    voxel::Material::Stone
+  } else if altitude < biome::SEA_LEVEL + BEACH_HEIGHT {
+    voxel::Material::Sand
+  } else if !is_flat(normal) {
+    voxel::Material::Stone
+  } else if altitude > SNOW_LINE {
+    voxel::Material::Snow
+  } else if depth > TOPSOIL_DEPTH {
+    voxel::Material::Dirt
+  } else {
+    voxel::Material::Terrain
+  }
+}
+
+/// The material of a solid point in bare rock, which only gets covered by snow.
+pub fn rock(depth: f32, normal: &Vector3<f32>, altitude: f32) -> voxel::Material {
+  if depth <= TOPSOIL_DEPTH && altitude > SNOW_LINE && is_flat(normal) {
+    voxel::Material::Snow
+  } else {
+    voxel::Material::Stone
+  }
+}
@@ -19,6 +19,7 @@
 
 extern crate cgmath;
 extern crate common;
+extern crate image;
 #[macro_use]
 extern crate log;
 extern crate noise;
@@ -30,17 +31,58 @@ extern crate voxel_data;
 extern crate num;
 
 mod cache_mosaic;
+#[cfg(test)]
+mod determinism;
 
 pub mod biome;
+pub mod forest;
+pub mod layers;
 pub mod tree;
 
 pub use noise::Seed;
 
-use cgmath::Aabb;
+use cgmath::{Aabb, Point3};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
 use std::sync::Mutex;
 
 use common::voxel;
 
+/// What to generate the world's terrain from.
+#[derive(Debug, Clone)]
+pub enum Source {
+  /// The noise-based `biome::demo`.
+  Demo,
+  /// An authored heightmap.
+  Heightmap(biome::heightmap::Config),
+}
+
+/// Bump this whenever generation changes, so that voxels saved from an older generator aren't reused.
+pub const GENERATOR_VERSION: u32 = 1;
+
+impl Source {
+  /// Everything generation depends on besides the seed: the generator version, the settings,
+  /// and the contents of any files, so that editing a heightmap changes the fingerprint.
+  pub fn fingerprint(&self) -> io::Result<Vec<u8>> {
+    let mut bytes = format!("v{} {:?}", GENERATOR_VERSION, self).into_bytes();
+    if let Source::Heightmap(ref config) = *self {
+      try!(append_file(&config.heightmap, &mut bytes));
+      if let Some(ref material_map) = config.material_map {
+        try!(append_file(material_map, &mut bytes));
+      }
+    }
+    Ok(bytes)
+  }
+}
+
+fn append_file(path: &Path, bytes: &mut Vec<u8>) -> io::Result<()> {
+  let mut file = try!(File::open(path));
+  try!(file.read_to_end(bytes));
+  Ok(())
+}
+
 /// This struct contains and lazily generates the world's terrain.
 #[allow(missing_docs)]
 pub struct T {
@@ -50,11 +92,23 @@ pub struct T {
 
 impl T {
   #[allow(missing_docs)]
-  pub fn new(terrain_seed: Seed) -> T {
-    T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
+  pub fn new(terrain_seed: u32, source: &Source) -> io::Result<T> {
+    let seed = Seed::new(terrain_seed);
+    let forest =
+      match *source {
+        Source::Demo => {
+          let biome = biome::demo::new(seed);
+          forest::new(terrain_seed, biome::demo::TREES, Box::new(biome))
+        },
+        Source::Heightmap(ref config) => {
+          let biome = try!(biome::heightmap::load(seed, config));
+          forest::new(terrain_seed, biome::heightmap::TREES, Box::new(biome))
+        },
+      };
+    Ok(T {
+      mosaic: Mutex::new(cache_mosaic::new(Box::new(forest))),
       voxels: Mutex::new(voxel::tree::new()),
-    }
+    })
   }
 
   /// Load the block of terrain at a given position.
@@ -82,6 +136,16 @@ impl T {
     }
   }
 
+  /// The material at a point, if the voxel containing it has already been loaded.
+  pub fn material_at(&self, p: &Point3<f32>) -> Option<voxel::Material> {
+    let bounds = voxel::bounds::new(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32, 0);
+    match self.voxels.lock().unwrap().get(&bounds) {
+      None => None,
+      Some(&voxel::Volume(material)) => Some(material),
+      Some(&voxel::Surface(ref voxel)) => Some(voxel.corner),
+    }
+  }
+
   /// Apply a voxel brush to the terrain.
   pub fn brush<VoxelChanged, Mosaic>(
     &self,
@@ -1,14 +1,20 @@
-//! A tree is comprised of a cylindrical trunk, a spherical bunch of leaves, and a spherical
-//! rounding to the bottom of the trunk.
+//! Trees are unions of simple fields: rounded cylinders for trunks and branches, and spheres
+//! or cones for leaves. The species decides how those pieces are arranged.
 
-use cgmath::{Point, Point3, Vector, Vector3, EuclideanVector, Rotation};
+use cgmath;
+use cgmath::{Point, Point3, Vector, Vector3, EuclideanVector, Matrix, Matrix3, Rotation};
 use rand;
+use rand::distributions::IndependentSample;
+use std::f32::consts::PI;
 use voxel_data;
 use voxel_data::field;
 use voxel_data::mosaic;
 
+use common::tree_species;
 use common::voxel;
 
+pub use common::tree_species::T as Species;
+
 mod pillar {
   use cgmath::{Point, Point3, Vector3, EuclideanVector};
 
@@ -35,13 +41,63 @@ mod pillar {
   }
 }
 
+mod cone {
+  use cgmath::{Point3, Vector3, EuclideanVector};
+
+  use voxel_data::field;
+
+  /// A solid cone with its base centered on the origin, pointing up the y axis.
+  pub struct T {
+    pub radius: f32,
+    pub height: f32,
+  }
+
+  unsafe impl Send for T {}
+
+  impl field::T for T {
+    fn density(&mut self, p: &Point3<f32>) -> f32 {
+      let d = (p.x*p.x + p.z*p.z).sqrt();
+      let r = self.radius * (1.0 - p.y / self.height);
+      f32::min(r - d, f32::min(p.y, self.height - p.y))
+    }
+
+    fn normal(&mut self, p: &Point3<f32>) -> Vector3<f32> {
+      let d = (p.x*p.x + p.z*p.z).sqrt();
+      if d == 0.0 {
+        return Vector3::new(0.0, 1.0, 0.0)
+      }
+      Vector3::new(p.x / d, self.radius / self.height, p.z / d).normalize()
+    }
+  }
+}
+
 #[allow(missing_docs)]
 pub struct T {
   union: voxel_data::mosaic::union::T<voxel::Material>,
+  /// The furthest any part of this tree is horizontally from its base.
+  pub radius: f32,
+  /// The furthest any part of this tree is above its base.
+  pub height: f32,
 }
 
 unsafe impl Send for T {}
 
+/// An upper bound on `T::radius` for any tree of a given species.
+pub fn max_radius(species: Species) -> f32 {
+  match species {
+    tree_species::T::Oak => 22.0,
+    tree_species::T::Conifer => 5.0,
+    tree_species::T::Birch => 6.0,
+    tree_species::T::Bush => 5.0,
+    tree_species::T::Dead => 6.5,
+    tree_species::T::Branching => 13.0,
+  }
+}
+
+fn horizontal_distance(p: &Point3<f32>) -> f32 {
+  (p.x*p.x + p.z*p.z).sqrt()
+}
+
 fn inside_sphere<Rng>(
   rng: &mut Rng,
   radius: f32,
@@ -61,49 +117,60 @@ fn inside_sphere<Rng>(
   }
 }
 
-#[allow(missing_docs)]
-pub fn new<Rng>(
-  rng: &mut Rng,
-  trunk_height: f32,
-  trunk_radius: f32,
-  leaf_radius: f32,
-) -> T
-  where Rng: rand::Rng,
-{
-  let trunk_top = Point3::new(0.0, trunk_height, 0.0);
-  let leaf_center = trunk_top.add_v(&Vector3::new(0.0, leaf_radius / 2.0, 0.0));
-  let trunk_center = Point3::new(0.0, trunk_height / 2.0, 0.0);
-
-  let mut union = mosaic::union::new();
-
-  let trunk =
-    field::translation::T {
-      translation: trunk_center.to_vec(),
-      field: field::intersection::new(
-        pillar::T {
-          radius: trunk_radius,
-        },
-        field::sphere::T {
-          radius: trunk_height / 2.0,
-        },
-      ),
+/// Some unit vector perpendicular to `v`.
+fn perpendicular(v: &Vector3<f32>) -> Vector3<f32> {
+  let other =
+    if v.x.abs() < 0.9 {
+      Vector3::new(1.0, 0.0, 0.0)
+    } else {
+      Vector3::new(0.0, 0.0, 1.0)
     };
+  v.cross(&other).normalize()
+}
+
+impl T {
+  fn empty() -> T {
+    T {
+      union: mosaic::union::new(),
+      radius: 0.0,
+      height: 0.0,
+    }
+  }
 
-  union.push(voxel::Material::Bark, trunk);
+  fn extend_bounds(&mut self, p: &Point3<f32>, margin: f32) {
+    self.radius = f32::max(self.radius, horizontal_distance(p) + margin);
+    self.height = f32::max(self.height, p.y + margin);
+  }
+
+  /// Add a vertical trunk from the origin.
+  fn push_trunk(&mut self, height: f32, radius: f32) {
+    let center = Point3::new(0.0, height / 2.0, 0.0);
+    self.union.push(
+      voxel::Material::Bark,
+      field::translation::T {
+        translation: center.to_vec(),
+        field: field::intersection::new(
+          pillar::T {
+            radius: radius,
+          },
+          field::sphere::T {
+            radius: height / 2.0,
+          },
+        ),
+      },
+    );
+    self.extend_bounds(&Point3::new(0.0, height, 0.0), radius);
+  }
 
-  let leaf_count = {
-    let leaf_radius = leaf_radius / 3.0;
-    (leaf_radius * leaf_radius * leaf_radius) as i32
-  };
-  for _ in 0..leaf_count {
-    let branch_end = leaf_center.add_v(&inside_sphere(rng, leaf_radius).to_vec());
-    let branch = branch_end.sub_p(&trunk_top);
+  /// Add a rounded branch between two points. `to - from` must not point straight up.
+  fn push_branch(&mut self, from: &Point3<f32>, to: &Point3<f32>, radius: f32) {
+    let branch = to.sub_p(from);
     let half_length = branch.length() / 2.0;
 
-    union.push(
+    self.union.push(
       voxel::Material::Bark,
       field::translation::T {
-        translation: trunk_top.to_vec(),
+        translation: from.to_vec(),
         field: field::rotation::T {
           rotation: Rotation::between_vectors(&Vector3::new(0.0, 1.0, 0.0), &branch.normalize()),
           field: field::translation::T {
@@ -113,30 +180,251 @@ pub fn new<Rng>(
                 radius: half_length,
               },
               pillar::T {
-                radius: 0.5,
+                radius: radius,
               },
             ),
           },
         },
       },
     );
+    self.extend_bounds(from, radius);
+    self.extend_bounds(to, radius);
+  }
 
-    union.push(
+  fn push_leaves(&mut self, center: &Point3<f32>, radius: f32) {
+    self.union.push(
       voxel::Material::Leaves,
       field::translation::T {
-        translation: branch_end.to_vec(),
+        translation: center.to_vec(),
         field: field::sphere::T {
-          radius: 4.0,
+          radius: radius,
         },
       },
     );
+    self.extend_bounds(center, radius);
   }
 
-  T {
-    union: union,
+  fn push_cone(&mut self, base: &Point3<f32>, radius: f32, height: f32) {
+    self.union.push(
+      voxel::Material::Leaves,
+      field::translation::T {
+        translation: base.to_vec(),
+        field: cone::T {
+          radius: radius,
+          height: height,
+        },
+      },
+    );
+    self.extend_bounds(base, radius);
+    self.extend_bounds(&base.add_v(&Vector3::new(0.0, height, 0.0)), 0.0);
   }
 }
 
+/// Shape parameters for trees with a trunk and a roughly spherical canopy.
+struct Canopy {
+  trunk_height: f32,
+  trunk_radius: f32,
+  /// Radius of the sphere that the branch ends lie in.
+  radius: f32,
+  branch_count: u32,
+  branch_radius: f32,
+  /// Radius of the clump of leaves at each branch end; zero for no leaves.
+  leaf_radius: f32,
+}
+
+fn canopy_tree<Rng>(rng: &mut Rng, canopy: &Canopy) -> T
+  where Rng: rand::Rng,
+{
+  let mut tree = T::empty();
+  tree.push_trunk(canopy.trunk_height, canopy.trunk_radius);
+
+  let trunk_top = Point3::new(0.0, canopy.trunk_height, 0.0);
+  let center = trunk_top.add_v(&Vector3::new(0.0, canopy.radius / 2.0, 0.0));
+  for _ in 0 .. canopy.branch_count {
+    let branch_end = center.add_v(&inside_sphere(rng, canopy.radius).to_vec());
+    tree.push_branch(&trunk_top, &branch_end, canopy.branch_radius);
+    if canopy.leaf_radius > 0.0 {
+      tree.push_leaves(&branch_end, canopy.leaf_radius);
+    }
+  }
+
+  tree
+}
+
+fn oak<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let trunk_radius =
+    rand::distributions::normal::Normal::new(2.0, 0.5)
+    .ind_sample(rng);
+  let trunk_radius =
+    f64::max(1.0, f64::min(3.0, trunk_radius));
+
+  let trunk_height =
+    rand::distributions::normal::Normal::new(8.0 * trunk_radius, 2.0 * trunk_radius)
+    .ind_sample(rng);
+  let trunk_height =
+    f64::max(4.0 * trunk_radius, f64::min(12.0 * trunk_radius, trunk_height));
+
+  let leaf_radius =
+    rand::distributions::normal::Normal::new(4.0 * trunk_radius, trunk_radius)
+    .ind_sample(rng);
+  let leaf_radius =
+    f64::max(2.0 * trunk_radius, f64::min(6.0 * trunk_radius, leaf_radius));
+
+  let radius = leaf_radius as f32;
+  canopy_tree(
+    rng,
+    &Canopy {
+      trunk_height: trunk_height as f32,
+      trunk_radius: trunk_radius as f32,
+      radius: radius,
+      branch_count: {
+        let r = radius / 3.0;
+        (r * r * r) as u32
+      },
+      branch_radius: 0.5,
+      leaf_radius: 4.0,
+    },
+  )
+}
+
+fn birch<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let canopy =
+    Canopy {
+      trunk_height: rng.gen_range(14.0, 22.0),
+      trunk_radius: rng.gen_range(0.4, 0.7),
+      radius: rng.gen_range(2.0, 4.0),
+      branch_count: rng.gen_range(8, 16),
+      branch_radius: 0.25,
+      leaf_radius: 2.0,
+    };
+  canopy_tree(rng, &canopy)
+}
+
+fn dead<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let mut tree = T::empty();
+  let trunk_height = rng.gen_range(6.0, 12.0);
+  tree.push_trunk(trunk_height, rng.gen_range(0.7, 1.2));
+
+  for _ in 0 .. rng.gen_range(4, 8) {
+    let from = Point3::new(0.0, trunk_height * rng.gen_range(0.5, 1.0), 0.0);
+    let azimuth = rng.gen_range(0.0, 2.0 * PI);
+    let length = rng.gen_range(2.0, 5.0);
+    let direction = Vector3::new(azimuth.cos(), rng.gen_range(0.2, 1.0), azimuth.sin()).normalize();
+    let to = from.add_v(&direction.mul_s(length));
+    tree.push_branch(&from, &to, 0.3);
+  }
+
+  tree
+}
+
+fn conifer<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let mut tree = T::empty();
+  let trunk_height = rng.gen_range(10.0, 20.0);
+  tree.push_trunk(trunk_height, rng.gen_range(0.5, 1.0));
+
+  // Stack progressively smaller cones from a quarter of the way up to just above the top.
+  let bottom = trunk_height / 4.0;
+  let top = trunk_height + 2.0;
+  let mut radius = rng.gen_range(3.0, 5.0);
+  let layers = rng.gen_range(3, 5);
+  let layer_height = (top - bottom) / layers as f32;
+  for i in 0 .. layers {
+    let base = Point3::new(0.0, bottom + layer_height * i as f32, 0.0);
+    tree.push_cone(&base, radius, 2.0 * layer_height);
+    radius *= 0.75;
+  }
+
+  tree
+}
+
+fn bush<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let mut tree = T::empty();
+  let center = Point3::new(0.0, 1.0, 0.0);
+  for _ in 0 .. rng.gen_range(3, 7) {
+    let p = center.add_v(&inside_sphere(rng, 2.0).to_vec());
+    tree.push_leaves(&p, rng.gen_range(1.5, 3.0));
+  }
+  tree
+}
+
+/// Recursively fork a branch, in the style of an L-system.
+fn fork<Rng>(
+  rng: &mut Rng,
+  tree: &mut T,
+  from: &Point3<f32>,
+  direction: &Vector3<f32>,
+  length: f32,
+  radius: f32,
+  depth: u32,
+) where Rng: rand::Rng,
+{
+  if depth == 0 {
+    tree.push_leaves(from, 2.0);
+    return
+  }
+
+  let axis = perpendicular(direction);
+  for _ in 0 .. rng.gen_range(2, 4) {
+    let tilt = Matrix3::from_axis_angle(&axis, cgmath::rad(rng.gen_range(PI / 7.0, PI / 4.0)));
+    let spin = Matrix3::from_axis_angle(direction, cgmath::rad(rng.gen_range(0.0, 2.0 * PI)));
+    let child_direction = spin.mul_m(&tilt).mul_v(direction).normalize();
+    let to = from.add_v(&child_direction.mul_s(length));
+    tree.push_branch(from, &to, radius);
+    fork(rng, tree, &to, &child_direction, length * 0.7, radius * 0.6, depth - 1);
+  }
+}
+
+fn branching<Rng>(rng: &mut Rng) -> T
+  where Rng: rand::Rng,
+{
+  let mut tree = T::empty();
+  let trunk_height = rng.gen_range(4.0, 6.0);
+  let trunk_radius = rng.gen_range(0.8, 1.2);
+  tree.push_trunk(trunk_height, trunk_radius);
+
+  let up = Vector3::new(0.0, 1.0, 0.0);
+  fork(
+    rng,
+    &mut tree,
+    &Point3::new(0.0, trunk_height, 0.0),
+    &up,
+    trunk_height * 0.7,
+    trunk_radius * 0.6,
+    4,
+  );
+  tree
+}
+
+/// Generate a tree of some species, with its base at the origin.
+pub fn new<Rng>(
+  rng: &mut Rng,
+  species: Species,
+) -> T
+  where Rng: rand::Rng,
+{
+  let tree =
+    match species {
+      tree_species::T::Oak => oak(rng),
+      tree_species::T::Conifer => conifer(rng),
+      tree_species::T::Birch => birch(rng),
+      tree_species::T::Bush => bush(rng),
+      tree_species::T::Dead => dead(rng),
+      tree_species::T::Branching => branching(rng),
+    };
+  debug_assert!(tree.radius <= max_radius(species), "{:?} {}", species, tree.radius);
+  tree
+}
+
 impl field::T for T {
   fn density(&mut self, p: &Point3<f32>) -> f32 {
     field::T::density(&mut self.union, p)
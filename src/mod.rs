@@ -20,6 +20,7 @@ extern crate client_lib;
 extern crate server_lib;
 
 use std::borrow::Borrow;
+use std::env;
 use std::sync::Mutex;
 
 fn main() {
@@ -28,15 +29,27 @@ fn main() {
   let listen_url = String::from("ipc:///tmp/client.ipc");
   let server_url = String::from("ipc:///tmp/server.ipc");
 
+  // Client settings first, then whatever's left is server config.
+  let (settings, args) =
+    match client_lib::settings::parse_known(env::args().skip(1)) {
+      Ok(parsed) => parsed,
+      Err(err) => panic!("{}", err),
+    };
+  let config =
+    match server_lib::config::parse(args.into_iter()) {
+      Ok(config) => config,
+      Err(err) => panic!("{}", err),
+    };
+
   let quit_signal = Mutex::new(false);
 
   unsafe {
     let _server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(server_url.borrow(), &config, &quit_signal);
       });
 
-    client_lib::run(listen_url.borrow(), server_url.borrow());
+    client_lib::run(listen_url.borrow(), server_url.borrow(), &settings);
 
     *quit_signal.lock().unwrap() = true;
     // Close all sockets.
@@ -11,3 +11,4 @@ testin() {
 testin common
 testin client/lib
 testin server/lib
+testin server/lib/terrain
@@ -0,0 +1,111 @@
+//! Headless bot client, for load testing a server. Run several at once (each with its own listen
+//! URL) to simulate a crowd, and compare the terrain latencies they report.
+//! It can also replay a recording of a server's messages (see `--record`) without a server.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+#![feature(plugin)]
+#![plugin(clippy)]
+
+extern crate client_lib;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use client_lib::bot;
+use client_lib::recording;
+use client_lib::settings;
+
+const USAGE: &'static str = "\
+Usage: bot [LISTEN_URL [SERVER_URL]] [options] [client settings]
+  --duration SECONDS   How long to play for (default: 60).
+  --script PATH        Run the commands in a script in a loop, instead of walking around at random.
+  --seed N             Seed the random walk (default: 0).
+  --replay PATH        Instead of connecting to a server, replay a recording of one.
+  --speed X            Replay X times faster than the recording (default: 1).
+Client settings:";
+
+fn usage(err: &str) -> ! {
+  panic!("{}\n{}\n{}", err, USAGE, settings::USAGE)
+}
+
+fn main() {
+  env_logger::init().unwrap();
+
+  let (settings, args) = settings::parse_known(env::args().skip(1)).unwrap_or_else(|err| usage(&err));
+  let mut args = args.into_iter().peekable();
+
+  let mut urls = Vec::new();
+  while urls.len() < 2 && args.peek().map_or(false, |arg| !arg.starts_with("--")) {
+    urls.push(args.next().unwrap());
+  }
+  let listen_url = urls.get(0).cloned().unwrap_or_else(|| String::from("ipc:///tmp/bot.ipc"));
+  let server_url = urls.get(1).cloned().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+
+  let mut duration = 60;
+  let mut behaviour = bot::Behaviour::RandomWalk(0);
+  let mut script = None;
+  let mut replay = None;
+  let mut speed = 1.0;
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().unwrap_or_else(|| usage(&format!("{} needs a value", arg)));
+    match arg.as_ref() {
+      "--duration" => duration = u64::from_str(&value()).unwrap_or_else(|_| usage("Expected seconds for --duration")),
+      "--seed" => {
+        let seed = u32::from_str(&value()).unwrap_or_else(|_| usage("Expected a number for --seed"));
+        behaviour = bot::Behaviour::RandomWalk(seed);
+      },
+      "--script" => script = Some(value()),
+      "--replay" => replay = Some(value()),
+      "--speed" => {
+        speed = f64::from_str(&value()).unwrap_or_else(|_| usage("Expected a number for --speed"));
+        if !(speed > 0.0) {
+          usage("--speed must be positive");
+        }
+      },
+      _ => usage(&format!("Unrecognized argument {:?}", arg)),
+    }
+  }
+  if let Some(path) = script {
+    let mut contents = String::new();
+    File::open(&path)
+      .and_then(|mut file| file.read_to_string(&mut contents))
+      .unwrap_or_else(|err| usage(&format!("Couldn't read {}: {}", path, err)));
+    let commands = bot::parse_script(&contents).unwrap_or_else(|err| usage(&format!("{}: {}", path, err)));
+    behaviour = bot::Behaviour::Script(commands);
+  }
+
+  let latency =
+    match replay {
+      Some(path) => {
+        let frames =
+          recording::read(Path::new(&path))
+          .unwrap_or_else(|err| usage(&format!("Couldn't read {}: {}", path, err)));
+        info!("Replaying {} messages from {}.", frames.len(), path);
+        bot::replay(frames, speed, &settings)
+      },
+      None => {
+        info!("Sending to {}.", server_url);
+        info!("Listening on {}.", listen_url);
+        bot::run(&listen_url, &server_url, &settings, &behaviour, Duration::from_secs(duration))
+      },
+    };
+
+  match latency {
+    None => println!("No terrain loaded."),
+    Some(latency) => {
+      println!(
+        "Terrain latency over {} blocks: mean {:.1} ms, median {:.1} ms, 95th percentile {:.1} ms, max {:.1} ms",
+        latency.count, latency.mean_ms, latency.median_ms, latency.p95_ms, latency.max_ms,
+      );
+    },
+  }
+}
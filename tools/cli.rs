@@ -0,0 +1,21 @@
+//! Command line helpers shared by the tools that generate terrain.
+//! Each tool includes this with `#[path]`, since they're separate crates.
+
+use cgmath::Point3;
+use std::str::FromStr;
+
+use server_lib::config;
+
+/// Parse a point written as X,Y,Z.
+pub fn point(s: &str) -> Option<Point3<i32>> {
+  let coords: Result<Vec<i32>, _> = s.split(',').map(|c| i32::from_str(c.trim())).collect();
+  match coords {
+    Ok(ref c) if c.len() == 3 => Some(Point3::new(c[0], c[1], c[2])),
+    _ => None,
+  }
+}
+
+/// Give up because of `err`, explaining the tool's own options and then the terrain options.
+pub fn usage(tool_usage: &str, err: &str) -> ! {
+  panic!("{}\n{}\n{}", err, tool_usage, config::USAGE)
+}
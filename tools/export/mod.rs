@@ -0,0 +1,129 @@
+//! Offline tool to generate (or load from a prefab) a region of the world and write it out as
+//! an OBJ or PLY mesh.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+#![feature(plugin)]
+#![plugin(clippy)]
+
+extern crate cgmath;
+extern crate client_lib;
+extern crate common;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+extern crate server_lib;
+extern crate terrain;
+
+#[path = "../cli.rs"]
+mod cli;
+
+use cgmath::Aabb3;
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use client_lib::mesh_export;
+use common::voxel;
+use server_lib::config;
+use server_lib::prefab;
+
+const USAGE: &'static str = "\
+Usage: export OUTPUT --min X,Y,Z --max X,Y,Z [options] [terrain options]
+  --lod N              Level of detail to mesh at; 0 is the finest.
+  --format obj|ply     Output format. By default, it's guessed from OUTPUT's extension.
+  --prefab NAME        Mesh a saved prefab, with its bottom-center at the origin, instead of terrain.
+Terrain options:";
+
+fn usage(err: &str) -> ! {
+  cli::usage(USAGE, err)
+}
+
+fn main() {
+  env_logger::init().unwrap();
+
+  let mut min = None;
+  let mut max = None;
+  let mut lod = 0;
+  let mut format = None;
+  let mut prefab_name = None;
+  let mut terrain_args = Vec::new();
+
+  let mut args = env::args().skip(1);
+  let output =
+    match args.next() {
+      Some(ref arg) if !arg.starts_with("--") => PathBuf::from(arg),
+      _ => usage("No output file given"),
+    };
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().unwrap_or_else(|| usage(&format!("{} needs a value", arg)));
+    match arg.as_ref() {
+      "--min" => min = Some(cli::point(&value()).unwrap_or_else(|| usage("Expected X,Y,Z for --min"))),
+      "--max" => max = Some(cli::point(&value()).unwrap_or_else(|| usage("Expected X,Y,Z for --max"))),
+      "--lod" => lod = u32::from_str(&value()).unwrap_or_else(|_| usage("Expected a number for --lod")),
+      "--format" => {
+        format =
+          match value().as_ref() {
+            "obj" => Some(mesh_export::Format::Obj),
+            "ply" => Some(mesh_export::Format::Ply),
+            _ => usage("Expected obj or ply for --format"),
+          };
+      },
+      "--prefab" => prefab_name = Some(value()),
+      _ => terrain_args.push(arg.clone()),
+    }
+  }
+
+  let bounds =
+    match (min, max) {
+      (Some(min), Some(max)) => Aabb3::new(min, max),
+      _ => usage("--min and --max are required"),
+    };
+  if lod >= mesh_export::LOD_COUNT {
+    usage(&format!("--lod must be less than {}", mesh_export::LOD_COUNT));
+  }
+  let format =
+    format
+    .or_else(|| mesh_export::Format::of_path(&output))
+    .unwrap_or_else(|| usage("Can't tell the format from the output file; use --format"));
+
+  let needed = mesh_export::voxels_needed(&bounds, lod);
+  info!("Loading {} voxels", needed.len());
+
+  let write = |voxels: &voxel::tree::T| {
+    let file = File::create(&output).unwrap_or_else(|err| panic!("Couldn't create {:?}: {}", output, err));
+    let mut file = BufWriter::new(file);
+    mesh_export::write(&mut file, format, voxels, &bounds, lod)
+      .unwrap_or_else(|err| panic!("Couldn't write {:?}: {}", output, err));
+    info!("Wrote {:?}", output);
+  };
+
+  match prefab_name {
+    Some(name) => {
+      if !terrain_args.is_empty() {
+        usage("Terrain options don't apply to prefabs");
+      }
+      let prefab = prefab::load(&name).unwrap_or_else(|err| panic!("Couldn't load prefab {:?}: {}", name, err));
+      let mut placed = prefab.place(0.0);
+      let mut voxels = voxel::tree::new();
+      for bounds in &needed {
+        let voxel = voxel::unwrap(voxel::of_field(&mut placed, bounds));
+        voxels.get_mut_or_create(bounds).force_branches().data = Some(voxel);
+      }
+      write(&voxels);
+    },
+    None => {
+      let config = config::parse(terrain_args.into_iter()).unwrap_or_else(|err| usage(&err));
+      let terrain =
+        terrain::T::new(config.terrain_seed, &config.terrain)
+        .unwrap_or_else(|err| panic!("Couldn't load terrain: {}", err));
+      for bounds in &needed {
+        terrain.load(bounds, |_| {});
+      }
+      write(&terrain.voxels.lock().unwrap());
+    },
+  }
+}
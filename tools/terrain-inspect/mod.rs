@@ -0,0 +1,244 @@
+//! Headless terrain inspection: generate a region without a client or a GPU, then report
+//! per-material voxel statistics, per-block generation timing, and optionally write PNG slices
+//! of material and density.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+#![feature(plugin)]
+#![plugin(clippy)]
+
+extern crate cgmath;
+extern crate common;
+extern crate env_logger;
+extern crate image;
+extern crate server_lib;
+extern crate terrain;
+extern crate time;
+
+#[path = "../cli.rs"]
+mod cli;
+
+use cgmath::{Aabb3, Point3};
+use std::cmp;
+use std::env;
+use std::str::FromStr;
+
+use common::voxel;
+use server_lib::config;
+
+/// Width of the blocks that generation is timed over, in voxels.
+const BLOCK_WIDTH: i32 = 8;
+/// How many of the slowest blocks to list.
+const SLOWEST_BLOCK_COUNT: usize = 5;
+/// Densities at or beyond this magnitude get the darkest/brightest colors in density slices.
+const DENSITY_RANGE: f32 = 8.0;
+
+const USAGE: &'static str = "\
+Usage: terrain-inspect --min X,Y,Z --max X,Y,Z [options] [terrain options]
+  --lg-size N          lg of the width of the voxels to generate. Defaults to 0, the finest.
+  --slice x|y|z N      Write PNG slices of material and density through the plane axis = N.
+  --slice-prefix PATH  Where to write slices; they're named PATH-material.png and PATH-density.png.
+Terrain options:";
+
+fn color(material: Option<voxel::Material>) -> [u8; 3] {
+  match material {
+    None => [255, 0, 0],
+    Some(material) => material.color(),
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Axis {
+  X,
+  Y,
+  Z,
+}
+
+fn usage(err: &str) -> ! {
+  cli::usage(USAGE, err)
+}
+
+fn ms(ns: u64) -> f64 {
+  ns as f64 / 1_000_000.0
+}
+
+#[derive(Default)]
+struct MaterialCount {
+  volume: u64,
+  surface: u64,
+}
+
+/// The low corners of the blocks covering [low, high).
+fn block_starts(low: i32, high: i32) -> Vec<i32> {
+  (0 ..).map(|i| low + i * BLOCK_WIDTH).take_while(|&x| x < high).collect()
+}
+
+/// Generate every block in `bounds`, printing timing and material statistics.
+fn generate(terrain: &terrain::T, bounds: &Aabb3<i32>, lg_size: i16) {
+  // Bounds in units of voxels, rounded outwards.
+  let size = 1 << lg_size;
+  let low = Point3::new(bounds.min.x >> lg_size, bounds.min.y >> lg_size, bounds.min.z >> lg_size);
+  let high =
+    Point3::new(
+      (bounds.max.x + size - 1) >> lg_size,
+      (bounds.max.y + size - 1) >> lg_size,
+      (bounds.max.z + size - 1) >> lg_size,
+    );
+
+  let mut block_times = Vec::new();
+  let mut counts: Vec<MaterialCount> = voxel::MATERIALS.iter().map(|_| Default::default()).collect();
+  let mut total_voxels = 0;
+
+  let start = time::precise_time_ns();
+  for block_x in block_starts(low.x, high.x) {
+  for block_y in block_starts(low.y, high.y) {
+  for block_z in block_starts(low.z, high.z) {
+    let block_start = time::precise_time_ns();
+    for x in block_x .. cmp::min(block_x + BLOCK_WIDTH, high.x) {
+    for y in block_y .. cmp::min(block_y + BLOCK_WIDTH, high.y) {
+    for z in block_z .. cmp::min(block_z + BLOCK_WIDTH, high.z) {
+      terrain.load(&voxel::bounds::new(x, y, z, lg_size), |voxel| {
+        let (material, is_surface) =
+          match *voxel {
+            voxel::Volume(material) => (material, false),
+            voxel::Surface(ref surface) => (surface.corner, true),
+          };
+        let count = &mut counts[material as usize];
+        if is_surface {
+          count.surface += 1;
+        } else {
+          count.volume += 1;
+        }
+        total_voxels += 1;
+      });
+    }}}
+    let block_low = Point3::new(block_x << lg_size, block_y << lg_size, block_z << lg_size);
+    block_times.push((block_low, time::precise_time_ns() - block_start));
+  }}}
+  let total_time = time::precise_time_ns() - start;
+
+  println!("Generated {} blocks ({} voxels) in {:.1}ms", block_times.len(), total_voxels, ms(total_time));
+  if !block_times.is_empty() {
+    block_times.sort_by(|x, y| y.1.cmp(&x.1));
+    let total: u64 = block_times.iter().fold(0, |total, &(_, t)| total + t);
+    println!(
+      "Per block: min {:.2}ms, mean {:.2}ms, max {:.2}ms",
+      ms(block_times[block_times.len() - 1].1),
+      ms(total) / block_times.len() as f64,
+      ms(block_times[0].1),
+    );
+    println!("Slowest blocks:");
+    for &(low, t) in block_times.iter().take(SLOWEST_BLOCK_COUNT) {
+      println!("  ({}, {}, {}): {:.2}ms", low.x, low.y, low.z, ms(t));
+    }
+  }
+
+  println!("{:<10} {:>10} {:>10} {:>7}", "Material", "Volume", "Surface", "%");
+  for (material, count) in voxel::MATERIALS.iter().zip(counts.iter()) {
+    let total = count.volume + count.surface;
+    if total == 0 {
+      continue
+    }
+    println!(
+      "{:<10} {:>10} {:>10} {:>6.2}%",
+      material.name(), count.volume, count.surface, 100.0 * total as f64 / total_voxels as f64,
+    );
+  }
+}
+
+/// Write material and density images of the plane `axis = at` within `bounds`, one pixel per unit.
+fn slice(terrain: &terrain::T, bounds: &Aabb3<i32>, axis: Axis, at: i32, prefix: &str) {
+  // Image x and y in terms of world coordinates; image y goes down, so flip world y.
+  let (width, height) =
+    match axis {
+      Axis::X => (bounds.max.z - bounds.min.z, bounds.max.y - bounds.min.y),
+      Axis::Y => (bounds.max.x - bounds.min.x, bounds.max.z - bounds.min.z),
+      Axis::Z => (bounds.max.x - bounds.min.x, bounds.max.y - bounds.min.y),
+    };
+  let world = |i: i32, j: i32| {
+    match axis {
+      Axis::X => Point3::new(at, bounds.max.y - 1 - j, bounds.min.z + i),
+      Axis::Y => Point3::new(bounds.min.x + i, at, bounds.min.z + j),
+      Axis::Z => Point3::new(bounds.min.x + i, bounds.max.y - 1 - j, at),
+    }
+  };
+
+  let mut materials = image::ImageBuffer::new(width as u32, height as u32);
+  let mut densities = image::ImageBuffer::new(width as u32, height as u32);
+  let mut mosaic = terrain.mosaic.lock().unwrap();
+  for i in 0 .. width {
+  for j in 0 .. height {
+    let p = world(i, j);
+    let p = Point3::new(p.x as f32, p.y as f32, p.z as f32);
+
+    let material = voxel::mosaic::T::material(&mut *mosaic, &p);
+    materials.put_pixel(i as u32, j as u32, image::Rgb(color(material)));
+
+    // Mid-gray is the surface, brighter is more solid.
+    let density = voxel::field::T::density(&mut *mosaic, &p);
+    let density = f32::max(-1.0, f32::min(1.0, density / DENSITY_RANGE));
+    let shade = (127.5 + density * 127.5) as u8;
+    densities.put_pixel(i as u32, j as u32, image::Rgb([shade, shade, shade]));
+  }}
+
+  let material_path = format!("{}-material.png", prefix);
+  let density_path = format!("{}-density.png", prefix);
+  materials.save(&material_path).unwrap_or_else(|err| panic!("Couldn't write {}: {}", material_path, err));
+  densities.save(&density_path).unwrap_or_else(|err| panic!("Couldn't write {}: {}", density_path, err));
+  println!("Wrote {} and {}", material_path, density_path);
+}
+
+fn main() {
+  env_logger::init().unwrap();
+
+  let mut min = None;
+  let mut max = None;
+  let mut lg_size = 0;
+  let mut slice_plane = None;
+  let mut slice_prefix = String::from("slice");
+  let mut terrain_args = Vec::new();
+
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    let mut value = || args.next().unwrap_or_else(|| usage(&format!("{} needs a value", arg)));
+    match arg.as_ref() {
+      "--min" => min = Some(cli::point(&value()).unwrap_or_else(|| usage("Expected X,Y,Z for --min"))),
+      "--max" => max = Some(cli::point(&value()).unwrap_or_else(|| usage("Expected X,Y,Z for --max"))),
+      "--lg-size" => lg_size = i16::from_str(&value()).unwrap_or_else(|_| usage("Expected a number for --lg-size")),
+      "--slice" => {
+        let axis =
+          match value().as_ref() {
+            "x" => Axis::X,
+            "y" => Axis::Y,
+            "z" => Axis::Z,
+            _ => usage("Expected x, y or z for --slice"),
+          };
+        let at = i32::from_str(&value()).unwrap_or_else(|_| usage("Expected a number for --slice"));
+        slice_plane = Some((axis, at));
+      },
+      "--slice-prefix" => slice_prefix = value(),
+      _ => terrain_args.push(arg.clone()),
+    }
+  }
+
+  let bounds =
+    match (min, max) {
+      (Some(min), Some(max)) => Aabb3::new(min, max),
+      _ => usage("--min and --max are required"),
+    };
+  if lg_size < 0 {
+    usage("--lg-size can't be negative");
+  }
+
+  let config = config::parse(terrain_args.into_iter()).unwrap_or_else(|err| usage(&err));
+  let terrain =
+    terrain::T::new(config.terrain_seed, &config.terrain)
+    .unwrap_or_else(|err| panic!("Couldn't load terrain: {}", err));
+
+  generate(&terrain, &bounds, lg_size);
+
+  if let Some((axis, at)) = slice_plane {
+    slice(&terrain, &bounds, axis, at, &slice_prefix);
+  }
+}